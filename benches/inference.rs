@@ -0,0 +1,54 @@
+//! Concurrent request throughput on one async worker, with inference run inline against inference
+//! moved to the blocking pool as `Model::embed_blocking` does.
+//!
+//! The ONNX run is stood in for by a fixed sleep, so the numbers show how much a single actix
+//! worker overlaps, not how fast the model is.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use futures_util::future::join_all;
+use std::{thread, time::Duration};
+use tokio::runtime::Builder;
+
+/// Requests in flight at once
+const CONCURRENT_REQUESTS: usize = 32;
+
+/// Time one stand-in inference takes
+const INFERENCE_TIME: Duration = Duration::from_millis(2);
+
+/// Embedding dimension of the built-in model
+const DIMENSION: usize = 384;
+
+fn infer() -> Vec<f32> {
+    thread::sleep(INFERENCE_TIME);
+    vec![0.0; DIMENSION]
+}
+
+/// A request that runs inference on the async worker itself, blocking every other request on it
+async fn inline_request() -> Vec<f32> {
+    infer()
+}
+
+/// A request that hands inference to the blocking pool and yields the worker while it runs
+async fn blocking_pool_request() -> Vec<f32> {
+    tokio::task::spawn_blocking(infer).await.expect("inference task")
+}
+
+fn concurrent_throughput(c: &mut Criterion) {
+    // Actix runs each worker on a single-threaded runtime
+    let runtime = Builder::new_current_thread().enable_all().build().expect("runtime");
+
+    let mut group = c.benchmark_group("concurrent_32");
+    group.sample_size(20);
+    group.bench_function("inline", |b| {
+        b.iter(|| black_box(runtime.block_on(join_all((0..CONCURRENT_REQUESTS).map(|_| inline_request())))))
+    });
+    group.bench_function("blocking_pool", |b| {
+        b.iter(|| {
+            black_box(runtime.block_on(join_all((0..CONCURRENT_REQUESTS).map(|_| blocking_pool_request()))))
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_throughput);
+criterion_main!(benches);
@@ -0,0 +1,158 @@
+//! Top-1 matching on the built-in palette: the exact scan against the IVF index, and cosine with
+//! cached reference norms against recomputing them per request. Also the exact scan over a
+//! synthetic 50k palette, split across threads against a single thread.
+//!
+//! `cargo bench --bench matching` prints the index's recall@1 against the exact scan before
+//! timing both.
+
+use colorizer::{
+    matching::References,
+    metric::{cosine_similarity, cosine_similarity_with_norm, l2_norm, SimilarityMetric},
+    reference::{ConflictPolicy, ReferenceSet, Vector},
+};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand_pcg::{
+    rand_core::{RngCore, SeedableRng},
+    Pcg32,
+};
+use rayon::ThreadPoolBuilder;
+use std::path::Path;
+
+/// Reference file shipped with the repository
+const PALETTE: &str = "custom/ref_embeddings.json";
+
+/// Clusters probed per ANN query, the server's default
+const PROBES: usize = 4;
+
+/// References in the synthetic palette, well above the parallel scan threshold
+const SYNTHETIC_SIZE: usize = 50_000;
+
+/// Embedding dimension of the synthetic palette, matching the built-in one
+const SYNTHETIC_DIMENSION: usize = 384;
+
+/// Largest change to each value of a reference embedding when making a query near it
+const NOISE: f32 = 0.02;
+
+fn load(ann: bool) -> References {
+    let set = ReferenceSet::load(Path::new(PALETTE), ConflictPolicy::First, SimilarityMetric::Cosine, ann, PROBES, 0)
+        .expect("built-in palette");
+    References::new(set)
+}
+
+/// One query per reference: its embedding with a little deterministic noise added
+fn queries(refs: &References) -> Vec<Vec<f32>> {
+    let mut rng = Pcg32::seed_from_u64(7);
+    refs.embeddings
+        .iter()
+        .map(|r| {
+            let Vector::F32(v) = &r.embedding else {
+                panic!("the built-in palette is f32");
+            };
+            v.iter()
+                .map(|x| x + (rng.next_u32() as f32 / u32::MAX as f32 * 2.0 - 1.0) * NOISE)
+                .collect()
+        })
+        .collect()
+}
+
+/// `SYNTHETIC_SIZE` random references and a handful of random queries
+fn synthetic() -> (References, Vec<Vec<f32>>) {
+    let mut rng = Pcg32::seed_from_u64(11);
+    let mut vector = || -> Vec<f32> {
+        (0..SYNTHETIC_DIMENSION)
+            .map(|_| rng.next_u32() as f32 / u32::MAX as f32 * 2.0 - 1.0)
+            .collect()
+    };
+    let entries: Vec<_> = (0..SYNTHETIC_SIZE).map(|i| (format!("color{i}"), vector(), (0, 0, 0))).collect();
+    let set = ReferenceSet::from_entries(entries, SimilarityMetric::Cosine, false, PROBES).expect("synthetic palette");
+    let queries = (0..16).map(|_| vector()).collect();
+    (References::new(set), queries)
+}
+
+fn top_matching(c: &mut Criterion) {
+    let exact = load(false);
+    let ann = load(true);
+    let queries = queries(&exact);
+
+    let best = |refs: &References, query: &Vec<f32>| refs.top_matches(query.clone(), 1)[0].index;
+    let hits = queries.iter().filter(|q| best(&exact, q) == best(&ann, q)).count();
+    println!(
+        "ANN recall@1 on {} references ({} probes): {:.3}",
+        exact.embeddings.len(),
+        PROBES,
+        hits as f64 / queries.len() as f64
+    );
+
+    let mut group = c.benchmark_group("top_1");
+    for (name, refs) in [("exact", &exact), ("ann", &ann)] {
+        group.bench_function(name, |b| {
+            let mut i = 0;
+            b.iter(|| {
+                i = (i + 1) % queries.len();
+                black_box(refs.top_matches(queries[i].clone(), 1))
+            })
+        });
+    }
+    group.finish();
+}
+
+/// The work `CosineWithNorm` scoring saves: one norm per reference, computed at load instead of
+/// for every query
+fn cached_norms(c: &mut Criterion) {
+    let refs = load(false);
+    let queries = queries(&refs);
+    let vectors: Vec<&[f32]> = refs
+        .embeddings
+        .iter()
+        .map(|r| match &r.embedding {
+            Vector::F32(v) => v.as_slice(),
+            Vector::F16(_) => panic!("the built-in palette is f32"),
+        })
+        .collect();
+    let norms: Vec<f32> = vectors.iter().map(|v| l2_norm(v)).collect();
+
+    let mut group = c.benchmark_group("cosine_scan");
+    group.bench_function("cached_norms", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            i = (i + 1) % queries.len();
+            let query = &queries[i];
+            let query_norm = l2_norm(query);
+            let scores = vectors.iter().zip(&norms);
+            black_box(scores.map(|(v, &norm)| cosine_similarity_with_norm(query, query_norm, v, norm)).sum::<f32>())
+        })
+    });
+    group.bench_function("recomputed_norms", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            i = (i + 1) % queries.len();
+            black_box(vectors.iter().map(|v| cosine_similarity(&queries[i], v)).sum::<f32>())
+        })
+    });
+    group.finish();
+}
+
+fn parallel_scan(c: &mut Criterion) {
+    let (refs, queries) = synthetic();
+    let single_thread = ThreadPoolBuilder::new().num_threads(1).build().expect("thread pool");
+
+    let mut group = c.benchmark_group("scan_50k");
+    group.bench_function("parallel", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            i = (i + 1) % queries.len();
+            black_box(refs.top_matches(queries[i].clone(), 1))
+        })
+    });
+    group.bench_function("linear", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            i = (i + 1) % queries.len();
+            single_thread.install(|| black_box(refs.top_matches(queries[i].clone(), 1)))
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, top_matching, cached_norms, parallel_scan);
+criterion_main!(benches);
@@ -0,0 +1,224 @@
+//! Adjustments applied to a matched color before it is returned.
+
+use crate::color_space::{contrast_ratio, hsl_to_rgb, linear_to_srgb, rgb_to_hsl, srgb_to_linear, Hsl};
+use rand_pcg::{
+    rand_core::{RngCore, SeedableRng},
+    Pcg32,
+};
+
+/// Minimum WCAG contrast ratio for normal text (level AA)
+pub const MIN_CONTRAST: f32 = 4.5;
+
+/// sRGB color of a ~3000K blackbody (warm incandescent white), the target of `warmth = 1`
+const WARM_WHITE: (u8, u8, u8) = (255, 180, 107);
+
+/// sRGB color of a ~10000K blackbody (overcast sky), the target of `warmth = -1`
+const COOL_WHITE: (u8, u8, u8) = (202, 218, 255);
+
+/// Offsets of a 4-step ordered (Bayer) dither, cycled along a gradient. All are within ±0.5, so a
+/// whole channel value always rounds to itself.
+const DITHER_OFFSETS: [f32; 4] = [-0.375, 0.125, -0.125, 0.375];
+
+/// Binary search steps when searching for a legible lightness; lightness is resolved to < 0.01%
+const CONTRAST_SEARCH_STEPS: u32 = 16;
+
+/// Snap a channel to the nearest of `2^bits` evenly spaced levels in `[0, 255]`.
+///
+/// `bits` must be in `1..=8`; 8 bits is the identity.
+pub fn quantize_channel(value: u8, bits: u8) -> u8 {
+    debug_assert!((1..=8).contains(&bits));
+    let levels = (1u32 << bits) - 1;
+    let level = (value as u32 * levels + 127) / 255;
+    ((level * 255 + levels / 2) / levels) as u8
+}
+
+/// Quantize every channel of a color to `bits` bits
+pub fn quantize(rgb: (u8, u8, u8), bits: u8) -> (u8, u8, u8) {
+    (
+        quantize_channel(rgb.0, bits),
+        quantize_channel(rgb.1, bits),
+        quantize_channel(rgb.2, bits),
+    )
+}
+
+/// Shift a color toward orange (`amount > 0`) or blue (`amount < 0`), like changing the white balance.
+///
+/// Each channel is scaled in linear light by the matching channel of a warm or cool blackbody white,
+/// blended with neutral by `|amount|`. At `±1`, white becomes [`WARM_WHITE`] or [`COOL_WHITE`]; `0`
+/// returns the color unchanged. `amount` is clamped to `[-1, 1]`.
+pub fn warmth(rgb: (u8, u8, u8), amount: f32) -> (u8, u8, u8) {
+    let amount = amount.clamp(-1.0, 1.0);
+    if amount == 0.0 {
+        return rgb;
+    }
+    let white = if amount > 0.0 { WARM_WHITE } else { COOL_WHITE };
+    let shift = |c: u8, w: u8| {
+        let gain = 1.0 + (srgb_to_linear(w) - 1.0) * amount.abs();
+        linear_to_srgb(srgb_to_linear(c) * gain)
+    };
+    (shift(rgb.0, white.0), shift(rgb.1, white.1), shift(rgb.2, white.2))
+}
+
+/// Linearly interpolate between two colors; `t = 0` gives `from`, `t = 1` gives `to`
+pub fn interpolate(from: (u8, u8, u8), to: (u8, u8, u8), t: f32) -> (u8, u8, u8) {
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round().clamp(0.0, 255.0) as u8;
+    (mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
+}
+
+/// Like [`interpolate`] for the `index`-th color of a gradient, but nudged by an ordered dither
+/// before rounding, so neighboring steps round different ways instead of forming flat bands. Each
+/// channel stays within 1 of the undithered value.
+pub fn interpolate_dithered(from: (u8, u8, u8), to: (u8, u8, u8), t: f32, index: usize) -> (u8, u8, u8) {
+    let offset = DITHER_OFFSETS[index % DITHER_OFFSETS.len()];
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t + offset).round().clamp(0.0, 255.0) as u8;
+    (mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
+}
+
+/// Weighted average of colors in HSL space; `weights` should sum to 1.
+///
+/// Hue is averaged as an angle, so red and yellow meet at orange along the shorter arc instead
+/// of passing through a muddy RGB midpoint. Each hue counts in proportion to its saturation, since
+/// grays have no meaningful hue. Saturation and lightness are averaged linearly.
+pub fn blend_hsl(colors: &[(u8, u8, u8)], weights: &[f32]) -> (u8, u8, u8) {
+    let (mut x, mut y, mut s, mut l) = (0.0, 0.0, 0.0, 0.0);
+    for (&rgb, &w) in colors.iter().zip(weights) {
+        let hsl = rgb_to_hsl(rgb);
+        let (sin, cos) = hsl.h.to_radians().sin_cos();
+        x += cos * hsl.s * w;
+        y += sin * hsl.s * w;
+        s += hsl.s * w;
+        l += hsl.l * w;
+    }
+    let h = y.atan2(x).to_degrees().rem_euclid(360.0);
+    hsl_to_rgb(Hsl { h, s, l })
+}
+
+/// Offset each channel by a pseudo-random amount in `[-amount, amount]`, clamped to `[0, 255]`.
+///
+/// The offsets come from a PCG generator seeded with `seed`, so the same seed always gives the
+/// same color.
+pub fn jitter(rgb: (u8, u8, u8), seed: u64, amount: u8) -> (u8, u8, u8) {
+    let mut rng = Pcg32::seed_from_u64(seed);
+    let span = 2 * amount as u64 + 1;
+    let mut offset = |c: u8| {
+        // Scale a 32-bit sample onto `0..span` without the bias of `%`
+        let step = (rng.next_u32() as u64 * span) >> 32;
+        (c as i32 + step as i32 - amount as i32).clamp(0, 255) as u8
+    };
+    (offset(rgb.0), offset(rgb.1), offset(rgb.2))
+}
+
+/// Adjust the lightness of `rgb` until its contrast against `bg` reaches `min_ratio`, keeping hue
+/// and saturation. Returns the color and the contrast it achieves.
+///
+/// Both darker and lighter colors are considered, preferring the smallest lightness change. If
+/// neither can reach `min_ratio`, the end (black or white) with the higher contrast is returned.
+pub fn ensure_contrast(rgb: (u8, u8, u8), bg: (u8, u8, u8), min_ratio: f32) -> ((u8, u8, u8), f32) {
+    let ratio = contrast_ratio(rgb, bg);
+    if ratio >= min_ratio {
+        return (rgb, ratio);
+    }
+
+    let hsl = rgb_to_hsl(rgb);
+    let with_lightness = |l: f32| hsl_to_rgb(Hsl { l, ..hsl });
+
+    // For each direction, find the lightness closest to the original that meets the target
+    let search = |target_l: f32| -> Option<f32> {
+        if contrast_ratio(with_lightness(target_l), bg) < min_ratio {
+            return None;
+        }
+        let (mut near, mut far) = (hsl.l, target_l);
+        for _ in 0..CONTRAST_SEARCH_STEPS {
+            let mid = (near + far) / 2.0;
+            if contrast_ratio(with_lightness(mid), bg) >= min_ratio {
+                far = mid;
+            } else {
+                near = mid;
+            }
+        }
+        Some(far)
+    };
+
+    let best_l = match (search(0.0), search(100.0)) {
+        (Some(dark), Some(light)) => {
+            if hsl.l - dark <= light - hsl.l {
+                dark
+            } else {
+                light
+            }
+        }
+        (Some(dark), None) => dark,
+        (None, Some(light)) => light,
+        (None, None) => {
+            if contrast_ratio(with_lightness(0.0), bg) >= contrast_ratio(with_lightness(100.0), bg) {
+                0.0
+            } else {
+                100.0
+            }
+        }
+    };
+
+    let adjusted = with_lightness(best_l);
+    (adjusted, contrast_ratio(adjusted, bg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantize_boundaries() {
+        for value in 0..=255 {
+            assert_eq!(quantize_channel(value, 8), value);
+            assert_eq!(quantize_channel(value, 1), if value < 128 { 0 } else { 255 });
+        }
+        assert_eq!(quantize((10, 128, 250), 1), (0, 255, 255));
+        assert_eq!(quantize((0, 36, 255), 3), (0, 36, 255));
+    }
+
+    #[test]
+    fn low_contrast_is_fixed_by_changing_lightness_only() {
+        let yellow = (255, 255, 0);
+        let white = (255, 255, 255);
+        let (adjusted, ratio) = ensure_contrast(yellow, white, 4.5);
+        assert!(ratio >= 4.5, "{ratio}");
+        assert_eq!(ratio, contrast_ratio(adjusted, white));
+        let (before, after) = (rgb_to_hsl(yellow), rgb_to_hsl(adjusted));
+        assert!((before.h - after.h).abs() < 2.0, "{before:?} -> {after:?}");
+        assert!(after.l < before.l);
+
+        // Already legible colors are left alone
+        assert_eq!(ensure_contrast((0, 0, 0), white, 4.5).0, (0, 0, 0));
+    }
+
+    #[test]
+    fn jitter_is_reproducible_per_seed() {
+        let base = (128, 0, 250);
+        assert_eq!(jitter(base, 42, 10), jitter(base, 42, 10));
+        assert_ne!(jitter(base, 42, 10), jitter(base, 43, 10));
+        assert_eq!(jitter(base, 42, 0), base);
+
+        for seed in 0..200 {
+            let (r, g, b) = jitter(base, seed, 10);
+            assert!(r.abs_diff(128) <= 10 && g <= 10 && b >= 240, "seed {seed}: {:?}", (r, g, b));
+        }
+    }
+
+    #[test]
+    fn warmth_is_identity_at_zero_and_white_balance_at_the_extremes() {
+        for rgb in [(0, 0, 0), (12, 200, 99), (255, 255, 255)] {
+            assert_eq!(warmth(rgb, 0.0), rgb);
+        }
+        let white = (255, 255, 255);
+        assert_eq!(warmth(white, 1.0), WARM_WHITE);
+        assert_eq!(warmth(white, -1.0), COOL_WHITE);
+        // Out-of-range amounts are clamped, and black has no light to shift
+        assert_eq!(warmth(white, 5.0), WARM_WHITE);
+        assert_eq!(warmth((0, 0, 0), -1.0), (0, 0, 0));
+
+        let gray = (128, 128, 128);
+        let (warm, cool) = (warmth(gray, 0.5), warmth(gray, -0.5));
+        assert!(warm.0 >= warm.1 && warm.1 > warm.2, "{warm:?}");
+        assert!(cool.2 > cool.1 && cool.1 > cool.0, "{cool:?}");
+    }
+}
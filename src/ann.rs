@@ -0,0 +1,101 @@
+//! Approximate nearest-neighbor search over L2-normalized embeddings.
+//!
+//! The index is an inverted file (IVF): references are clustered with spherical k-means, and a
+//! query only scores the references in its `probes` closest clusters instead of the whole palette.
+
+use serde::{Deserialize, Serialize};
+
+/// Number of k-means refinement passes when building the index
+const KMEANS_ITERATIONS: usize = 10;
+
+/// Inverted-file index over a fixed set of normalized vectors
+#[derive(Deserialize, Serialize)]
+pub struct IvfIndex {
+    centroids: Vec<Vec<f32>>,
+    lists: Vec<Vec<usize>>,
+    probes: usize,
+}
+
+impl IvfIndex {
+    /// Cluster `vectors` into roughly `sqrt(n)` lists. Vectors must already be L2-normalized.
+    pub fn build(vectors: &[&[f32]], probes: usize) -> Self {
+        let n = vectors.len();
+        let nlist = ((n as f64).sqrt().round() as usize).clamp(1, n.max(1));
+
+        // Deterministic seeding: evenly spaced vectors from the input
+        let mut centroids: Vec<Vec<f32>> = (0..nlist).map(|c| vectors[c * n / nlist].to_vec()).collect();
+        let mut assignment = vec![0usize; n];
+
+        for _ in 0..KMEANS_ITERATIONS {
+            for (i, v) in vectors.iter().enumerate() {
+                assignment[i] = nearest_centroid(&centroids, v);
+            }
+
+            let dim = centroids[0].len();
+            let mut sums = vec![vec![0.0f32; dim]; nlist];
+            for (v, &c) in vectors.iter().zip(&assignment) {
+                sums[c].iter_mut().zip(v.iter()).for_each(|(s, x)| *s += x);
+            }
+            for (centroid, sum) in centroids.iter_mut().zip(sums) {
+                let norm = sum.iter().map(|x| x * x).sum::<f32>().sqrt();
+                // Empty clusters keep their previous centroid
+                if norm > 0.0 {
+                    *centroid = sum.into_iter().map(|x| x / norm).collect();
+                }
+            }
+        }
+
+        let mut lists = vec![Vec::new(); nlist];
+        for (i, v) in vectors.iter().enumerate() {
+            lists[nearest_centroid(&centroids, v)].push(i);
+        }
+
+        IvfIndex {
+            centroids,
+            lists,
+            probes: probes.clamp(1, nlist),
+        }
+    }
+
+    /// Indices of the vectors in the clusters closest to `query`, in ascending order
+    pub fn candidates(&self, query: &[f32]) -> Vec<usize> {
+        let mut ranked: Vec<(usize, f32)> = self
+            .centroids
+            .iter()
+            .enumerate()
+            .map(|(c, centroid)| (c, dot(centroid, query)))
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let mut candidates: Vec<usize> = ranked
+            .iter()
+            .take(self.probes)
+            .flat_map(|&(c, _)| self.lists[c].iter().copied())
+            .collect();
+        candidates.sort_unstable();
+        candidates
+    }
+
+    /// Change how many clusters are searched per query
+    pub fn set_probes(&mut self, probes: usize) {
+        self.probes = probes.clamp(1, self.lists.len());
+    }
+
+    /// Number of clusters in the index
+    pub fn list_count(&self) -> usize {
+        self.lists.len()
+    }
+}
+
+fn nearest_centroid(centroids: &[Vec<f32>], v: &[f32]) -> usize {
+    centroids
+        .iter()
+        .enumerate()
+        .map(|(c, centroid)| (c, dot(centroid, v)))
+        .fold((0, f32::MIN), |best, cur| if cur.1 > best.1 { cur } else { best })
+        .0
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
@@ -0,0 +1,91 @@
+//! Curated antonym pairs (`COLORIZER_ANTONYMS_PATH`), used to pull apart opposite words that the
+//! embedding geometry places close together.
+//!
+//! Words like "happy" and "sad" often share contexts, so a text can score almost equally against
+//! both. When the top two matches are a known pair, the runner-up is penalized so the better one
+//! wins blends and distributions more decisively.
+
+use std::{collections::HashSet, error::Error, fs, path::Path};
+
+use colorizer::matching::Match;
+
+/// Antonym pairs, stored in both orders for lookup
+pub struct Antonyms {
+    pairs: HashSet<(String, String)>,
+    /// Subtracted from the runner-up's similarity when the top two matches are antonyms
+    penalty: f32,
+}
+
+impl Antonyms {
+    /// Read a JSON array of word pairs, e.g. `[["happy", "sad"], ["hot", "cold"]]`
+    pub fn load(path: &Path, penalty: f32) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let text = fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        let list: Vec<(String, String)> =
+            serde_json::from_str(&text).map_err(|e| format!("invalid antonyms file {}: {}", path.display(), e))?;
+        let pairs = list
+            .into_iter()
+            .flat_map(|(a, b)| [(a.clone(), b.clone()), (b, a)])
+            .collect();
+        Ok(Antonyms { pairs, penalty })
+    }
+
+    /// Number of distinct pairs
+    pub fn pair_count(&self) -> usize {
+        self.pairs.iter().filter(|(a, b)| a <= b).count()
+    }
+
+    fn are_antonyms(&self, a: &str, b: &str) -> bool {
+        self.pairs.contains(&(a.to_string(), b.to_string()))
+    }
+
+    /// Penalize the second of `top` (best first) if it's an antonym of the first, then restore the
+    /// best-first order. `word` looks up a match's reference word.
+    pub fn separate<'a>(&self, top: &mut [Match], word: impl Fn(usize) -> &'a str) {
+        let [first, second, ..] = top else {
+            return;
+        };
+        if self.are_antonyms(word(first.index), word(second.index)) {
+            second.similarity -= self.penalty;
+            top.sort_by(|a, b| b.cmp(a));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(scores: &[(usize, f32)]) -> Vec<Match> {
+        scores
+            .iter()
+            .map(|&(index, similarity)| Match { index, similarity, rank: index })
+            .collect()
+    }
+
+    #[test]
+    fn the_runner_up_is_penalized_only_when_it_is_an_antonym_of_the_best() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("antonyms.json");
+        fs::write(&path, r#"[["happy", "sad"], ["hot", "cold"]]"#).unwrap();
+        let antonyms = Antonyms::load(&path, 0.1).unwrap();
+        assert_eq!(antonyms.pair_count(), 2);
+
+        let words = ["happy", "sad", "calm", "hot"];
+        let word = |i: usize| words[i];
+        let order = |top: &[Match]| top.iter().map(|m| (words[m.index], m.similarity)).collect::<Vec<_>>();
+
+        // Either order of the pair works, and a third word can overtake the penalized one
+        let mut top = matches(&[(1, 0.8), (0, 0.79), (2, 0.75)]);
+        antonyms.separate(&mut top, word);
+        let expected = [("sad", 0.8), ("calm", 0.75), ("happy", 0.79 - 0.1)];
+        assert_eq!(order(&top), expected);
+
+        let mut top = matches(&[(0, 0.8), (2, 0.79), (1, 0.78)]);
+        antonyms.separate(&mut top, word);
+        assert_eq!(order(&top), [("happy", 0.8), ("calm", 0.79), ("sad", 0.78)]);
+
+        let mut single = matches(&[(3, 0.9)]);
+        antonyms.separate(&mut single, word);
+        assert_eq!(order(&single), [("hot", 0.9)]);
+    }
+}
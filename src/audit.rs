@@ -0,0 +1,176 @@
+//! Optional audit log of `/color` requests (`COLORIZER_AUDIT_LOG`), one JSON object per line, for
+//! studying which reference words get chosen over time.
+//!
+//! The file is rotated once it would grow past `COLORIZER_AUDIT_LOG_MAX_BYTES`: `audit.jsonl`
+//! becomes `audit.jsonl.1`, the previous `.1` becomes `.2`, and so on up to
+//! `COLORIZER_AUDIT_LOG_KEEP` old files.
+
+use serde::Serialize;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// One line of the audit log. Raw text is only included when `COLORIZER_LOG_TEXT=1`.
+#[derive(Serialize)]
+pub struct AuditRecord<'a> {
+    /// Seconds since the Unix epoch
+    pub ts: u64,
+    pub id: &'a str,
+    pub text_len: usize,
+    pub word: Option<&'a str>,
+    pub similarity: Option<f32>,
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<&'a str>,
+}
+
+/// The open log file and its current size
+struct Current {
+    file: File,
+    len: u64,
+}
+
+/// Appends [`AuditRecord`]s to a size-rotated JSONL file
+pub struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    current: Mutex<Current>,
+}
+
+impl AuditLog {
+    /// Open `path` for appending, creating it if needed
+    pub fn open(path: &Path, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        Ok(AuditLog {
+            path: path.to_path_buf(),
+            max_bytes,
+            keep,
+            current: Mutex::new(open_append(path)?),
+        })
+    }
+
+    /// Append one record, rotating first if it would take the file past the size limit
+    pub fn record(&self, record: &AuditRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let mut current = self.current.lock().unwrap();
+        if current.len > 0 && current.len + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+            *current = open_append(&self.path)?;
+        }
+        current.file.write_all(&line)?;
+        current.len += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shift `path.N` to `path.N+1`, dropping the oldest, and move `path` to `path.1`
+    fn rotate(&self) -> io::Result<()> {
+        if self.keep == 0 {
+            return fs::remove_file(&self.path);
+        }
+        for n in (1..self.keep).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                fs::rename(&from, self.rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<Current> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let len = file.metadata()?.len();
+    Ok(Current { file, len })
+}
+
+/// Seconds since the Unix epoch, for [`AuditRecord::ts`]
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn record(id: &str) -> AuditRecord<'_> {
+        AuditRecord {
+            ts: 1_700_000_000,
+            id,
+            text_len: 11,
+            word: Some("ocean"),
+            similarity: Some(0.5),
+            r: 0,
+            g: 80,
+            b: 200,
+            text: None,
+        }
+    }
+
+    /// The `id` of each record in the file at `path`, oldest first
+    fn ids(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["id"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn records_are_appended_as_one_json_object_per_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        AuditLog::open(&path, 1 << 20, 1).unwrap().record(&record("r1")).unwrap();
+        // Reopening appends instead of truncating
+        let log = AuditLog::open(&path, 1 << 20, 1).unwrap();
+        log.record(&AuditRecord { text: Some("stormy \"sea\"\n"), ..record("r2") }).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.ends_with('\n'));
+        let lines: Vec<Value> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let first = json!({
+            "ts": 1_700_000_000, "id": "r1", "text_len": 11, "word": "ocean", "similarity": 0.5,
+            "r": 0, "g": 80, "b": 200
+        });
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], first);
+        assert_eq!(lines[1]["text"], "stormy \"sea\"\n");
+    }
+
+    #[test]
+    fn the_file_rotates_past_the_size_limit_and_keeps_n_old_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let line_len = serde_json::to_vec(&record("r1")).unwrap().len() as u64 + 1;
+        // Room for two records per file, and two old files
+        let log = AuditLog::open(&path, 2 * line_len, 2).unwrap();
+        for n in 1..=7 {
+            log.record(&record(&format!("r{}", n))).unwrap();
+        }
+
+        assert_eq!(ids(&path), ["r7"]);
+        assert_eq!(ids(&log.rotated(1)), ["r5", "r6"]);
+        assert_eq!(ids(&log.rotated(2)), ["r3", "r4"]);
+        assert!(!log.rotated(3).exists());
+        for file in [path.clone(), log.rotated(1), log.rotated(2)] {
+            assert!(fs::metadata(file).unwrap().len() <= 2 * line_len);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::{header, StatusCode},
+    middleware::Next,
+    web, Error,
+};
+
+use crate::error_response;
+
+/// Paths that require the API key when one is configured
+pub const PROTECTED_PATHS: &[&str] = &[
+    "/color",
+    "/color/debug",
+    "/colors",
+    "/colors/stream",
+    "/gradient",
+    "/interpolate",
+    "/swatch",
+    "/palette/nearest",
+    "/embed",
+    "/model-info",
+    "/compare",
+    "/reload",
+    "/usage",
+    "/ws",
+];
+
+/// Token expected in `Authorization: Bearer <key>`; `None` disables auth
+pub struct ApiKey(pub Option<String>);
+
+impl ApiKey {
+    /// Whether the request carries the expected bearer token
+    fn authorizes(&self, req: &ServiceRequest) -> bool {
+        let Some(expected) = &self.0 else {
+            return true;
+        };
+        req.headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|token| constant_time_eq(token.trim().as_bytes(), expected.as_bytes()))
+    }
+}
+
+/// Compare without returning early, so response timing doesn't reveal how much of the key matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Middleware rejecting requests to [`PROTECTED_PATHS`] with 401 unless they carry the API key
+pub async fn require_api_key(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let protected = PROTECTED_PATHS.contains(&req.path());
+    let authorized = match req.app_data::<web::Data<ApiKey>>() {
+        Some(key) => key.authorizes(&req),
+        None => true,
+    };
+
+    if protected && !authorized {
+        let mut res = error_response(StatusCode::UNAUTHORIZED, "missing or invalid API key");
+        res.headers_mut()
+            .insert(header::WWW_AUTHENTICATE, header::HeaderValue::from_static("Bearer"));
+        return Ok(req.into_response(res).map_into_right_body());
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{
+        middleware::from_fn,
+        test::{call_service, init_service, TestRequest},
+        App, HttpResponse,
+    };
+
+    /// Status of `GET path` with an optional bearer token, against an app expecting `key`
+    async fn status(key: Option<&str>, path: &str, token: Option<&str>) -> StatusCode {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(ApiKey(key.map(str::to_string))))
+                .wrap(from_fn(require_api_key))
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+        let mut req = TestRequest::get().uri(path);
+        if let Some(token) = token {
+            req = req.insert_header((header::AUTHORIZATION, format!("Bearer {token}")));
+        }
+        call_service(&app, req.to_request()).await.status()
+    }
+
+    #[actix_web::test]
+    async fn protected_paths_need_the_right_token() {
+        for path in ["/color", "/colors", "/ws"] {
+            assert_eq!(status(Some("secret"), path, None).await, StatusCode::UNAUTHORIZED, "{path}");
+            assert_eq!(status(Some("secret"), path, Some("guess")).await, StatusCode::UNAUTHORIZED, "{path}");
+            assert_eq!(status(Some("secret"), path, Some("secre")).await, StatusCode::UNAUTHORIZED, "{path}");
+            assert_eq!(status(Some("secret"), path, Some("secret")).await, StatusCode::OK, "{path}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn open_paths_and_unset_keys_need_no_token() {
+        for path in ["/", "/healthz"] {
+            assert_eq!(status(Some("secret"), path, None).await, StatusCode::OK, "{path}");
+        }
+        assert_eq!(status(None, "/color", None).await, StatusCode::OK);
+    }
+}
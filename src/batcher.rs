@@ -0,0 +1,108 @@
+//! Micro-batching of concurrent embedding requests (`COLORIZER_BATCH_WINDOW_MS`).
+//!
+//! Texts arriving within one window are embedded by a single [`Embedder::embed_batch`] call, and
+//! each result is sent back to its waiting request over a oneshot channel.
+
+use actix_web::web;
+use colorizer::error::ColorizerError;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{timeout_at, Instant},
+};
+
+use crate::{embedder::Embedder, metrics::Metrics};
+
+/// Where a queued text's embedding is delivered
+type Reply = oneshot::Sender<Result<Vec<f32>, ColorizerError>>;
+
+/// Handle to the background task collecting texts into batches
+pub struct Batcher {
+    queue: mpsc::UnboundedSender<(String, Reply)>,
+}
+
+impl Batcher {
+    /// Start the collecting task. A batch is run `window` after its first text arrives, or as soon
+    /// as it holds `max_size` texts.
+    ///
+    /// Must be called from within a Tokio runtime; the task stops once the `Batcher` is dropped.
+    pub fn spawn(
+        embedder: Arc<dyn Embedder + Send + Sync>,
+        window: Duration,
+        max_size: usize,
+        metrics: web::Data<Metrics>,
+    ) -> Self {
+        let (queue, jobs) = mpsc::unbounded_channel();
+        tokio::spawn(collect(jobs, embedder, window, max_size, metrics));
+        Batcher { queue }
+    }
+
+    /// Queue `text` for the next batch and wait for its embedding
+    pub async fn embed(&self, text: String) -> Result<Vec<f32>, ColorizerError> {
+        let (reply, result) = oneshot::channel();
+        self.queue
+            .send((text, reply))
+            .map_err(|_| ColorizerError::Inference("embedding batcher has stopped".to_string()))?;
+        result
+            .await
+            .map_err(|_| ColorizerError::Inference("embedding batch was dropped".to_string()))?
+    }
+}
+
+/// Gather queued texts into batches and run each on the blocking thread pool
+async fn collect(
+    mut jobs: mpsc::UnboundedReceiver<(String, Reply)>,
+    embedder: Arc<dyn Embedder + Send + Sync>,
+    window: Duration,
+    max_size: usize,
+    metrics: web::Data<Metrics>,
+) {
+    while let Some(first) = jobs.recv().await {
+        let deadline = Instant::now() + window;
+        let mut batch = vec![first];
+        while batch.len() < max_size {
+            match timeout_at(deadline, jobs.recv()).await {
+                Ok(Some(job)) => batch.push(job),
+                // The window is over, or every sender is gone
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        let embedder = Arc::clone(&embedder);
+        let metrics = metrics.clone();
+        tokio::task::spawn_blocking(move || run_batch(embedder.as_ref(), batch, &metrics));
+    }
+}
+
+/// Embed one batch and answer every waiter.
+///
+/// If the batched run fails, each text is retried on its own so one bad input only fails its own
+/// request rather than every request that shared its window.
+fn run_batch(embedder: &(dyn Embedder + Send + Sync), batch: Vec<(String, Reply)>, metrics: &Metrics) {
+    let (texts, replies): (Vec<String>, Vec<Reply>) = batch.into_iter().unzip();
+    let texts: Vec<&str> = texts.iter().map(String::as_str).collect();
+
+    let timer = metrics.inference_duration.start_timer();
+    let batched = embedder.embed_batch(&texts);
+    timer.observe_duration();
+
+    match batched {
+        Ok(embeddings) => {
+            for (reply, embedding) in replies.into_iter().zip(embeddings) {
+                // The request may have timed out and stopped waiting
+                let _ = reply.send(Ok(embedding));
+            }
+        }
+        Err(e) if texts.len() == 1 => {
+            if let Some(reply) = replies.into_iter().next() {
+                let _ = reply.send(Err(e));
+            }
+        }
+        Err(_) => {
+            for (text, reply) in texts.into_iter().zip(replies) {
+                let _timer = metrics.inference_duration.start_timer();
+                let _ = reply.send(embedder.embed(text));
+            }
+        }
+    }
+}
@@ -1,66 +1,156 @@
 use anyhow::{anyhow, Result};
-use ndarray::{Array2, Axis, CowArray};
-use ort::{
-    environment::Environment,
-    session::{Session, SessionBuilder},
-    tensor::OrtOwnedTensor,
-    value::Value,
+use colorizer::{
+    embedding::{get_embedding, EmbeddingOptions},
+    env::{env_var, load_config_file, load_profile},
+    precision::Precision,
+    profile::Profile,
+    session::{build_session, Provider},
+};
+use half::f16;
+use ort::environment::Environment;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    sync::Arc,
 };
-use serde::Serialize;
-use serde_json;
-use std::{fs::File, sync::Arc};
 use tokenizers::Tokenizer;
 
-/// A reference embedding tied to a color.
-#[derive(Serialize)]
-struct RefEmbedding {
-    embedding: Vec<f32>,
-    color: (u8, u8, u8),
-}
+/// An RGB color triple.
+type RgbColor = (u8, u8, u8);
 
-/// Generate an embedding for a sentence using the tokenizer + ONNX model.
-fn get_embedding(tokenizer: &Tokenizer, session: &Session, sentence: &str) -> Result<Vec<f32>> {
-    let encoding = tokenizer
-        .encode(sentence, true)
-        .map_err(|e| anyhow!("Tokenizer error: {}", e))?;
+/// Where the embeddings are written, and what `--report` reads by default.
+const REF_EMBEDDINGS_PATH: &str = "custom/ref_embeddings.json";
 
-    let input_ids: Vec<i64> = encoding.get_ids().iter().map(|&id| id as i64).collect();
-    let attention_mask: Vec<i64> = encoding.get_attention_mask().iter().map(|&m| m as i64).collect();
+/// Cosine similarity at or above which `--report` groups two words, unless
+/// `COLORIZER_CLUSTER_THRESHOLD` is set.
+const DEFAULT_CLUSTER_THRESHOLD: f32 = 0.95;
 
-    let seq_len = input_ids.len();
-    let input_ids_arr = Array2::from_shape_vec((1, seq_len), input_ids)?.into_dyn();
-    let attention_mask_arr = Array2::from_shape_vec((1, seq_len), attention_mask)?.into_dyn();
+/// A reference embedding tied to a color, with values of type `T`.
+#[derive(Serialize, Deserialize)]
+struct RefEmbedding<T> {
+    word: String,
+    embedding: Vec<T>,
+    color: RgbColor,
+}
 
-    // ✅ Bind temporaries so they live long enough
-    let input_ids_cow = CowArray::from(input_ids_arr);
-    let attention_mask_cow = CowArray::from(attention_mask_arr);
+/// Reference file written with `COLORIZER_REF_PRECISION=f16`; f32 files are a plain array.
+#[derive(Serialize, Deserialize)]
+struct TaggedRefFile {
+    precision: Precision,
+    references: Vec<RefEmbedding<f16>>,
+}
 
-    let input_ids_val = Value::from_array(session.allocator(), &input_ids_cow)?;
-    let attention_mask_val = Value::from_array(session.allocator(), &attention_mask_cow)?;
+/// Either reference file format, as read back by `--report`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RefFile {
+    Plain(Vec<RefEmbedding<f32>>),
+    Tagged(TaggedRefFile),
+}
 
-    let outputs = session.run(vec![input_ids_val, attention_mask_val])?;
-    let tensor: OrtOwnedTensor<f32, _> = outputs[0].try_extract()?;
-    let arr = tensor.view();
+/// One word of a `--report` cluster.
+#[derive(Serialize)]
+struct ClusterMember {
+    word: String,
+    color: RgbColor,
+}
 
-    // Average token embeddings
-    let summed = arr.index_axis(Axis(0), 0).sum_axis(Axis(0));
-    let pooled = summed.clone() / summed.len() as f32;
+/// Words whose embeddings are near-identical, linked by pairs at or above the threshold.
+#[derive(Serialize)]
+struct Cluster {
+    /// Lowest similarity among the linking pairs, so how loosely the cluster holds together
+    min_similarity: f32,
+    members: Vec<ClusterMember>,
+}
 
-    Ok(pooled.into_raw_vec())
+/// JSON written to stdout by `--report`.
+#[derive(Serialize)]
+struct ClusterReport {
+    threshold: f32,
+    reference_count: usize,
+    /// Words that could be pruned while keeping one per cluster
+    redundant_count: usize,
+    /// Largest clusters first
+    clusters: Vec<Cluster>,
 }
 
-/// Entrypoint: generates `custom/ref_embeddings.json`
-fn main() -> Result<()> {
-    println!("📦 Generating reference embeddings...");
+/// What to do when the same word appears more than once in the palette.
+#[derive(Clone, Copy)]
+enum DuplicatePolicy {
+    /// Abort, listing every duplicated word.
+    Error,
+    /// Keep one entry whose color is the average of all entries.
+    Average,
+}
 
-    // Load tokenizer + ONNX model
-    let tokenizer = Tokenizer::from_file("models/tokenizer.json")
-        .map_err(|e| anyhow!("Failed to load tokenizer: {}", e))?;
-    let environment = Arc::new(Environment::builder().with_name("default").build()?);
-    let session = SessionBuilder::new(&environment)?.with_model_from_file("models/model.onnx")?;
+impl DuplicatePolicy {
+    /// Read `COLORIZER_DUPLICATE_POLICY` (`average` or `error`, default `average`).
+    fn from_env() -> Result<Self> {
+        match env_var("COLORIZER_DUPLICATE_POLICY").as_deref() {
+            None | Some("average") => Ok(DuplicatePolicy::Average),
+            Some("error") => Ok(DuplicatePolicy::Error),
+            Some(other) => Err(anyhow!(
+                "Invalid COLORIZER_DUPLICATE_POLICY {:?}: expected \"average\" or \"error\"",
+                other
+            )),
+        }
+    }
+}
+
+/// Collapse duplicate words according to `policy`, keeping first-seen order.
+///
+/// Returns the deduplicated list and the number of words that had duplicates.
+fn dedup_ref_words(
+    ref_words: Vec<(String, RgbColor)>,
+    policy: DuplicatePolicy,
+) -> Result<(Vec<(String, RgbColor)>, usize)> {
+    let mut groups: Vec<(String, Vec<RgbColor>)> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for (word, rgb) in ref_words {
+        match positions.get(&word) {
+            Some(&i) => groups[i].1.push(rgb),
+            None => {
+                positions.insert(word.clone(), groups.len());
+                groups.push((word, vec![rgb]));
+            }
+        }
+    }
+
+    let duplicates: Vec<&str> = groups
+        .iter()
+        .filter(|(_, colors)| colors.len() > 1)
+        .map(|(word, _)| word.as_str())
+        .collect();
+    if matches!(policy, DuplicatePolicy::Error) && !duplicates.is_empty() {
+        return Err(anyhow!("Duplicate reference words: {}", duplicates.join(", ")));
+    }
+    let duplicate_count = duplicates.len();
+
+    let deduped = groups
+        .into_iter()
+        .map(|(word, colors)| {
+            if colors.len() == 1 {
+                return (word, colors[0]);
+            }
+            let mean = |channel: fn(&RgbColor) -> u8| {
+                let sum: u32 = colors.iter().map(|c| channel(c) as u32).sum();
+                (sum as f32 / colors.len() as f32).round() as u8
+            };
+            let averaged = (mean(|c| c.0), mean(|c| c.1), mean(|c| c.2));
+            println!("  ⚠️  Duplicate word {:?} ({} entries) → averaged to {:?}", word, colors.len(), averaged);
+            (word, averaged)
+        })
+        .collect();
+
+    Ok((deduped, duplicate_count))
+}
 
-    // Words mapped to representative RGB colors
-    let ref_words = vec![
+/// Built-in words mapped to representative RGB colors.
+fn builtin_ref_words() -> Vec<(String, RgbColor)> {
+    let ref_words: Vec<(&str, RgbColor)> = vec![
         ("love", (255, 0, 0)),
         ("sun", (255, 255, 0)),
         ("girl", (255, 110, 240)),
@@ -307,21 +397,346 @@ fn main() -> Result<()> {
         ("decay", (128, 128, 0)),
     ];
 
+    ref_words.into_iter().map(|(word, rgb)| (word.to_string(), rgb)).collect()
+}
+
+/// Read `word,r,g,b` rows from a CSV or TSV palette file.
+///
+/// The delimiter is a tab for `.tsv` files and a comma otherwise. Blank lines, `#` comments
+/// and a leading `word,r,g,b` header are skipped.
+fn load_ref_words(path: &Path) -> Result<Vec<(String, RgbColor)>> {
+    let contents = fs::read_to_string(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let delimiter = if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("tsv")) {
+        '\t'
+    } else {
+        ','
+    };
+
+    let mut ref_words = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(delimiter).map(str::trim).collect();
+        if line_no == 1 && fields.first().is_some_and(|f| f.eq_ignore_ascii_case("word")) {
+            continue;
+        }
+        let [word, r, g, b] = fields[..] else {
+            return Err(anyhow!(
+                "{}:{}: expected 4 columns (word,r,g,b), found {}",
+                path.display(),
+                line_no,
+                fields.len()
+            ));
+        };
+
+        let channel = |name: &str, value: &str| {
+            value.parse::<u8>().map_err(|_| {
+                anyhow!("{}:{}: {} value {:?} is not in 0-255", path.display(), line_no, name, value)
+            })
+        };
+        ref_words.push((word.to_string(), (channel("r", r)?, channel("g", g)?, channel("b", b)?)));
+    }
+
+    Ok(ref_words)
+}
+
+/// Cosine similarity of two embeddings, or 0 if either is all zeros.
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let (norm_a, norm_b) = (norm(a), norm(b));
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+/// Root of `i` in a union-find forest, compressing the path on the way.
+fn find_root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// Group references whose embeddings have a cosine similarity of at least `threshold`.
+///
+/// Grouping is transitive (single linkage): if `a` is close to `b` and `b` to `c`, all three
+/// share a cluster even when `a` and `c` are further apart. Words without a close neighbor are
+/// left out.
+fn cluster_references(references: &[RefEmbedding<f32>], threshold: f32) -> Vec<Cluster> {
+    let n = references.len();
+    let mut parents: Vec<usize> = (0..n).collect();
+    let mut links: Vec<(usize, f32)> = Vec::new();
+    for i in 0..n {
+        for j in i + 1..n {
+            let similarity = cosine_similarity(&references[i].embedding, &references[j].embedding);
+            if similarity >= threshold {
+                let (root_i, root_j) = (find_root(&mut parents, i), find_root(&mut parents, j));
+                parents[root_j] = root_i;
+                links.push((i, similarity));
+            }
+        }
+    }
+
+    // Clusters keyed by root, each listing its members in palette order
+    let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..n {
+        let root = find_root(&mut parents, i);
+        members.entry(root).or_default().push(i);
+    }
+    let mut min_similarity: HashMap<usize, f32> = HashMap::new();
+    for (i, similarity) in links {
+        let root = find_root(&mut parents, i);
+        let min = min_similarity.entry(root).or_insert(similarity);
+        *min = min.min(similarity);
+    }
+
+    let mut clusters: Vec<Cluster> = members
+        .into_iter()
+        .filter(|(_, indices)| indices.len() > 1)
+        .map(|(root, indices)| Cluster {
+            min_similarity: min_similarity[&root],
+            members: indices
+                .into_iter()
+                .map(|i| ClusterMember {
+                    word: references[i].word.clone(),
+                    color: references[i].color,
+                })
+                .collect(),
+        })
+        .collect();
+    clusters.sort_by(|a, b| {
+        b.members
+            .len()
+            .cmp(&a.members.len())
+            .then_with(|| a.members[0].word.cmp(&b.members[0].word))
+    });
+    clusters
+}
+
+/// `--report`: print clusters of near-identical reference words in `path` as JSON, so redundant
+/// entries can be pruned from the palette. Doesn't load the model.
+fn report(path: &Path) -> Result<()> {
+    let threshold = match env_var("COLORIZER_CLUSTER_THRESHOLD") {
+        Some(value) => value
+            .parse::<f32>()
+            .ok()
+            .filter(|t| (-1.0..=1.0).contains(t))
+            .ok_or_else(|| anyhow!("Invalid COLORIZER_CLUSTER_THRESHOLD {:?}: expected a number in [-1, 1]", value))?,
+        None => DEFAULT_CLUSTER_THRESHOLD,
+    };
+
+    let contents = fs::read(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let references = match serde_json::from_slice(&contents)
+        .map_err(|e| anyhow!("Failed to parse {}: {}", path.display(), e))?
+    {
+        RefFile::Plain(references) => references,
+        RefFile::Tagged(file) => file
+            .references
+            .into_iter()
+            .map(|r| RefEmbedding {
+                word: r.word,
+                embedding: r.embedding.into_iter().map(f16::to_f32).collect(),
+                color: r.color,
+            })
+            .collect(),
+    };
+
+    let clusters = cluster_references(&references, threshold);
+    let report = ClusterReport {
+        threshold,
+        reference_count: references.len(),
+        redundant_count: clusters.iter().map(|c| c.members.len() - 1).sum(),
+        clusters,
+    };
+    serde_json::to_writer_pretty(std::io::stdout().lock(), &report)?;
+    println!();
+    Ok(())
+}
+
+/// Write `references` as JSON at `precision`, sorted by word so regenerating from a reordered
+/// palette produces the same file and diffs between regenerations only show real changes
+fn write_ref_embeddings(
+    writer: impl Write,
+    mut references: Vec<RefEmbedding<f32>>,
+    precision: Precision,
+) -> Result<()> {
+    references.sort_by(|a, b| a.word.cmp(&b.word));
+    match precision {
+        Precision::F32 => serde_json::to_writer_pretty(writer, &references)?,
+        Precision::F16 => {
+            let references = references
+                .into_iter()
+                .map(|r| RefEmbedding {
+                    word: r.word,
+                    embedding: r.embedding.into_iter().map(f16::from_f32).collect(),
+                    color: r.color,
+                })
+                .collect();
+            serde_json::to_writer_pretty(writer, &TaggedRefFile { precision, references })?;
+        }
+    }
+    Ok(())
+}
+
+/// Entrypoint: generates `custom/ref_embeddings.json`, or with `--report` analyzes an existing one
+///
+/// Usage: `generate_ref_embeddings [palette.csv|palette.tsv]` or
+/// `generate_ref_embeddings --report [ref_embeddings.json]`
+fn main() -> Result<()> {
+    // Read the same config file as the server, so both embed text the same way
+    if let Some(file) = load_config_file().map_err(|e| anyhow!("{}", e))? {
+        println!("📄 Config file: {}", file.path.display());
+    }
+    let profile = load_profile().map_err(|e| anyhow!("{}", e))?;
+    if profile != Profile::Custom {
+        println!("🎛️  Profile: {}", profile);
+    }
+    let mut args = std::env::args().skip(1);
+    let first_arg = args.next();
+    if first_arg.as_deref() == Some("--report") {
+        let path = args.next().unwrap_or_else(|| REF_EMBEDDINGS_PATH.to_string());
+        return report(Path::new(&path));
+    }
+
+    println!("📦 Generating reference embeddings...");
+
+    // Load tokenizer + ONNX model
+    let mut tokenizer = Tokenizer::from_file("models/tokenizer.json")
+        .map_err(|e| anyhow!("Failed to load tokenizer: {}", e))?;
+    let environment = Arc::new(Environment::builder().with_name("default").build()?);
+    let provider = Provider::from_env().map_err(|e| anyhow!("{}", e))?;
+    let session = build_session(&environment, Path::new("models/model.onnx"), provider)
+        .map_err(|e| anyhow!("Failed to load model: {}", e))?;
+    println!("🖥️  Execution provider: {}", provider);
+
+    // Must match the server's settings, or request and reference embeddings won't be comparable
+    let options = EmbeddingOptions::from_env().map_err(|e| anyhow!("{}", e))?;
+    options
+        .apply_to(&mut tokenizer)
+        .map_err(|e| anyhow!("Failed to configure tokenizer: {}", e))?;
+    println!(
+        "🧮 Pooling: {}, max tokens: {}, lowercase: {}",
+        options.pooling, options.max_tokens, options.lowercase
+    );
+    if let Some(index) = options.cls_index {
+        println!("📍 CLS index: {}", index);
+    }
+    if options.stem {
+        println!("🌱 Stemming enabled");
+    }
+    if options.pre_pool_norm {
+        println!("📏 Token vectors L2-normalized before pooling");
+    }
+    if options.exclude_special {
+        println!("✂️  Special tokens excluded from pooling");
+    }
+    if !options.aliases.is_empty() {
+        println!("🔁 Aliases: {}", options.aliases.len());
+    }
+    if !options.idf.is_empty() {
+        println!("⚖️  IDF weights: {} tokens", options.idf.len());
+    }
+    let precision = Precision::from_env().map_err(|e| anyhow!("{}", e))?;
+    println!("💾 Precision: {}", precision);
+
+    // Words mapped to representative RGB colors, from a palette file if one was given
+    let ref_words = match first_arg {
+        Some(path) => {
+            println!("📄 Reading palette from {}", path);
+            load_ref_words(Path::new(&path))?
+        }
+        None => builtin_ref_words(),
+    };
+    let (ref_words, duplicate_count) = dedup_ref_words(ref_words, DuplicatePolicy::from_env()?)?;
+
     // Build embeddings
     let mut ref_embeddings = Vec::new();
     for (word, rgb) in ref_words {
-        let emb = get_embedding(&tokenizer, &session, word)?;
+        let emb = get_embedding(&tokenizer, &session, &options, &word)
+            .map_err(|e| anyhow!("Failed to embed {:?}: {}", word, e))?;
+        println!("  ✓ Embedded word: {}", word);
         ref_embeddings.push(RefEmbedding {
+            word,
             embedding: emb,
             color: rgb,
         });
-        println!("  ✓ Embedded word: {}", word);
     }
 
     // Save to JSON
-    let file = File::create("custom/ref_embeddings.json")?;
-    serde_json::to_writer_pretty(file, &ref_embeddings)?;
-    println!("✅ Saved reference embeddings → custom/ref_embeddings.json");
+    write_ref_embeddings(File::create(REF_EMBEDDINGS_PATH)?, ref_embeddings, precision)?;
+    println!("✅ Saved reference embeddings → {}", REF_EMBEDDINGS_PATH);
+    if duplicate_count > 0 {
+        println!("⚠️  {} duplicate word(s) were merged", duplicate_count);
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A reference whose 2-D embedding points `degrees` around the unit circle
+    fn at_angle(word: &str, degrees: f32) -> RefEmbedding<f32> {
+        let radians = degrees.to_radians();
+        RefEmbedding {
+            word: word.to_string(),
+            embedding: vec![radians.cos(), radians.sin()],
+            color: (0, 0, 0),
+        }
+    }
+
+    #[test]
+    fn clusters_link_transitively_and_leave_out_lone_words() {
+        // Neighbors 15° apart clear 0.95 (cos 15° ≈ 0.966), while sky and navy, 30° apart, don't
+        let references = [
+            at_angle("lone", 200.0),
+            at_angle("sea", 95.0),
+            at_angle("sky", 0.0),
+            at_angle("azure", 15.0),
+            at_angle("navy", 30.0),
+            at_angle("ocean", 90.0),
+        ];
+        let clusters = cluster_references(&references, 0.95);
+
+        let words: Vec<Vec<&str>> = clusters
+            .iter()
+            .map(|c| c.members.iter().map(|m| m.word.as_str()).collect())
+            .collect();
+        assert_eq!(words, [vec!["sky", "azure", "navy"], vec!["sea", "ocean"]]);
+        assert!((clusters[0].min_similarity - 15f32.to_radians().cos()).abs() < 1e-6);
+        assert!((clusters[1].min_similarity - 5f32.to_radians().cos()).abs() < 1e-6);
+
+        assert!(cluster_references(&references, 0.999).is_empty());
+    }
+
+    #[test]
+    fn regenerating_from_a_reordered_palette_writes_identical_bytes() {
+        let palette = [("sky", 0.0), ("ocean", 90.0), ("azure", 15.0), ("lone", 200.0)];
+        let generate = |order: &[usize], precision: Precision| {
+            let references = order.iter().map(|&i| at_angle(palette[i].0, palette[i].1)).collect();
+            let mut bytes = Vec::new();
+            write_ref_embeddings(&mut bytes, references, precision).unwrap();
+            bytes
+        };
+
+        for precision in [Precision::F32, Precision::F16] {
+            let first = generate(&[0, 1, 2, 3], precision);
+            assert_eq!(first, generate(&[0, 1, 2, 3], precision));
+            assert_eq!(first, generate(&[3, 2, 1, 0], precision));
+        }
+
+        let bytes = generate(&[1, 3, 0, 2], Precision::F32);
+        let written: Vec<RefEmbedding<f32>> = serde_json::from_slice(&bytes).unwrap();
+        let words: Vec<&str> = written.iter().map(|r| r.word.as_str()).collect();
+        assert_eq!(words, ["azure", "lone", "ocean", "sky"]);
+    }
+}
@@ -0,0 +1,161 @@
+//! Conversions between RGB and other color spaces.
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// HSL color: hue in degrees `[0, 360)`, saturation and lightness in percent `[0, 100]`
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, ToSchema)]
+pub struct Hsl {
+    pub h: f32,
+    pub s: f32,
+    pub l: f32,
+}
+
+/// CMYK color, each component in percent `[0, 100]`
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, ToSchema)]
+pub struct Cmyk {
+    pub c: f32,
+    pub m: f32,
+    pub y: f32,
+    pub k: f32,
+}
+
+/// Convert an RGB color to HSL
+pub fn rgb_to_hsl(rgb: (u8, u8, u8)) -> Hsl {
+    let (r, g, b) = (rgb.0 as f32 / 255.0, rgb.1 as f32 / 255.0, rgb.2 as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+    let delta = max - min;
+
+    if delta == 0.0 {
+        return Hsl { h: 0.0, s: 0.0, l: l * 100.0 };
+    }
+
+    let s = delta / (1.0 - (2.0 * l - 1.0).abs());
+    let h = if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+
+    Hsl {
+        h: h.rem_euclid(360.0),
+        s: s * 100.0,
+        l: l * 100.0,
+    }
+}
+
+/// Convert an HSL color back to RGB, rounding each channel
+pub fn hsl_to_rgb(hsl: Hsl) -> (u8, u8, u8) {
+    let s = (hsl.s / 100.0).clamp(0.0, 1.0);
+    let l = (hsl.l / 100.0).clamp(0.0, 1.0);
+    let h = hsl.h.rem_euclid(360.0) / 60.0;
+
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = l - c / 2.0;
+    let channel = |v: f32| ((v + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+
+    (channel(r), channel(g), channel(b))
+}
+
+/// Convert an RGB color to CMYK
+pub fn rgb_to_cmyk(rgb: (u8, u8, u8)) -> Cmyk {
+    let (r, g, b) = (rgb.0 as f32 / 255.0, rgb.1 as f32 / 255.0, rgb.2 as f32 / 255.0);
+    let k = 1.0 - r.max(g).max(b);
+
+    if k >= 1.0 {
+        return Cmyk { c: 0.0, m: 0.0, y: 0.0, k: 100.0 };
+    }
+
+    Cmyk {
+        c: (1.0 - r - k) / (1.0 - k) * 100.0,
+        m: (1.0 - g - k) / (1.0 - k) * 100.0,
+        y: (1.0 - b - k) / (1.0 - k) * 100.0,
+        k: k * 100.0,
+    }
+}
+
+/// Decode an sRGB channel to linear light in `[0, 1]`
+pub fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encode linear light back to an sRGB channel, clamping to `[0, 255]`
+pub fn linear_to_srgb(v: f32) -> u8 {
+    let v = v.clamp(0.0, 1.0);
+    let c = if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round() as u8
+}
+
+/// WCAG 2.x relative luminance in `[0, 1]`
+pub fn relative_luminance(rgb: (u8, u8, u8)) -> f32 {
+    0.2126 * srgb_to_linear(rgb.0) + 0.7152 * srgb_to_linear(rgb.1) + 0.0722 * srgb_to_linear(rgb.2)
+}
+
+/// WCAG contrast ratio between two colors, from 1 (identical) to 21 (black on white)
+pub fn contrast_ratio(a: (u8, u8, u8), b: (u8, u8, u8)) -> f32 {
+    let (la, lb) = (relative_luminance(a), relative_luminance(b));
+    (la.max(lb) + 0.05) / (la.min(lb) + 0.05)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgb_survives_a_round_trip_through_hsl() {
+        for r in (0..=255).step_by(15) {
+            for g in (0..=255).step_by(17) {
+                for b in (0..=255).step_by(51) {
+                    let (r2, g2, b2) = hsl_to_rgb(rgb_to_hsl((r, g, b)));
+                    let close = |x: u8, y: u8| x.abs_diff(y) <= 1;
+                    let back = (r2, g2, b2);
+                    assert!(close(r, r2) && close(g, g2) && close(b, b2), "{:?} came back as {:?}", (r, g, b), back);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn primaries_convert_to_known_values() {
+        assert_eq!(rgb_to_hsl((255, 0, 0)), Hsl { h: 0.0, s: 100.0, l: 50.0 });
+        assert_eq!(rgb_to_hsl((0, 0, 255)), Hsl { h: 240.0, s: 100.0, l: 50.0 });
+        assert_eq!(rgb_to_cmyk((255, 0, 0)), Cmyk { c: 0.0, m: 100.0, y: 100.0, k: 0.0 });
+        assert_eq!(rgb_to_cmyk((0, 0, 0)), Cmyk { c: 0.0, m: 0.0, y: 0.0, k: 100.0 });
+    }
+
+    #[test]
+    fn contrast_matches_known_wcag_pairs() {
+        let white = (255, 255, 255);
+        let close = |ratio: f32, expected: f32| (ratio - expected).abs() < 0.01;
+        assert!(close(contrast_ratio((0, 0, 0), white), 21.0));
+        assert_eq!(contrast_ratio(white, white), 1.0);
+        // #767676 is the lightest gray passing AA on white, #777777 just misses it
+        assert!(close(contrast_ratio((0x76, 0x76, 0x76), white), 4.54));
+        assert!(close(contrast_ratio((0x77, 0x77, 0x77), white), 4.48));
+        assert!(close(contrast_ratio((0, 0, 255), white), 8.59));
+        assert!(close(contrast_ratio((255, 0, 0), white), 4.0));
+        assert_eq!(contrast_ratio((0, 0, 255), white), contrast_ratio(white, (0, 0, 255)));
+    }
+}
@@ -0,0 +1,362 @@
+use colorizer::{
+    embedding::EmbeddingOptions,
+    env::{env_flag, env_list, env_opt, env_or, env_parse},
+    metric::SimilarityMetric,
+    reference::{resolve_paths, ConflictPolicy},
+    session::Provider,
+};
+use serde::Deserialize;
+use std::{collections::BTreeMap, error::Error, fs, num::NonZeroUsize, path::PathBuf};
+
+use crate::RgbColor;
+
+/// Name given to the model configured through the single-model environment variables
+const DEFAULT_MODEL_NAME: &str = "default";
+
+/// Files for one embedding model and the reference embeddings generated with it
+#[derive(Deserialize)]
+pub struct ModelSpec {
+    pub tokenizer: PathBuf,
+    pub model: PathBuf,
+    pub ref_embeddings: PathBuf,
+}
+
+/// Contents of `COLORIZER_MODELS_FILE`:
+/// `{ "default": "colors", "models": { "colors": { "tokenizer": ..., "model": ..., "ref_embeddings": ... } } }`
+#[derive(Deserialize)]
+struct ModelsFile {
+    default: String,
+    models: BTreeMap<String, ModelSpec>,
+}
+
+/// Per-client request rate limit
+#[derive(Clone, Copy)]
+pub struct RateLimit {
+    /// Interval at which one request's worth of quota is replenished
+    pub milliseconds_per_request: u64,
+    /// Number of requests a client may make back-to-back
+    pub burst: u32,
+}
+
+/// Runtime configuration, resolved from `COLORIZER_*` environment variables and the config file.
+pub struct Config {
+    /// Models by name; a single `default` model unless `COLORIZER_MODELS_FILE` is set
+    pub models: BTreeMap<String, ModelSpec>,
+    /// Model used when a request doesn't name one
+    pub default_model: String,
+    /// Where `models` was read from, if not from the single-model variables
+    pub models_file: Option<PathBuf>,
+    pub bind_addr: String,
+    pub port: u16,
+    /// Maximum number of cached embeddings; 0 disables the cache
+    pub embed_cache_size: usize,
+    /// Origins allowed to make cross-origin requests; empty means same-origin only
+    pub allowed_origins: Vec<String>,
+    /// Use the approximate nearest-neighbor index instead of a linear scan
+    pub ann: bool,
+    /// Number of clusters probed per ANN query
+    pub ann_probes: usize,
+    /// JSON file of antonym pairs whose runner-up match is penalized; `None` disables the heuristic
+    pub antonyms_path: Option<PathBuf>,
+    /// Subtracted from the runner-up's similarity when the top two matches are antonyms
+    pub antonym_penalty: f32,
+    /// How request embeddings are scored against the references
+    pub metric: SimilarityMetric,
+    /// Which entry wins when merged reference files define the same word
+    pub ref_conflict: ConflictPolicy,
+    /// Color returned when there is no reference to match
+    pub fallback_color: Option<RgbColor>,
+    /// Serve an empty palette instead of refusing it at startup and on `/reload`
+    pub allow_empty_refs: bool,
+    /// Include the raw input text in request logs
+    pub log_text: bool,
+    /// Reject clearly non-English input (`COLORIZER_STRICT_LANG=en`)
+    pub strict_english: bool,
+    /// Serve raw sentence embeddings on `POST /embed`
+    pub expose_embeddings: bool,
+    /// Serve the full scoring table on `POST /color/debug`
+    pub debug: bool,
+    /// Rate limit applied per client IP and per WebSocket; `None` when `COLORIZER_RATE_DISABLED=1`
+    pub rate_limit: Option<RateLimit>,
+    /// Requests each client IP may make per UTC day before getting 429; 0 only counts them
+    pub daily_quota: u64,
+    /// Bearer token required on the colorization endpoints; `None` leaves them open
+    pub api_key: Option<String>,
+    /// Largest accepted request body, after decompression; larger bodies fail with 413
+    pub max_body_bytes: usize,
+    /// Texts with fewer real tokens fail with 422; 0 disables the check
+    pub min_tokens: usize,
+    /// Share of unknown tokens above which `/color` answers with a hash color; 1 disables the fallback
+    pub unk_threshold: f32,
+    /// Milliseconds an inference may run before the request fails with 504; 0 disables the limit
+    pub inference_timeout_ms: u64,
+    /// Seconds to wait for in-flight requests to finish after SIGINT/SIGTERM
+    pub shutdown_timeout_secs: u64,
+    /// Times startup tries to find and load the model files before giving up
+    pub load_attempts: NonZeroUsize,
+    /// Milliseconds before the first retry; the wait doubles after each failed attempt
+    pub load_retry_ms: u64,
+    /// Skip the warmup inferences run before the server starts listening
+    pub skip_warmup: bool,
+    /// Skip loading tokenizers and models and answer with hash-derived embeddings instead
+    pub mock: bool,
+    /// Number of actix worker threads; defaults to the number of available CPUs
+    pub workers: usize,
+    /// ONNX sessions loaded per model; inferences are spread across them round-robin
+    pub session_pool: NonZeroUsize,
+    /// Milliseconds concurrent inferences are collected for before running as one batch; 0 disables batching
+    pub batch_window_ms: u64,
+    /// Largest number of texts run in one batch
+    pub batch_max: usize,
+    /// Seconds a `/color` response is replayed for retries with the same `Idempotency-Key`
+    pub idempotency_ttl_secs: u64,
+    /// JSONL file each `/color` request is recorded in; `None` disables the audit log
+    pub audit_log: Option<PathBuf>,
+    /// Size past which the audit log is rotated
+    pub audit_log_max_bytes: u64,
+    /// Rotated audit log files kept besides the current one
+    pub audit_log_keep: usize,
+    /// `max-age` of cacheable `GET` responses, in seconds
+    pub cache_max_age_secs: u32,
+    /// Seconds an idle keep-alive connection stays open; 0 closes connections after each response
+    pub keep_alive_secs: u64,
+    /// Embedding pipeline settings shared with `generate_ref_embeddings`
+    pub embedding: EmbeddingOptions,
+    /// ONNX execution provider used for every model
+    pub provider: Provider,
+}
+
+impl Config {
+    /// Read the configuration from the environment and the config file loaded by
+    /// [`load_config_file`](colorizer::env::load_config_file), falling back to the defaults
+    pub fn from_env() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let models_file = env_opt("COLORIZER_MODELS_FILE").map(PathBuf::from);
+        let (models, default_model) = match &models_file {
+            Some(path) => {
+                let file: ModelsFile = serde_json::from_slice(&fs::read(path)?)
+                    .map_err(|e| format!("invalid models file {}: {}", path.display(), e))?;
+                if !file.models.contains_key(&file.default) {
+                    return Err(format!("default model {:?} is not defined in {}", file.default, path.display()).into());
+                }
+                (file.models, file.default)
+            }
+            None => {
+                let spec = ModelSpec {
+                    tokenizer: env_or("COLORIZER_TOKENIZER_PATH", "models/tokenizer.json").into(),
+                    model: env_or("COLORIZER_MODEL_PATH", "models/model.onnx").into(),
+                    ref_embeddings: env_or("COLORIZER_REF_EMBEDDINGS_PATH", "custom/ref_embeddings.json").into(),
+                };
+                (BTreeMap::from([(DEFAULT_MODEL_NAME.to_string(), spec)]), DEFAULT_MODEL_NAME.to_string())
+            }
+        };
+
+        let default_workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let workers = env_parse("COLORIZER_WORKERS", default_workers)?;
+        if workers == 0 {
+            return Err("COLORIZER_WORKERS must be at least 1".into());
+        }
+        let batch_max = env_parse("COLORIZER_BATCH_MAX", 32)?;
+        if batch_max == 0 {
+            return Err("COLORIZER_BATCH_MAX must be at least 1".into());
+        }
+
+        let max_body_bytes = env_parse("COLORIZER_MAX_BODY_BYTES", 64 * 1024)?;
+        if max_body_bytes == 0 {
+            return Err("COLORIZER_MAX_BODY_BYTES must be at least 1".into());
+        }
+
+        let antonym_penalty: f32 = env_parse("COLORIZER_ANTONYM_PENALTY", 0.1)?;
+        if !(antonym_penalty >= 0.0 && antonym_penalty.is_finite()) {
+            return Err("COLORIZER_ANTONYM_PENALTY must be a non-negative number".into());
+        }
+        let unk_threshold = env_parse("COLORIZER_UNK_THRESHOLD", 0.5)?;
+        if !(0.0..=1.0).contains(&unk_threshold) {
+            return Err("COLORIZER_UNK_THRESHOLD must be between 0 and 1".into());
+        }
+
+        Ok(Config {
+            models,
+            default_model,
+            models_file,
+            bind_addr: env_or("COLORIZER_BIND_ADDR", "0.0.0.0"),
+            port: env_parse("COLORIZER_PORT", 8090)?,
+            embed_cache_size: env_parse("COLORIZER_EMBED_CACHE_SIZE", 1024)?,
+            allowed_origins: env_list("COLORIZER_ALLOWED_ORIGINS"),
+            ann: env_flag("COLORIZER_ANN"),
+            ann_probes: env_parse("COLORIZER_ANN_PROBES", 4)?,
+            antonyms_path: env_opt("COLORIZER_ANTONYMS_PATH").map(PathBuf::from),
+            antonym_penalty,
+            metric: env_parse("COLORIZER_METRIC", SimilarityMetric::default())?,
+            ref_conflict: env_parse("COLORIZER_REF_CONFLICT", ConflictPolicy::default())?,
+            fallback_color: fallback_color_from_env()?,
+            allow_empty_refs: env_flag("COLORIZER_ALLOW_EMPTY_REFS"),
+            log_text: env_flag("COLORIZER_LOG_TEXT"),
+            strict_english: strict_lang_from_env()?,
+            expose_embeddings: env_flag("COLORIZER_EXPOSE_EMBEDDINGS"),
+            debug: env_flag("COLORIZER_DEBUG"),
+            rate_limit: rate_limit_from_env()?,
+            daily_quota: env_parse("COLORIZER_DAILY_QUOTA", 0)?,
+            api_key: env_opt("COLORIZER_API_KEY"),
+            max_body_bytes,
+            min_tokens: env_parse("COLORIZER_MIN_TOKENS", 1)?,
+            unk_threshold,
+            inference_timeout_ms: env_parse("COLORIZER_INFERENCE_TIMEOUT_MS", 10_000)?,
+            shutdown_timeout_secs: env_parse("COLORIZER_SHUTDOWN_TIMEOUT_SECS", 30)?,
+            load_attempts: env_parse("COLORIZER_LOAD_ATTEMPTS", NonZeroUsize::new(3).unwrap())?,
+            load_retry_ms: env_parse("COLORIZER_LOAD_RETRY_MS", 1000)?,
+            skip_warmup: env_flag("COLORIZER_SKIP_WARMUP"),
+            mock: env_flag("COLORIZER_MOCK"),
+            workers,
+            session_pool: env_parse("COLORIZER_SESSION_POOL", NonZeroUsize::MIN)?,
+            batch_window_ms: env_parse("COLORIZER_BATCH_WINDOW_MS", 0)?,
+            batch_max,
+            idempotency_ttl_secs: env_parse("COLORIZER_IDEMPOTENCY_TTL_SECS", 86_400)?,
+            audit_log: env_opt("COLORIZER_AUDIT_LOG").map(PathBuf::from),
+            audit_log_max_bytes: env_parse("COLORIZER_AUDIT_LOG_MAX_BYTES", 10 * 1024 * 1024)?,
+            audit_log_keep: env_parse("COLORIZER_AUDIT_LOG_KEEP", 5)?,
+            cache_max_age_secs: env_parse("COLORIZER_CACHE_MAX_AGE_SECS", 3600)?,
+            keep_alive_secs: env_parse("COLORIZER_KEEPALIVE_SECS", 5)?,
+            embedding: EmbeddingOptions::from_env()?,
+            provider: Provider::from_env()?,
+        })
+    }
+
+    /// The defaults `from_env` falls back to, ignoring the environment and any config file, so tests
+    /// don't change behavior with the shell they run in
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        let spec = ModelSpec {
+            tokenizer: "models/tokenizer.json".into(),
+            model: "models/model.onnx".into(),
+            ref_embeddings: "custom/ref_embeddings.json".into(),
+        };
+        Config {
+            models: BTreeMap::from([(DEFAULT_MODEL_NAME.to_string(), spec)]),
+            default_model: DEFAULT_MODEL_NAME.to_string(),
+            models_file: None,
+            bind_addr: "0.0.0.0".to_string(),
+            port: 8090,
+            embed_cache_size: 1024,
+            allowed_origins: Vec::new(),
+            ann: false,
+            ann_probes: 4,
+            antonyms_path: None,
+            antonym_penalty: 0.1,
+            metric: SimilarityMetric::default(),
+            ref_conflict: ConflictPolicy::default(),
+            fallback_color: None,
+            allow_empty_refs: false,
+            log_text: false,
+            strict_english: false,
+            expose_embeddings: false,
+            debug: false,
+            rate_limit: Some(RateLimit { milliseconds_per_request: 200, burst: 10 }),
+            daily_quota: 0,
+            api_key: None,
+            max_body_bytes: 64 * 1024,
+            min_tokens: 1,
+            unk_threshold: 0.5,
+            inference_timeout_ms: 10_000,
+            shutdown_timeout_secs: 30,
+            load_attempts: NonZeroUsize::new(3).unwrap(),
+            load_retry_ms: 1000,
+            skip_warmup: false,
+            mock: false,
+            workers: 1,
+            session_pool: NonZeroUsize::MIN,
+            batch_window_ms: 0,
+            batch_max: 32,
+            idempotency_ttl_secs: 86_400,
+            audit_log: None,
+            audit_log_max_bytes: 10 * 1024 * 1024,
+            audit_log_keep: 5,
+            cache_max_age_secs: 3600,
+            keep_alive_secs: 5,
+            embedding: EmbeddingOptions::default(),
+            provider: Provider::default(),
+        }
+    }
+
+    /// Fail with a readable error if any configured input file is missing, including each file of
+    /// a reference list or directory; in mock mode only the reference embeddings are needed
+    pub fn check_files(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        for (name, spec) in &self.models {
+            let ref_files = resolve_paths(&spec.ref_embeddings)?;
+            let model_files = [
+                ("COLORIZER_TOKENIZER_PATH", &spec.tokenizer),
+                ("COLORIZER_MODEL_PATH", &spec.model),
+            ];
+            let model_files = model_files.into_iter().filter(|_| !self.mock);
+            let ref_files = ref_files.iter().map(|path| ("COLORIZER_REF_EMBEDDINGS_PATH", path));
+            for (var, path) in model_files.chain(ref_files) {
+                if path.is_file() {
+                    continue;
+                }
+                let hint = match &self.models_file {
+                    Some(file) => format!("model {:?} in {}", name, file.display()),
+                    None => format!("set {} to override", var),
+                };
+                return Err(format!("file not found: {} ({})", path.display(), hint).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Read `COLORIZER_FALLBACK_COLOR` as `r,g,b`, defaulting to black
+fn fallback_color_from_env() -> Result<Option<RgbColor>, Box<dyn Error + Send + Sync>> {
+    env_opt("COLORIZER_FALLBACK_COLOR").map(|value| parse_fallback_color(&value)).transpose()
+}
+
+/// Parse a fallback color written as `r,g,b`
+fn parse_fallback_color(value: &str) -> Result<RgbColor, Box<dyn Error + Send + Sync>> {
+    let invalid = || format!("invalid COLORIZER_FALLBACK_COLOR {:?} (expected r,g,b with each in 0-255)", value);
+    let channels = value
+        .split(',')
+        .map(|c| c.trim().parse::<u8>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid())?;
+    match channels[..] {
+        [r, g, b] => Ok((r, g, b)),
+        _ => Err(invalid().into()),
+    }
+}
+
+/// Read `COLORIZER_RATE_MS` and `COLORIZER_RATE_BURST`, unless `COLORIZER_RATE_DISABLED` is set
+fn rate_limit_from_env() -> Result<Option<RateLimit>, Box<dyn Error + Send + Sync>> {
+    if env_flag("COLORIZER_RATE_DISABLED") {
+        return Ok(None);
+    }
+    let rate_limit = RateLimit {
+        milliseconds_per_request: env_parse("COLORIZER_RATE_MS", 200)?,
+        burst: env_parse("COLORIZER_RATE_BURST", 10)?,
+    };
+    if rate_limit.milliseconds_per_request == 0 || rate_limit.burst == 0 {
+        return Err("COLORIZER_RATE_MS and COLORIZER_RATE_BURST must be at least 1".into());
+    }
+    Ok(Some(rate_limit))
+}
+
+/// Read `COLORIZER_STRICT_LANG`; only `en` is supported
+fn strict_lang_from_env() -> Result<bool, Box<dyn Error + Send + Sync>> {
+    match env_opt("COLORIZER_STRICT_LANG") {
+        None => Ok(false),
+        Some(lang) if lang.trim().eq_ignore_ascii_case("en") => Ok(true),
+        Some(lang) => Err(format!("unsupported COLORIZER_STRICT_LANG {:?} (only \"en\" is supported)", lang).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fallback_color_must_be_three_channels() {
+        assert_eq!(parse_fallback_color("10,20,30").unwrap(), (10, 20, 30));
+        assert_eq!(parse_fallback_color(" 255, 0 ,128 ").unwrap(), (255, 0, 128));
+        for invalid in ["", "10,20", "10,20,30,40", "256,0,0", "-1,0,0", "red"] {
+            let error = parse_fallback_color(invalid).unwrap_err().to_string();
+            assert!(error.starts_with("invalid COLORIZER_FALLBACK_COLOR"), "{invalid:?}: {error}");
+        }
+    }
+}
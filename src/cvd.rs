@@ -0,0 +1,78 @@
+//! Color vision deficiency simulation (`?cvd=`), to preview how a color appears to color-blind users.
+//!
+//! Uses the severity-1.0 matrices of Machado, Oliveira & Fernandes (2009), applied in linear light.
+
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::color_space::{linear_to_srgb, srgb_to_linear};
+
+/// Which deficiency to simulate
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Cvd {
+    /// Leave the color unchanged
+    #[default]
+    None,
+    /// No working long-wavelength (red) cones
+    Protanopia,
+    /// No working medium-wavelength (green) cones
+    Deuteranopia,
+    /// No working short-wavelength (blue) cones
+    Tritanopia,
+}
+
+const PROTANOPIA: [[f32; 3]; 3] = [
+    [0.152286, 1.052583, -0.204868],
+    [0.114503, 0.786281, 0.099216],
+    [-0.003882, -0.048116, 1.051998],
+];
+
+const DEUTERANOPIA: [[f32; 3]; 3] = [
+    [0.367322, 0.860646, -0.227968],
+    [0.280085, 0.672501, 0.047413],
+    [-0.011820, 0.042940, 0.968881],
+];
+
+const TRITANOPIA: [[f32; 3]; 3] = [
+    [1.255528, -0.076749, -0.178779],
+    [-0.078411, 0.930809, 0.147602],
+    [0.004733, 0.691367, 0.303900],
+];
+
+/// How `rgb` appears with the given deficiency
+pub fn simulate(rgb: (u8, u8, u8), cvd: Cvd) -> (u8, u8, u8) {
+    let matrix = match cvd {
+        Cvd::None => return rgb,
+        Cvd::Protanopia => &PROTANOPIA,
+        Cvd::Deuteranopia => &DEUTERANOPIA,
+        Cvd::Tritanopia => &TRITANOPIA,
+    };
+    let linear = [srgb_to_linear(rgb.0), srgb_to_linear(rgb.1), srgb_to_linear(rgb.2)];
+    let channel = |row: &[f32; 3]| linear_to_srgb(row.iter().zip(linear).map(|(m, c)| m * c).sum());
+    (channel(&matrix[0]), channel(&matrix[1]), channel(&matrix[2]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pure_red_matches_the_published_simulations() {
+        let red = (255, 0, 0);
+        assert_eq!(simulate(red, Cvd::None), red);
+        assert_eq!(simulate(red, Cvd::Protanopia), (0x6d, 0x5f, 0x00));
+        assert_eq!(simulate(red, Cvd::Deuteranopia), (0xa3, 0x90, 0x00));
+        assert_eq!(simulate(red, Cvd::Tritanopia), (0xff, 0x00, 0x0f));
+    }
+
+    #[test]
+    fn neutral_colors_are_left_alone() {
+        // Each matrix row sums to 1, so grays map to themselves
+        for cvd in [Cvd::Protanopia, Cvd::Deuteranopia, Cvd::Tritanopia] {
+            for gray in [(0, 0, 0), (128, 128, 128), (255, 255, 255)] {
+                assert_eq!(simulate(gray, cvd), gray);
+            }
+        }
+    }
+}
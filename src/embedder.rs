@@ -0,0 +1,261 @@
+//! Embedding backends: the tokenizer + ONNX pipeline, or a mock for testing the HTTP layer.
+
+use colorizer::{
+    embedding::{get_embedding, get_embeddings, EmbeddingOptions},
+    error::ColorizerError,
+    reference::content_hash,
+    session::SessionPool,
+};
+use ort::environment::Environment;
+use ort::session::Session;
+use rand_pcg::{
+    rand_core::{RngCore, SeedableRng},
+    Pcg32,
+};
+use serde::Serialize;
+use std::{error::Error, sync::Arc};
+use tokenizers::Tokenizer;
+use utoipa::ToSchema;
+
+use crate::config::{Config, ModelSpec};
+
+/// Dimension of mock embeddings when there are no references to match
+const DEFAULT_MOCK_DIMENSION: usize = 384;
+
+/// Unknown-word tokens counted for the out-of-vocabulary ratio: BERT, then RoBERTa/SentencePiece
+const UNK_TOKENS: [&str; 2] = ["[UNK]", "<unk>"];
+
+/// Tokens a text encodes to, not counting special tokens like `[CLS]`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokenCounts {
+    pub real: usize,
+    /// Real tokens the vocabulary doesn't know, encoded as `[UNK]`
+    pub unknown: usize,
+}
+
+impl TokenCounts {
+    /// Fraction of real tokens that are unknown; 0 for a text without any
+    pub fn unknown_ratio(&self) -> f32 {
+        if self.real == 0 {
+            0.0
+        } else {
+            self.unknown as f32 / self.real as f32
+        }
+    }
+}
+
+/// Name, shape and element type of one model input or output; `null` dimensions are dynamic
+#[derive(Serialize, ToSchema)]
+pub struct TensorInfo {
+    pub name: String,
+    pub shape: Vec<Option<usize>>,
+    #[serde(rename = "type")]
+    pub element_type: String,
+}
+
+/// What an ONNX model declares it takes and returns, for `GET /model-info`
+#[derive(Serialize, ToSchema)]
+pub struct ModelIo {
+    pub inputs: Vec<TensorInfo>,
+    pub outputs: Vec<TensorInfo>,
+}
+
+impl ModelIo {
+    fn of(session: &Session) -> Self {
+        ModelIo {
+            inputs: session
+                .inputs
+                .iter()
+                .map(|input| TensorInfo {
+                    name: input.name.clone(),
+                    shape: input.dimensions().collect(),
+                    element_type: format!("{:?}", input.input_type),
+                })
+                .collect(),
+            outputs: session
+                .outputs
+                .iter()
+                .map(|output| TensorInfo {
+                    name: output.name.clone(),
+                    shape: output.dimensions().collect(),
+                    element_type: format!("{:?}", output.output_type),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Turns text into a sentence embedding.
+///
+/// Each model holds one as `Arc<dyn Embedder + Send + Sync>`, shared with its batcher, so
+/// handlers don't depend on how embeddings are produced.
+pub trait Embedder {
+    /// Embed one sentence
+    fn embed(&self, text: &str) -> Result<Vec<f32>, ColorizerError>;
+
+    /// Embed several sentences at once, in order. Backends that can't batch embed them one by one.
+    fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, ColorizerError> {
+        texts.iter().map(|text| self.embed(text)).collect()
+    }
+
+    /// Real and unknown tokens in `text`. Backends without a tokenizer count whitespace-separated
+    /// words instead, none of them unknown.
+    fn count_tokens(&self, text: &str) -> Result<TokenCounts, ColorizerError> {
+        Ok(TokenCounts {
+            real: text.split_whitespace().count(),
+            unknown: 0,
+        })
+    }
+
+    /// The model's declared inputs and outputs; `None` for backends without an ONNX model
+    fn io(&self) -> Option<ModelIo> {
+        None
+    }
+
+    /// Embed `text` `runs` times and discard the results, so lazy allocations happen up front
+    fn warm_up(&self, text: &str, runs: usize) -> Result<(), ColorizerError> {
+        for _ in 0..runs {
+            self.embed(text)?;
+        }
+        Ok(())
+    }
+}
+
+/// A tokenizer and a pool of ONNX sessions for one model
+pub struct OnnxEmbedder {
+    tokenizer: Tokenizer,
+    sessions: SessionPool,
+    options: EmbeddingOptions,
+}
+
+impl OnnxEmbedder {
+    /// Load the tokenizer and ONNX sessions for one model
+    pub fn load(
+        environment: &Arc<Environment>,
+        spec: &ModelSpec,
+        config: &Config,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut tokenizer = Tokenizer::from_file(&spec.tokenizer)?;
+        config.embedding.apply_to(&mut tokenizer)?;
+        let sessions = SessionPool::build(environment, &spec.model, config.provider, config.session_pool)?;
+        if sessions.size() > 1 {
+            println!("  • sessions:       {}", sessions.size());
+        }
+
+        Ok(OnnxEmbedder {
+            tokenizer,
+            sessions,
+            options: config.embedding.clone(),
+        })
+    }
+}
+
+impl Embedder for OnnxEmbedder {
+    fn embed(&self, text: &str) -> Result<Vec<f32>, ColorizerError> {
+        get_embedding(&self.tokenizer, self.sessions.get(), &self.options, text)
+    }
+
+    fn count_tokens(&self, text: &str) -> Result<TokenCounts, ColorizerError> {
+        let encoding = self
+            .tokenizer
+            .encode(self.options.normalize_text(text), true)
+            .map_err(|e| ColorizerError::Tokenize(e.to_string()))?;
+        let unk_id = UNK_TOKENS.iter().find_map(|token| self.tokenizer.token_to_id(token));
+        let real = encoding
+            .get_ids()
+            .iter()
+            .zip(encoding.get_special_tokens_mask())
+            .filter(|&(_, &special)| special == 0);
+        Ok(real.fold(TokenCounts::default(), |counts, (&id, _)| TokenCounts {
+            real: counts.real + 1,
+            unknown: counts.unknown + usize::from(Some(id) == unk_id),
+        }))
+    }
+
+    fn io(&self) -> Option<ModelIo> {
+        Some(ModelIo::of(self.sessions.get()))
+    }
+
+    /// One padded ONNX run for the whole batch
+    fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, ColorizerError> {
+        get_embeddings(&self.tokenizer, self.sessions.get(), &self.options, texts)
+    }
+
+    /// Warm every session in the pool, not just the next one in turn
+    fn warm_up(&self, text: &str, runs: usize) -> Result<(), ColorizerError> {
+        for session in self.sessions.all() {
+            for _ in 0..runs {
+                get_embedding(&self.tokenizer, session, &self.options, text)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Pseudo-random embeddings seeded by a hash of the text (`COLORIZER_MOCK=1`).
+///
+/// The same text always gets the same embedding, and so the same nearest color, but similar texts
+/// don't get similar colors.
+pub struct MockEmbedder {
+    dimension: usize,
+}
+
+impl MockEmbedder {
+    /// Produce embeddings of `dimension` values, or [`DEFAULT_MOCK_DIMENSION`] if `None`
+    pub fn new(dimension: Option<usize>) -> Self {
+        MockEmbedder {
+            dimension: dimension.unwrap_or(DEFAULT_MOCK_DIMENSION),
+        }
+    }
+}
+
+impl Embedder for MockEmbedder {
+    fn embed(&self, text: &str) -> Result<Vec<f32>, ColorizerError> {
+        let mut rng = Pcg32::seed_from_u64(content_hash(text.as_bytes()));
+        // Uniform in [-1, 1]
+        Ok((0..self.dimension)
+            .map(|_| rng.next_u32() as f32 / u32::MAX as f32 * 2.0 - 1.0)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Embeds each text as `[length, 1]`, remembering what it was asked
+    #[derive(Default)]
+    struct LengthEmbedder {
+        seen: Mutex<Vec<String>>,
+    }
+
+    impl Embedder for LengthEmbedder {
+        fn embed(&self, text: &str) -> Result<Vec<f32>, ColorizerError> {
+            self.seen.lock().unwrap().push(text.to_string());
+            Ok(vec![text.len() as f32, 1.0])
+        }
+    }
+
+    #[test]
+    fn a_custom_embedder_only_needs_embed() {
+        let embedder = Arc::new(LengthEmbedder::default());
+        let backend: Arc<dyn Embedder + Send + Sync> = embedder.clone();
+
+        assert_eq!(backend.embed_batch(&["a", "abc"]).unwrap(), vec![vec![1.0, 1.0], vec![3.0, 1.0]]);
+        let counts = backend.count_tokens("two  words").unwrap();
+        assert_eq!((counts.real, counts.unknown), (2, 0));
+        assert!(backend.io().is_none());
+        backend.warm_up("warm", 2).unwrap();
+        assert_eq!(*embedder.seen.lock().unwrap(), ["a", "abc", "warm", "warm"]);
+    }
+
+    #[test]
+    fn mock_embeddings_are_deterministic_per_text() {
+        let mock = MockEmbedder::new(Some(8));
+        assert_eq!(mock.embed("ocean").unwrap(), mock.embed("ocean").unwrap());
+        assert_ne!(mock.embed("ocean").unwrap(), mock.embed("fire").unwrap());
+        assert_eq!(mock.embed("ocean").unwrap().len(), 8);
+        assert_eq!(MockEmbedder::new(None).embed("ocean").unwrap().len(), DEFAULT_MOCK_DIMENSION);
+    }
+}
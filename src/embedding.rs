@@ -0,0 +1,793 @@
+use crate::{
+    env::{env_flag, env_opt, env_parse},
+    error::ColorizerError,
+};
+use ndarray::{Array2, ArrayView1, ArrayView2, ArrayViewD, Axis, CowArray, Ix2, ShapeError};
+use ort::{session::Session, tensor::OrtOwnedTensor, value::Value, OrtError};
+use rust_stemmers::{Algorithm, Stemmer};
+use std::{collections::HashMap, error::Error, fmt, fs, iter, str::FromStr};
+use tokenizers::{Encoding, Tokenizer, TruncationParams};
+use unicode_normalization::UnicodeNormalization;
+
+/// Smallest accepted `COLORIZER_MAX_TOKENS`, leaving room for special tokens like `[CLS]`/`[SEP]`
+const MIN_MAX_TOKENS: usize = 4;
+
+/// Classification tokens looked up for CLS pooling, in order of preference: BERT, RoBERTa, XLNet
+const CLS_TOKENS: [&str; 3] = ["[CLS]", "<s>", "<cls>"];
+
+/// Which tokenizer output feeds one of the model's inputs, matched by the input's name so models
+/// exported with fewer inputs, or in a different order, still run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ModelInput {
+    InputIds,
+    AttentionMask,
+    TokenTypeIds,
+}
+
+impl ModelInput {
+    /// Recognize `input_ids`, `attention_mask` and `token_type_ids`, plus a few names exporters
+    /// are known to use for them. Anything else, like `position_ids`, isn't guessed at.
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            // `input.1` is what `torch.onnx.export` calls an unnamed first input
+            "input_ids" | "input.1" => Some(ModelInput::InputIds),
+            // TensorFlow BERT's names
+            "attention_mask" | "input_mask" => Some(ModelInput::AttentionMask),
+            "token_type_ids" | "segment_ids" => Some(ModelInput::TokenTypeIds),
+            _ => None,
+        }
+    }
+
+    /// What to feed each of the inputs `names`, in the model's order
+    fn for_inputs<'a>(names: impl IntoIterator<Item = &'a str>) -> Result<Vec<Self>, ColorizerError> {
+        names
+            .into_iter()
+            .map(|name| {
+                ModelInput::from_name(name).ok_or_else(|| {
+                    ColorizerError::Inference(format!(
+                        "unsupported model input {:?} (expected input_ids, attention_mask or token_type_ids)",
+                        name
+                    ))
+                })
+            })
+            .collect()
+    }
+}
+
+/// How token embeddings are reduced to a single sentence embedding.
+///
+/// The server and `generate_ref_embeddings` must use the same strategy (`COLORIZER_POOLING`),
+/// or the reference embeddings won't be comparable to request embeddings.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PoolingStrategy {
+    /// Average of the real (unmasked) token embeddings
+    #[default]
+    Mean,
+    /// Embedding of the classification token (`[CLS]`), wherever the tokenizer places it
+    Cls,
+    /// Element-wise maximum over the real token embeddings
+    Max,
+}
+
+impl FromStr for PoolingStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "mean" => Ok(PoolingStrategy::Mean),
+            "cls" => Ok(PoolingStrategy::Cls),
+            "max" => Ok(PoolingStrategy::Max),
+            other => Err(format!("unknown pooling strategy {:?} (expected mean, cls or max)", other)),
+        }
+    }
+}
+
+impl fmt::Display for PoolingStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PoolingStrategy::Mean => "mean",
+            PoolingStrategy::Cls => "cls",
+            PoolingStrategy::Max => "max",
+        })
+    }
+}
+
+/// Settings that change how text is embedded; read identically by both binaries
+#[derive(Clone, Debug)]
+pub struct EmbeddingOptions {
+    pub pooling: PoolingStrategy,
+    /// Inputs are truncated to this many tokens (including special tokens)
+    pub max_tokens: usize,
+    /// Lowercase inputs after NFC normalization, so casing doesn't change the color
+    pub lowercase: bool,
+    /// Words rewritten to a canonical spelling before embedding, keyed by lowercased alias
+    pub aliases: HashMap<String, String>,
+    /// Token position used by CLS pooling; `None` finds the tokenizer's classification token
+    pub cls_index: Option<usize>,
+    /// Leave special tokens like `[CLS]`/`[SEP]` out of mean and max pooling
+    pub exclude_special: bool,
+    /// Reduce English words to their stem, so "running" and "runs" embed like "run"
+    pub stem: bool,
+    /// L2-normalize each token vector before mean pooling, for models trained that way
+    pub pre_pool_norm: bool,
+    /// IDF weight of each token for mean pooling, keyed by token as the tokenizer spells it;
+    /// empty weighs every token the same
+    pub idf: HashMap<String, f32>,
+}
+
+impl Default for EmbeddingOptions {
+    fn default() -> Self {
+        EmbeddingOptions {
+            pooling: PoolingStrategy::default(),
+            max_tokens: 128,
+            lowercase: false,
+            aliases: HashMap::new(),
+            cls_index: None,
+            exclude_special: false,
+            stem: false,
+            pre_pool_norm: false,
+            idf: HashMap::new(),
+        }
+    }
+}
+
+impl EmbeddingOptions {
+    /// Read the options from `COLORIZER_*` environment variables
+    pub fn from_env() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let defaults = EmbeddingOptions::default();
+        let options = EmbeddingOptions {
+            pooling: env_parse("COLORIZER_POOLING", defaults.pooling)?,
+            max_tokens: env_parse("COLORIZER_MAX_TOKENS", defaults.max_tokens)?,
+            lowercase: env_flag("COLORIZER_LOWERCASE"),
+            aliases: match env_opt("COLORIZER_ALIASES_PATH") {
+                Some(path) => load_aliases(&path).map_err(|e| format!("COLORIZER_ALIASES_PATH {}: {}", path, e))?,
+                None => defaults.aliases,
+            },
+            cls_index: env_opt("COLORIZER_CLS_INDEX")
+                .map(|value| value.trim().parse::<usize>())
+                .transpose()
+                .map_err(|e| format!("invalid value for COLORIZER_CLS_INDEX ({})", e))?,
+            exclude_special: env_flag("COLORIZER_EXCLUDE_SPECIAL"),
+            stem: env_flag("COLORIZER_STEM"),
+            pre_pool_norm: env_flag("COLORIZER_PRE_POOL_NORM"),
+            idf: match env_opt("COLORIZER_IDF_PATH") {
+                Some(path) => load_idf(&path).map_err(|e| format!("COLORIZER_IDF_PATH {}: {}", path, e))?,
+                None => defaults.idf,
+            },
+        };
+
+        if options.max_tokens < MIN_MAX_TOKENS {
+            return Err(format!("COLORIZER_MAX_TOKENS must be at least {}", MIN_MAX_TOKENS).into());
+        }
+        if options.cls_index.is_some_and(|index| index >= options.max_tokens) {
+            return Err("COLORIZER_CLS_INDEX must be less than COLORIZER_MAX_TOKENS".into());
+        }
+        Ok(options)
+    }
+
+    /// Configure the tokenizer to match these options (truncation to `max_tokens`)
+    pub fn apply_to(&self, tokenizer: &mut Tokenizer) -> Result<(), Box<dyn Error + Send + Sync>> {
+        tokenizer.with_truncation(Some(TruncationParams {
+            max_length: self.max_tokens,
+            ..Default::default()
+        }))?;
+        Ok(())
+    }
+
+    /// Normalize text before tokenization: Unicode NFC, so composed and decomposed accents embed
+    /// the same, then lowercasing if enabled, then alias rewriting, then stemming if enabled
+    pub fn normalize_text(&self, text: &str) -> String {
+        let text: String = text.nfc().collect();
+        let text = if self.lowercase { text.to_lowercase() } else { text };
+        let text = if self.aliases.is_empty() {
+            text
+        } else {
+            map_words(&text, |word| self.aliases.get(&word.to_lowercase()).cloned())
+        };
+        if self.stem {
+            let stemmer = Stemmer::create(Algorithm::English);
+            map_words(&text, |word| {
+                let lower = word.to_lowercase();
+                let stem = stemmer.stem(&lower);
+                // Words the stemmer leaves alone keep their casing
+                (stem != lower).then(|| stem.into_owned())
+            })
+        } else {
+            text
+        }
+    }
+}
+
+/// Replace each word for which `replace` returns `Some`, leaving spacing and punctuation as they are
+fn map_words(text: &str, replace: impl Fn(&str) -> Option<String>) -> String {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '\'';
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(is_word_char) {
+        out.push_str(&rest[..start]);
+        let len = rest[start..].find(|c| !is_word_char(c)).unwrap_or(rest.len() - start);
+        let word = &rest[start..start + len];
+        match replace(word) {
+            Some(replacement) => out.push_str(&replacement),
+            None => out.push_str(word),
+        }
+        rest = &rest[start + len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Read an alias map like `{ "luv": "love", "colour": "color" }`; aliases are matched case-insensitively
+fn load_aliases(path: &str) -> Result<HashMap<String, String>, Box<dyn Error + Send + Sync>> {
+    let aliases: HashMap<String, String> = serde_json::from_slice(&fs::read(path)?)?;
+    Ok(aliases.into_iter().map(|(alias, canonical)| (alias.to_lowercase(), canonical)).collect())
+}
+
+/// Read an IDF table like `{ "the": 1.2, "crimson": 9.7 }`; every weight must be positive
+fn load_idf(path: &str) -> Result<HashMap<String, f32>, Box<dyn Error + Send + Sync>> {
+    let idf: HashMap<String, f32> = serde_json::from_slice(&fs::read(path)?)?;
+    if let Some((token, weight)) = idf.iter().find(|(_, &weight)| !(weight.is_finite() && weight > 0.0)) {
+        return Err(format!("weight of {:?} must be a positive number, got {}", token, weight).into());
+    }
+    Ok(idf)
+}
+
+/// L2-normalize an embedding in place. Returns `false` if it has zero norm and was left untouched.
+pub fn l2_normalize(v: &mut [f32]) -> bool {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        return false;
+    }
+    v.iter_mut().for_each(|x| *x /= norm);
+    true
+}
+
+/// Copy of `(seq_len, hidden)` token embeddings with each token vector L2-normalized; zero vectors
+/// are left as they are
+pub fn l2_normalize_rows(token_embeddings: ArrayView2<f32>) -> Array2<f32> {
+    let mut normalized = token_embeddings.to_owned();
+    for mut row in normalized.outer_iter_mut() {
+        if let Some(row) = row.as_slice_mut() {
+            l2_normalize(row);
+        }
+    }
+    normalized
+}
+
+/// Mean-pool token embeddings, weighting each token by its attention mask value
+pub fn masked_mean_pool(token_embeddings: ArrayView2<f32>, attention_mask: &[f32]) -> Vec<f32> {
+    let mask = ArrayView1::from(attention_mask).insert_axis(Axis(1));
+    let summed = (&token_embeddings * &mask).sum_axis(Axis(0));
+    let mask_sum: f32 = attention_mask.iter().sum();
+
+    if mask_sum == 0.0 {
+        summed.into_raw_vec()
+    } else {
+        (summed / mask_sum).into_raw_vec()
+    }
+}
+
+/// Element-wise maximum over the tokens whose attention mask is set
+pub fn masked_max_pool(token_embeddings: ArrayView2<f32>, attention_mask: &[f32]) -> Vec<f32> {
+    let mut pooled: Option<Vec<f32>> = None;
+    for (row, &m) in token_embeddings.outer_iter().zip(attention_mask) {
+        if m == 0.0 {
+            continue;
+        }
+        match &mut pooled {
+            Some(max) => max.iter_mut().zip(row).for_each(|(acc, &x)| *acc = acc.max(x)),
+            None => pooled = Some(row.to_vec()),
+        }
+    }
+
+    pooled.unwrap_or_else(|| vec![0.0; token_embeddings.ncols()])
+}
+
+/// Reduce `(seq_len, hidden)` token embeddings to one vector with the given strategy; CLS pooling
+/// takes the token at `cls_position`
+pub fn pool(
+    token_embeddings: ArrayView2<f32>,
+    attention_mask: &[f32],
+    strategy: PoolingStrategy,
+    cls_position: usize,
+) -> Vec<f32> {
+    match strategy {
+        PoolingStrategy::Mean => masked_mean_pool(token_embeddings, attention_mask),
+        PoolingStrategy::Cls => token_embeddings.row(cls_position).to_vec(),
+        PoolingStrategy::Max => masked_max_pool(token_embeddings, attention_mask),
+    }
+}
+
+/// Weights of each token in mean and max pooling: the attention mask, with special tokens zeroed
+/// when `exclude_special` is set. An input made only of special tokens keeps its attention mask,
+/// so it still pools to something. With an IDF table, mean pooling also scales each real token by
+/// its IDF weight; tokens missing from the table weigh 1.
+fn pooling_mask(encoding: &Encoding, options: &EmbeddingOptions) -> Vec<f32> {
+    let mask = encoding.get_attention_mask();
+    let special = encoding.get_special_tokens_mask();
+    let mut weights: Vec<f32> =
+        if options.exclude_special && mask.iter().zip(special).any(|(&m, &s)| m == 1 && s == 0) {
+            mask.iter().zip(special).map(|(&m, &s)| if s == 1 { 0.0 } else { m as f32 }).collect()
+        } else {
+            mask.iter().map(|&m| m as f32).collect()
+        };
+    if !options.idf.is_empty() && options.pooling == PoolingStrategy::Mean {
+        for ((weight, token), &s) in weights.iter_mut().zip(encoding.get_tokens()).zip(special) {
+            if s == 0 {
+                *weight *= options.idf.get(token).copied().unwrap_or(1.0);
+            }
+        }
+    }
+    weights
+}
+
+/// Id of the tokenizer's classification token, the first of [`CLS_TOKENS`] it knows
+fn cls_token_id(tokenizer: &Tokenizer) -> Option<u32> {
+    CLS_TOKENS.iter().find_map(|token| tokenizer.token_to_id(token))
+}
+
+/// Position CLS pooling reads in one encoding: `COLORIZER_CLS_INDEX` if set, else the first
+/// special token matching `cls_id`, else 0
+fn cls_position(encoding: &Encoding, cls_id: Option<u32>, options: &EmbeddingOptions) -> Result<usize, ColorizerError> {
+    if let Some(index) = options.cls_index {
+        // The text is too short for the configured position, not a fault of the pipeline
+        if index >= encoding.len() {
+            return Err(ColorizerError::TooFewTokens {
+                found: encoding.len(),
+                min: index + 1,
+            });
+        }
+        return Ok(index);
+    }
+    let position = cls_id.and_then(|id| {
+        encoding
+            .get_ids()
+            .iter()
+            .zip(encoding.get_special_tokens_mask())
+            .position(|(&token, &special)| token == id && special == 1)
+    });
+    Ok(position.unwrap_or(0))
+}
+
+/// Generate an embedding for a sentence using the tokenizer + ONNX model.
+///
+/// The sentence is normalized first (see [`EmbeddingOptions::normalize_text`]).
+/// Fails with [`ColorizerError::NonFiniteEmbedding`] if the pooled embedding contains NaN or
+/// infinity.
+pub fn get_embedding(
+    tokenizer: &Tokenizer,
+    session: &Session,
+    options: &EmbeddingOptions,
+    sentence: &str,
+) -> Result<Vec<f32>, ColorizerError> {
+    let mut embeddings = get_embeddings(tokenizer, session, options, &[sentence])?;
+    Ok(embeddings.remove(0))
+}
+
+/// Embed several sentences with a single ONNX run, returning one embedding per sentence in order.
+///
+/// Shorter sequences are padded to the longest with masked-out tokens, which pooling ignores, so
+/// each result matches [`get_embedding`] on that sentence alone up to floating-point rounding.
+/// Any failure, including one non-finite embedding, fails the whole batch.
+pub fn get_embeddings(
+    tokenizer: &Tokenizer,
+    session: &Session,
+    options: &EmbeddingOptions,
+    sentences: &[&str],
+) -> Result<Vec<Vec<f32>>, ColorizerError> {
+    if sentences.is_empty() {
+        return Ok(Vec::new());
+    }
+    let batch = encode_batch(tokenizer, options, sentences)?;
+
+    // Bind the views so they live long enough
+    let input_ids_cow = CowArray::from(batch.input_ids.view().into_dyn());
+    let attention_mask_cow = CowArray::from(batch.attention_mask.view().into_dyn());
+    let token_type_ids_cow = CowArray::from(batch.token_type_ids.view().into_dyn());
+
+    // Feed exactly the inputs the model declares, in its order
+    let inference_error = |e: OrtError| ColorizerError::Inference(e.to_string());
+    let inputs = ModelInput::for_inputs(session.inputs.iter().map(|input| input.name.as_str()))?
+        .into_iter()
+        .map(|input| {
+            let tensor = match input {
+                ModelInput::InputIds => &input_ids_cow,
+                ModelInput::AttentionMask => &attention_mask_cow,
+                ModelInput::TokenTypeIds => &token_type_ids_cow,
+            };
+            Value::from_array(session.allocator(), tensor).map_err(inference_error)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let outputs = session.run(inputs).map_err(inference_error)?;
+    let tensor: OrtOwnedTensor<f32, _> = outputs[0].try_extract().map_err(inference_error)?;
+    pool_batch(tensor.view(), &batch, options)
+}
+
+/// Tokenized sentences padded to a common length, as `(size, seq_len)` model inputs
+struct EncodedBatch {
+    size: usize,
+    seq_len: usize,
+    input_ids: Array2<i64>,
+    attention_mask: Array2<i64>,
+    token_type_ids: Array2<i64>,
+    /// Per-token pooling weights from [`pooling_mask`], row-major and zero over the padding
+    pooling_mask: Vec<f32>,
+    /// Token CLS pooling reads in each sentence; all 0 for other strategies
+    cls_positions: Vec<usize>,
+}
+
+/// Normalize and tokenize `sentences`, padding each to the longest with `pad_id` and masked-out
+/// positions
+fn encode_batch(
+    tokenizer: &Tokenizer,
+    options: &EmbeddingOptions,
+    sentences: &[&str],
+) -> Result<EncodedBatch, ColorizerError> {
+    let normalized: Vec<String> = sentences.iter().map(|s| options.normalize_text(s)).collect();
+    let encodings = tokenizer
+        .encode_batch(normalized, true)
+        .map_err(|e| ColorizerError::Tokenize(e.to_string()))?;
+
+    let size = encodings.len();
+    let seq_len = encodings.iter().map(|e| e.len()).max().unwrap_or(0);
+    let cls_positions = match options.pooling {
+        PoolingStrategy::Cls => {
+            let cls_id = cls_token_id(tokenizer);
+            encodings
+                .iter()
+                .map(|encoding| cls_position(encoding, cls_id, options))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+        _ => vec![0; size],
+    };
+    let pad_id = tokenizer.get_padding().map_or(0, |p| p.pad_id as i64);
+    let mut input_ids: Vec<i64> = Vec::with_capacity(size * seq_len);
+    let mut attention_mask: Vec<i64> = Vec::with_capacity(size * seq_len);
+    let mut token_type_ids: Vec<i64> = Vec::with_capacity(size * seq_len);
+    // The model still attends to special tokens; only pooling may skip them
+    let mut mask: Vec<f32> = Vec::with_capacity(size * seq_len);
+    for encoding in &encodings {
+        let padding = seq_len - encoding.len();
+        input_ids.extend(encoding.get_ids().iter().map(|&id| id as i64));
+        input_ids.extend(iter::repeat_n(pad_id, padding));
+        attention_mask.extend(encoding.get_attention_mask().iter().map(|&m| m as i64));
+        attention_mask.extend(iter::repeat_n(0, padding));
+        token_type_ids.extend(encoding.get_type_ids().iter().map(|&t| t as i64));
+        token_type_ids.extend(iter::repeat_n(0, padding));
+        mask.extend(pooling_mask(encoding, options));
+        mask.extend(iter::repeat_n(0.0, padding));
+    }
+
+    let shape_error = |e: ShapeError| ColorizerError::ShapeMismatch(e.to_string());
+    Ok(EncodedBatch {
+        size,
+        seq_len,
+        input_ids: Array2::from_shape_vec((size, seq_len), input_ids).map_err(shape_error)?,
+        attention_mask: Array2::from_shape_vec((size, seq_len), attention_mask).map_err(shape_error)?,
+        token_type_ids: Array2::from_shape_vec((size, seq_len), token_type_ids).map_err(shape_error)?,
+        pooling_mask: mask,
+        cls_positions,
+    })
+}
+
+/// Pool the model's `(size, seq_len, hidden)` output into one embedding per sentence of `batch`
+fn pool_batch(
+    output: ArrayViewD<f32>,
+    batch: &EncodedBatch,
+    options: &EmbeddingOptions,
+) -> Result<Vec<Vec<f32>>, ColorizerError> {
+    if output.ndim() != 3 || output.shape()[0] != batch.size {
+        return Err(ColorizerError::ShapeMismatch(format!(
+            "expected [{}, tokens, dimension], got {:?}",
+            batch.size,
+            output.shape()
+        )));
+    }
+
+    let shape_error = |e: ShapeError| ColorizerError::ShapeMismatch(e.to_string());
+    let mut embeddings = Vec::with_capacity(batch.size);
+    for i in 0..batch.size {
+        let token_embeddings = output.index_axis(Axis(0), i).into_dimensionality::<Ix2>().map_err(shape_error)?;
+        let row_mask = &batch.pooling_mask[i * batch.seq_len..(i + 1) * batch.seq_len];
+        let normalized;
+        let token_embeddings = if options.pre_pool_norm && options.pooling == PoolingStrategy::Mean {
+            normalized = l2_normalize_rows(token_embeddings);
+            normalized.view()
+        } else {
+            token_embeddings
+        };
+        let embedding = pool(token_embeddings, row_mask, options.pooling, batch.cls_positions[i]);
+        if !embedding.iter().all(|x| x.is_finite()) {
+            return Err(ColorizerError::NonFiniteEmbedding);
+        }
+        embeddings.push(embedding);
+    }
+    Ok(embeddings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::{array, s, Array3};
+
+    /// A BERT-style word-level tokenizer over `words`, wrapping each input in `[CLS]` ... `[SEP]`
+    fn word_tokenizer(words: &[&str]) -> Tokenizer {
+        let bert = serde_json::json!({ "type": "BertProcessing", "sep": ["[SEP]", 2], "cls": ["[CLS]", 1] });
+        word_tokenizer_with(words, bert)
+    }
+
+    /// A word-level tokenizer over `words` that appends `[CLS]` to each input, as XLNet does
+    fn cls_last_tokenizer(words: &[&str]) -> Tokenizer {
+        let cls = serde_json::json!({ "SpecialToken": { "id": "[CLS]", "type_id": 0 } });
+        let sequence = |id: &str| serde_json::json!({ "Sequence": { "id": id, "type_id": 0 } });
+        let template = serde_json::json!({
+            "type": "TemplateProcessing",
+            "single": [sequence("A"), cls],
+            "pair": [sequence("A"), sequence("B"), cls],
+            "special_tokens": { "[CLS]": { "id": "[CLS]", "ids": [1], "tokens": ["[CLS]"] } }
+        });
+        word_tokenizer_with(words, template)
+    }
+
+    /// A word-level tokenizer over `words` with the given post-processor
+    fn word_tokenizer_with(words: &[&str], post_processor: serde_json::Value) -> Tokenizer {
+        let mut vocab = serde_json::json!({ "[UNK]": 0, "[CLS]": 1, "[SEP]": 2 });
+        for (id, word) in words.iter().enumerate() {
+            vocab[*word] = serde_json::json!(id + 3);
+        }
+        let special = |id: u32, content: &str| {
+            serde_json::json!({
+                "id": id, "content": content, "single_word": false, "lstrip": false,
+                "rstrip": false, "normalized": false, "special": true
+            })
+        };
+        let config = serde_json::json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [special(0, "[UNK]"), special(1, "[CLS]"), special(2, "[SEP]")],
+            "normalizer": null,
+            "pre_tokenizer": { "type": "Whitespace" },
+            "post_processor": post_processor,
+            "decoder": null,
+            "model": { "type": "WordLevel", "vocab": vocab, "unk_token": "[UNK]" }
+        });
+        config.to_string().parse().unwrap()
+    }
+
+    /// Stand-in for a model output where each token's vector depends only on its id, so padding a
+    /// sentence in a batch can only change its embedding if pooling reads the padded positions
+    fn lookup_model(batch: &EncodedBatch) -> Array3<f32> {
+        Array3::from_shape_fn((batch.size, batch.seq_len, 4), |(i, t, h)| {
+            ((batch.input_ids[[i, t]] + 1) as f32 * (h + 1) as f32).sin()
+        })
+    }
+
+    #[test]
+    fn batched_embeddings_match_each_sentence_embedded_alone() {
+        let tokenizer = word_tokenizer(&["red", "sky", "at", "night", "sea"]);
+        let sentences = ["red", "red sky at night", "sea sky", "sea"];
+        let variants = [
+            (PoolingStrategy::Mean, false, false),
+            (PoolingStrategy::Mean, true, true),
+            (PoolingStrategy::Max, true, false),
+            (PoolingStrategy::Cls, false, false),
+        ];
+        for (pooling, exclude_special, pre_pool_norm) in variants {
+            let options = EmbeddingOptions { pooling, exclude_special, pre_pool_norm, ..Default::default() };
+            let embed = |sentences: &[&str]| {
+                let batch = encode_batch(&tokenizer, &options, sentences).unwrap();
+                (batch.seq_len, pool_batch(lookup_model(&batch).view().into_dyn(), &batch, &options).unwrap())
+            };
+
+            let (seq_len, batched) = embed(&sentences);
+            assert_eq!(seq_len, 6);
+            for (sentence, embedding) in sentences.iter().zip(&batched) {
+                let (_, alone) = embed(&[sentence]);
+                let close = alone[0].iter().zip(embedding).all(|(a, b)| (a - b).abs() < 1e-6);
+                assert!(close, "{pooling} {sentence:?}: {:?} vs {embedding:?}", alone[0]);
+            }
+        }
+    }
+
+    #[test]
+    fn special_tokens_are_dropped_from_the_pool_when_excluded() {
+        let tokenizer = word_tokenizer(&["red", "sky"]);
+        let encoding = tokenizer.encode("red sky", true).unwrap();
+        assert_eq!(encoding.get_tokens(), ["[CLS]", "red", "sky", "[SEP]"]);
+
+        let kept = EmbeddingOptions::default();
+        let excluded = EmbeddingOptions { exclude_special: true, ..Default::default() };
+        assert_eq!(pooling_mask(&encoding, &kept), [1.0, 1.0, 1.0, 1.0]);
+        assert_eq!(pooling_mask(&encoding, &excluded), [0.0, 1.0, 1.0, 0.0]);
+
+        // The pooled vector is the mean of the two word rows alone
+        let batch = encode_batch(&tokenizer, &excluded, &["red sky"]).unwrap();
+        let output = lookup_model(&batch);
+        let pooled = pool_batch(output.view().into_dyn(), &batch, &excluded).unwrap();
+        let words = output.slice(s![0, 1..3, ..]).mean_axis(Axis(0)).unwrap();
+        assert!(pooled[0].iter().zip(&words).all(|(a, b)| (a - b).abs() < 1e-6), "{pooled:?} vs {words}");
+
+        // Nothing but special tokens: they stay, so the input still pools to something
+        let empty = tokenizer.encode("", true).unwrap();
+        assert_eq!(pooling_mask(&empty, &excluded), [1.0, 1.0]);
+    }
+
+    #[test]
+    fn model_inputs_are_fed_by_name_in_the_models_order() {
+        use ModelInput::*;
+        assert_eq!(ModelInput::for_inputs(["input_ids"]).unwrap(), [InputIds]);
+        assert_eq!(ModelInput::for_inputs(["input.1"]).unwrap(), [InputIds]);
+        let tensorflow = ["input_mask", "segment_ids", "input_ids"];
+        assert_eq!(ModelInput::for_inputs(tensorflow).unwrap(), [AttentionMask, TokenTypeIds, InputIds]);
+        assert_eq!(ModelInput::for_inputs(["attention_mask", "input_ids"]).unwrap(), [AttentionMask, InputIds]);
+        assert_eq!(
+            ModelInput::for_inputs(["input_ids", "token_type_ids", "attention_mask"]).unwrap(),
+            [InputIds, TokenTypeIds, AttentionMask]
+        );
+
+        for name in ["pixel_values", "position_ids", "input_lengths", "inputs_embeds", "token_types"] {
+            let err = ModelInput::for_inputs(["input_ids", name]).unwrap_err();
+            assert!(err.to_string().contains(&format!("unsupported model input {:?}", name)), "{err}");
+        }
+    }
+
+    #[test]
+    fn cls_pooling_reads_a_classification_token_placed_last() {
+        let tokenizer = cls_last_tokenizer(&["red", "sky", "at"]);
+        let options = EmbeddingOptions { pooling: PoolingStrategy::Cls, ..Default::default() };
+        let encoding = tokenizer.encode("red sky at", true).unwrap();
+        assert_eq!(encoding.get_tokens(), ["red", "sky", "at", "[CLS]"]);
+        assert_eq!(cls_position(&encoding, cls_token_id(&tokenizer), &options).unwrap(), 3);
+
+        let batch = encode_batch(&tokenizer, &options, &["red sky at"]).unwrap();
+        let output = lookup_model(&batch);
+        let pooled = pool_batch(output.view().into_dyn(), &batch, &options).unwrap();
+        assert_eq!(pooled[0], output.slice(s![0, 3, ..]).to_vec());
+
+        // A configured index past the end of a short text is the input's fault
+        let options = EmbeddingOptions { cls_index: Some(10), ..options };
+        let error = cls_position(&encoding, cls_token_id(&tokenizer), &options).unwrap_err();
+        assert!(matches!(error, ColorizerError::TooFewTokens { found: 4, min: 11 }), "{error}");
+    }
+
+    #[test]
+    fn padded_positions_do_not_change_the_mean() {
+        let tokens = array![[1.0, 2.0], [3.0, 4.0]];
+        let padded = array![[1.0, 2.0], [3.0, 4.0], [100.0, -100.0], [7.0, 7.0]];
+
+        let pooled = masked_mean_pool(tokens.view(), &[1.0, 1.0]);
+        assert_eq!(pooled, vec![2.0, 3.0]);
+        assert_eq!(masked_mean_pool(padded.view(), &[1.0, 1.0, 0.0, 0.0]), pooled);
+    }
+
+    #[test]
+    fn normalizing_tokens_before_pooling_stops_long_vectors_dominating() {
+        // A long token, a unit token, and padding
+        let output = array![[[3.0, 4.0], [0.0, 1.0], [50.0, 50.0]]];
+        let batch = EncodedBatch {
+            size: 1,
+            seq_len: 3,
+            input_ids: Array2::zeros((1, 3)),
+            attention_mask: array![[1, 1, 0]],
+            token_type_ids: Array2::zeros((1, 3)),
+            pooling_mask: vec![1.0, 1.0, 0.0],
+            cls_positions: vec![0],
+        };
+        let pooled = |pre_pool_norm: bool| {
+            let options = EmbeddingOptions { pre_pool_norm, ..Default::default() };
+            pool_batch(output.view().into_dyn(), &batch, &options).unwrap().remove(0)
+        };
+
+        assert_eq!(pooled(false), [1.5, 2.5]);
+        let pre = pooled(true);
+        assert!((pre[0] - 0.3).abs() < 1e-6 && (pre[1] - 0.9).abs() < 1e-6, "{pre:?}");
+
+        // Even after the final normalization the two point different ways
+        let (mut post, mut pre) = (pooled(false), pre);
+        l2_normalize(&mut post);
+        l2_normalize(&mut pre);
+        assert!((post[0] - pre[0]).abs() > 0.1, "{post:?} vs {pre:?}");
+    }
+
+    #[test]
+    fn each_pooling_strategy_reduces_a_fixed_tensor() {
+        // [CLS], two words, and a padded position that must never win
+        let tokens = array![[0.5, 0.5], [1.0, -2.0], [3.0, 0.0], [9.0, 9.0]];
+        let mask = [1.0, 1.0, 1.0, 0.0];
+
+        assert_eq!(pool(tokens.view(), &mask, PoolingStrategy::Mean, 0), vec![1.5, -0.5]);
+        assert_eq!(pool(tokens.view(), &mask, PoolingStrategy::Max, 0), vec![3.0, 0.5]);
+        assert_eq!(pool(tokens.view(), &mask, PoolingStrategy::Cls, 0), vec![0.5, 0.5]);
+        assert_eq!(pool(tokens.view(), &mask, PoolingStrategy::Cls, 2), vec![3.0, 0.0]);
+    }
+
+    #[test]
+    fn long_input_is_truncated_to_max_tokens() {
+        let options = EmbeddingOptions { max_tokens: 16, ..Default::default() };
+        let mut tokenizer = word_tokenizer(&["red", "sky"]);
+        options.apply_to(&mut tokenizer).unwrap();
+
+        let text = "red sky ".repeat(10_000);
+        let encoding = tokenizer.encode(options.normalize_text(&text), true).unwrap();
+        assert_eq!(encoding.len(), 16);
+        // Special tokens survive truncation, so CLS pooling still finds its token
+        assert_eq!(encoding.get_tokens().first().map(String::as_str), Some("[CLS]"));
+        assert_eq!(encoding.get_tokens().last().map(String::as_str), Some("[SEP]"));
+        assert_eq!(pooling_mask(&encoding, &options).len(), 16);
+    }
+
+    #[test]
+    fn text_is_composed_and_optionally_lowercased() {
+        let decomposed = "lo\u{301}ve";
+        let options = EmbeddingOptions::default();
+        assert_eq!(options.normalize_text(decomposed), "l\u{f3}ve");
+        assert_eq!(options.normalize_text("Love"), "Love");
+
+        let lowercase = EmbeddingOptions { lowercase: true, ..Default::default() };
+        assert_eq!(lowercase.normalize_text("Love"), "love");
+        assert_eq!(lowercase.normalize_text("L\u{d3}VE"), lowercase.normalize_text(decomposed));
+    }
+
+    #[test]
+    fn aliases_are_rewritten_word_by_word() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aliases.json");
+        fs::write(&path, r#"{ "luv": "love", "Colour": "color" }"#).unwrap();
+        let aliases = load_aliases(path.to_str().unwrap()).unwrap();
+        let options = EmbeddingOptions { aliases, ..Default::default() };
+
+        assert_eq!(options.normalize_text("I LUV the colour, luvly!"), "I love the color, luvly!");
+    }
+
+    #[test]
+    fn idf_weighting_pulls_a_two_word_input_toward_its_rarer_word() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("idf.json");
+        fs::write(&path, r#"{ "the": 0.1, "ocean": 3.0 }"#).unwrap();
+        let idf = load_idf(path.to_str().unwrap()).unwrap();
+
+        let tokenizer = word_tokenizer(&["the", "ocean", "deep"]);
+        let uniform = EmbeddingOptions { exclude_special: true, ..Default::default() };
+        let weighted = EmbeddingOptions { idf, ..uniform.clone() };
+        let encoding = tokenizer.encode("the ocean", true).unwrap();
+        assert_eq!(pooling_mask(&encoding, &uniform), [0.0, 1.0, 1.0, 0.0]);
+        assert_eq!(pooling_mask(&encoding, &weighted), [0.0, 0.1, 3.0, 0.0]);
+        // Tokens missing from the table weigh 1
+        let encoding = tokenizer.encode("deep ocean", true).unwrap();
+        assert_eq!(pooling_mask(&encoding, &weighted), [0.0, 1.0, 3.0, 0.0]);
+
+        let batch = encode_batch(&tokenizer, &uniform, &["the ocean"]).unwrap();
+        let output = lookup_model(&batch);
+        let ocean = output.slice(s![0, 2, ..]).to_vec();
+        let similarity_to_ocean = |options: &EmbeddingOptions| {
+            let batch = encode_batch(&tokenizer, options, &["the ocean"]).unwrap();
+            let pooled = pool_batch(output.view().into_dyn(), &batch, options).unwrap().remove(0);
+            crate::metric::cosine_similarity(&pooled, &ocean)
+        };
+        let (uniform, weighted) = (similarity_to_ocean(&uniform), similarity_to_ocean(&weighted));
+        assert!(weighted > uniform && weighted > 0.99, "{weighted} vs {uniform}");
+
+        fs::write(&path, r#"{ "the": 0.0 }"#).unwrap();
+        assert!(load_idf(path.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn stemming_makes_running_and_run_embed_the_same() {
+        let tokenizer = word_tokenizer(&["run", "running", "runs", "fast"]);
+        let embed = |options: &EmbeddingOptions, text: &str| {
+            let batch = encode_batch(&tokenizer, options, &[text]).unwrap();
+            pool_batch(lookup_model(&batch).view().into_dyn(), &batch, options).unwrap().remove(0)
+        };
+
+        let plain = EmbeddingOptions::default();
+        assert_ne!(embed(&plain, "running fast"), embed(&plain, "run fast"));
+
+        let stemmed = EmbeddingOptions { stem: true, ..Default::default() };
+        assert_eq!(stemmed.normalize_text("Running, runs!"), "run, run!");
+        assert_eq!(embed(&stemmed, "running fast"), embed(&stemmed, "run fast"));
+        assert_eq!(embed(&stemmed, "runs fast"), embed(&stemmed, "run fast"));
+    }
+}
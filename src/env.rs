@@ -0,0 +1,197 @@
+//! Helpers for reading `COLORIZER_*` environment variables.
+//!
+//! Settings may also come from a TOML file (`COLORIZER_CONFIG`, default `colorizer.toml`) loaded
+//! with [`load_config_file`]; a variable set in the environment always wins over the file. Both
+//! win over the preset chosen with `COLORIZER_PROFILE` ([`load_profile`]).
+
+use std::{
+    collections::BTreeMap,
+    env,
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
+};
+
+use crate::profile::Profile;
+
+/// Config file read when `COLORIZER_CONFIG` is unset, if it exists
+const DEFAULT_CONFIG_FILE: &str = "colorizer.toml";
+
+/// Values from the config file, keyed by the variable they stand in for
+static FILE_VALUES: OnceLock<BTreeMap<String, String>> = OnceLock::new();
+
+/// Preset selected by `COLORIZER_PROFILE`
+static PROFILE: OnceLock<Profile> = OnceLock::new();
+
+/// Read a variable from the environment, else from the config file, else from the profile preset
+pub fn env_var(key: &str) -> Option<String> {
+    resolve(key, env::var(key).ok(), FILE_VALUES.get(), PROFILE.get().copied())
+}
+
+/// Pick the value of `key` from its sources in order of precedence
+fn resolve(
+    key: &str,
+    from_env: Option<String>,
+    file: Option<&BTreeMap<String, String>>,
+    profile: Option<Profile>,
+) -> Option<String> {
+    from_env
+        .or_else(|| file.and_then(|values| values.get(key).cloned()))
+        .or_else(|| profile.and_then(|profile| profile.value(key)).map(String::from))
+}
+
+/// Read `COLORIZER_PROFILE` so the other helpers fall back to its preset. Must be called after
+/// [`load_config_file`], which may set it, and before any other setting is read.
+pub fn load_profile() -> Result<Profile, Box<dyn Error + Send + Sync>> {
+    let profile = env_parse("COLORIZER_PROFILE", Profile::default())?;
+    PROFILE.set(profile).map_err(|_| "the profile was already loaded")?;
+    Ok(profile)
+}
+
+/// A loaded config file, for logging where the effective settings came from
+pub struct ConfigFile {
+    pub path: PathBuf,
+    /// Each variable the file sets, and whether the environment overrides it
+    pub settings: Vec<(String, bool)>,
+}
+
+/// Load the config file named by `COLORIZER_CONFIG`, or `colorizer.toml` if present, so the other
+/// helpers fall back to it; `None` when there is no file.
+///
+/// Keys are variable names without the `COLORIZER_` prefix, in any case, and tables join their
+/// name to their keys with `_`, so `[rate] ms = 200` sets `COLORIZER_RATE_MS`. Arrays become
+/// comma-separated lists. Must be called before any setting is read, and only once.
+pub fn load_config_file() -> Result<Option<ConfigFile>, Box<dyn Error + Send + Sync>> {
+    let path = match env::var("COLORIZER_CONFIG") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) if Path::new(DEFAULT_CONFIG_FILE).is_file() => PathBuf::from(DEFAULT_CONFIG_FILE),
+        Err(_) => return Ok(None),
+    };
+    let contents = fs::read_to_string(&path).map_err(|e| format!("cannot read config file {}: {}", path.display(), e))?;
+    let values = parse_config(&contents).map_err(|e| format!("invalid config file {}: {}", path.display(), e))?;
+    let settings = values.keys().map(|key| (key.clone(), env::var(key).is_ok())).collect();
+    FILE_VALUES
+        .set(values)
+        .map_err(|_| "the config file was already loaded")?;
+    Ok(Some(ConfigFile { path, settings }))
+}
+
+/// Parse config file contents into the variables they set
+fn parse_config(contents: &str) -> Result<BTreeMap<String, String>, String> {
+    let table: toml::Table = contents.parse().map_err(|e: toml::de::Error| e.to_string())?;
+    let mut values = BTreeMap::new();
+    flatten("COLORIZER", &table, &mut values)?;
+    Ok(values)
+}
+
+/// Turn each value in `table` into the string its variable would hold
+fn flatten(prefix: &str, table: &toml::Table, values: &mut BTreeMap<String, String>) -> Result<(), String> {
+    for (key, value) in table {
+        let name = format!("{}_{}", prefix, key.to_ascii_uppercase());
+        let value = match value {
+            toml::Value::Table(table) => {
+                flatten(&name, table, values)?;
+                continue;
+            }
+            toml::Value::Array(items) => items.iter().map(scalar).collect::<Option<Vec<_>>>().map(|items| items.join(",")),
+            value => scalar(value),
+        };
+        let value = value.ok_or_else(|| format!("{} must be a string, number, boolean or list of those", key))?;
+        values.insert(name, value);
+    }
+    Ok(())
+}
+
+/// A string, number or boolean as it would be written in an environment variable
+fn scalar(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(s) => Some(s.clone()),
+        toml::Value::Integer(i) => Some(i.to_string()),
+        toml::Value::Float(f) => Some(f.to_string()),
+        toml::Value::Boolean(b) => Some(b.to_string()),
+        toml::Value::Datetime(_) | toml::Value::Array(_) | toml::Value::Table(_) => None,
+    }
+}
+
+/// Read a string variable, falling back to `default` when unset
+pub fn env_or(key: &str, default: &str) -> String {
+    env_var(key).unwrap_or_else(|| default.to_string())
+}
+
+/// Read an optional string variable; unset or blank counts as `None`
+pub fn env_opt(key: &str) -> Option<String> {
+    env_var(key).filter(|value| !value.trim().is_empty())
+}
+
+/// Parse a variable, falling back to `default` when unset and failing on invalid values
+pub fn env_parse<T: FromStr>(key: &str, default: T) -> Result<T, Box<dyn Error + Send + Sync>>
+where
+    T::Err: fmt::Display,
+{
+    match env_var(key) {
+        Some(value) => value
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid value for {}: {:?} ({})", key, value, e).into()),
+        None => Ok(default),
+    }
+}
+
+/// Read a boolean flag: `1`, `true` and `yes` enable it, anything else (or unset) disables it
+pub fn env_flag(key: &str) -> bool {
+    env_var(key).is_some_and(|value| matches!(value.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+}
+
+/// Read a comma-separated list, skipping empty entries; empty when unset
+pub fn env_list(key: &str) -> Vec<String> {
+    env_var(key)
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_beats_config_file_beats_profile() {
+        let file = parse_config(
+            r#"
+            model_path = "models/custom.onnx"
+            max_tokens = 64
+            metric = "dot"
+            models = ["emotions", "seasons"]
+
+            [rate]
+            ms = 200
+            burst = 5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(file["COLORIZER_MODEL_PATH"], "models/custom.onnx");
+        assert_eq!(file["COLORIZER_MODELS"], "emotions,seasons");
+        assert_eq!(file["COLORIZER_RATE_MS"], "200");
+
+        let value = |key: &str, from_env: Option<&str>| {
+            resolve(key, from_env.map(String::from), Some(&file), Some(Profile::MiniLm))
+        };
+        assert_eq!(value("COLORIZER_RATE_MS", Some("50")).as_deref(), Some("50"));
+        assert_eq!(value("COLORIZER_RATE_MS", None).as_deref(), Some("200"));
+        // The file overrides the preset's 256 and cosine, and the preset fills in what neither sets
+        assert_eq!(value("COLORIZER_MAX_TOKENS", None).as_deref(), Some("64"));
+        assert_eq!(value("COLORIZER_METRIC", None).as_deref(), Some("dot"));
+        assert_eq!(value("COLORIZER_POOLING", None).as_deref(), Some("mean"));
+        assert_eq!(value("COLORIZER_BIND", None), None);
+
+        assert!(parse_config("[rate]\nwhen = 2024-01-01").unwrap_err().contains("when must be"));
+    }
+}
@@ -0,0 +1,82 @@
+//! Errors of the embedding pipeline, each mapped to the HTTP status the server answers with.
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde_json::json;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Why a text couldn't be embedded
+#[derive(Debug, Error)]
+pub enum ColorizerError {
+    /// The text was empty or only whitespace
+    #[error("text must not be empty")]
+    EmptyInput,
+    /// The tokenizer rejected the text
+    #[error("tokenization failed: {0}")]
+    Tokenize(String),
+    /// ONNX Runtime failed to build the inputs or run the model
+    #[error("inference failed: {0}")]
+    Inference(String),
+    /// The model output isn't shaped `[batch, tokens, dimension]`
+    #[error("unexpected model output shape: {0}")]
+    ShapeMismatch(String),
+    /// The text has fewer real tokens than `COLORIZER_MIN_TOKENS`, too little to color reliably
+    #[error("text has {found} token(s), at least {min} required")]
+    TooFewTokens { found: usize, min: usize },
+    /// The model produced NaN or infinite values, which would make every similarity score meaningless
+    #[error("model produced a non-finite embedding (NaN or infinity) for this input")]
+    NonFiniteEmbedding,
+    /// Inference took longer than `COLORIZER_INFERENCE_TIMEOUT_MS`
+    #[error("inference timed out after {}ms", .0.as_millis())]
+    InferenceTimeout(Duration),
+}
+
+impl ResponseError for ColorizerError {
+    /// 400 for bad input, 422 when the model can't handle this particular text, 504 on timeout and
+    /// 500 when the pipeline itself is broken
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ColorizerError::EmptyInput => StatusCode::BAD_REQUEST,
+            ColorizerError::NonFiniteEmbedding | ColorizerError::TooFewTokens { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            ColorizerError::InferenceTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ColorizerError::Tokenize(_) | ColorizerError::Inference(_) | ColorizerError::ShapeMismatch(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// The same `{ "error": ... }` body as every other error response
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(json!({ "error": self.to_string() }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::MessageBody;
+
+    #[test]
+    fn each_variant_maps_to_its_status() {
+        let cases = [
+            (ColorizerError::EmptyInput, StatusCode::BAD_REQUEST),
+            (ColorizerError::Tokenize("bad".into()), StatusCode::INTERNAL_SERVER_ERROR),
+            (ColorizerError::Inference("bad".into()), StatusCode::INTERNAL_SERVER_ERROR),
+            (ColorizerError::ShapeMismatch("bad".into()), StatusCode::INTERNAL_SERVER_ERROR),
+            (ColorizerError::TooFewTokens { found: 1, min: 2 }, StatusCode::UNPROCESSABLE_ENTITY),
+            (ColorizerError::NonFiniteEmbedding, StatusCode::UNPROCESSABLE_ENTITY),
+            (ColorizerError::InferenceTimeout(Duration::from_millis(250)), StatusCode::GATEWAY_TIMEOUT),
+        ];
+        for (error, status) in cases {
+            assert_eq!(error.status_code(), status, "{error}");
+            assert_eq!(error.error_response().status(), status, "{error}");
+        }
+    }
+
+    #[test]
+    fn the_body_is_the_shared_error_shape() {
+        let response = ColorizerError::InferenceTimeout(Duration::from_millis(250)).error_response();
+        let body = response.into_body().try_into_bytes().unwrap();
+        assert_eq!(&body[..], br#"{"error":"inference timed out after 250ms"}"#);
+    }
+}
@@ -0,0 +1,183 @@
+//! Replaying `/color` responses for retried requests that carry an `Idempotency-Key` header.
+//!
+//! The first successful response for a key is stored for `COLORIZER_IDEMPOTENCY_TTL_SECS`; a retry
+//! with the same key and the same request gets it back without running inference again.
+
+use actix_web::{
+    body::{self, BoxBody, MessageBody},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    http::{
+        header::{self, HeaderMap, HeaderName, HeaderValue},
+        StatusCode,
+    },
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+use colorizer::reference::content_hash;
+use futures_util::StreamExt;
+use lru::LruCache;
+use std::{
+    mem,
+    num::NonZeroUsize,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::error_response;
+
+/// Header carrying the client's key
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Header set on replayed responses
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// Longest accepted key
+const MAX_KEY_LEN: usize = 255;
+
+/// Most responses kept at once; the least recently used is dropped first
+const CAPACITY: usize = 10_000;
+
+/// Paths whose responses are replayed
+const IDEMPOTENT_PATHS: &[&str] = &["/color"];
+
+/// A stored successful response
+struct Stored {
+    /// Hash of the method, path, query, `Accept` header and body it answered
+    fingerprint: u64,
+    stored_at: Instant,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: web::Bytes,
+}
+
+/// Responses by idempotency key
+pub struct Idempotency {
+    ttl: Duration,
+    /// Bodies are read here before the JSON extractor sees them, so they need the same limit
+    max_body_bytes: usize,
+    responses: Mutex<LruCache<String, Stored>>,
+}
+
+impl Idempotency {
+    pub fn new(ttl: Duration, max_body_bytes: usize) -> Self {
+        Idempotency {
+            ttl,
+            max_body_bytes,
+            responses: Mutex::new(LruCache::new(NonZeroUsize::new(CAPACITY).unwrap())),
+        }
+    }
+
+    /// The live response stored for `key`, as `(fingerprint, response)`
+    fn get(&self, key: &str) -> Option<(u64, HttpResponse)> {
+        let mut responses = self.responses.lock().unwrap();
+        let stored = responses.get(key)?;
+        if stored.stored_at.elapsed() > self.ttl {
+            responses.pop(key);
+            return None;
+        }
+        let mut res = HttpResponse::build(stored.status).body(stored.body.clone());
+        for (name, value) in &stored.headers {
+            res.headers_mut().append(name.clone(), value.clone());
+        }
+        res.headers_mut()
+            .insert(HeaderName::from_static(REPLAYED_HEADER), HeaderValue::from_static("true"));
+        Some((stored.fingerprint, res))
+    }
+}
+
+/// Middleware replaying the stored response for a repeated `Idempotency-Key` on
+/// [`IDEMPOTENT_PATHS`].
+///
+/// A key reused for a different request is rejected with 422. Only 2xx responses are stored, so
+/// a retry after an error runs again. Requests without the header pass straight through.
+pub async fn replay_idempotent(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    // A TTL of 0 disables replaying
+    let idempotency = req
+        .app_data::<web::Data<Idempotency>>()
+        .filter(|idempotency| !idempotency.ttl.is_zero())
+        .cloned();
+    let key = req
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .map(|v| v.to_str().unwrap_or_default().to_string());
+    let (Some(idempotency), Some(key)) = (idempotency, key) else {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    };
+    if !IDEMPOTENT_PATHS.contains(&req.path()) {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    }
+    if key.is_empty() || key.len() > MAX_KEY_LEN || !key.bytes().all(|b| b.is_ascii_graphic()) {
+        let res = error_response(
+            StatusCode::BAD_REQUEST,
+            format!("Idempotency-Key must be 1-{} printable ASCII characters", MAX_KEY_LEN),
+        );
+        return Ok(req.into_response(res));
+    }
+
+    // Read the raw body for the fingerprint, then hand it back for the handler's extractor
+    let mut payload = mem::replace(req.parts_mut().1, Payload::None);
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        body.extend_from_slice(&chunk?);
+        if body.len() > idempotency.max_body_bytes {
+            let res = error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("request body is larger than {} bytes", idempotency.max_body_bytes),
+            );
+            return Ok(req.into_response(res));
+        }
+    }
+    let body = body.freeze();
+    let fingerprint = fingerprint(&req, &body);
+    req.set_payload(Payload::from(body));
+
+    match idempotency.get(&key) {
+        Some((stored, res)) if stored == fingerprint => return Ok(req.into_response(res)),
+        Some(_) => {
+            let res = error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Idempotency-Key was already used for a different request",
+            );
+            return Ok(req.into_response(res));
+        }
+        None => {}
+    }
+
+    let res = next.call(req).await?;
+    if !res.status().is_success() {
+        return Ok(res.map_into_boxed_body());
+    }
+    let (req, res) = res.into_parts();
+    let (res, res_body) = res.into_parts();
+    let res_body = body::to_bytes(res_body).await.map_err(|e| {
+        let e: Box<dyn std::error::Error> = e.into();
+        actix_web::error::ErrorInternalServerError(e.to_string())
+    })?;
+
+    idempotency.responses.lock().unwrap().put(
+        key,
+        Stored {
+            fingerprint,
+            stored_at: Instant::now(),
+            status: res.status(),
+            headers: res.headers().clone(),
+            body: res_body.clone(),
+        },
+    );
+    Ok(ServiceResponse::new(req, res.set_body(BoxBody::new(res_body))))
+}
+
+/// Hash what makes two requests the same: method, path, query, `Accept` and the raw body
+fn fingerprint(req: &ServiceRequest, body: &[u8]) -> u64 {
+    let accept = req.headers().get(header::ACCEPT).map_or(&b""[..], |v| v.as_bytes());
+    let mut bytes = Vec::with_capacity(body.len() + 64);
+    for part in [req.method().as_str().as_bytes(), req.path().as_bytes(), req.query_string().as_bytes(), accept] {
+        bytes.extend_from_slice(part);
+        bytes.push(0);
+    }
+    bytes.extend_from_slice(body);
+    content_hash(&bytes)
+}
@@ -0,0 +1,105 @@
+//! Rough script detection, used to reject clearly non-English input when `COLORIZER_STRICT_LANG=en`.
+//!
+//! This only looks at which Unicode blocks the letters fall in, so it can tell Latin from Cyrillic
+//! or Japanese but not English from French.
+
+use std::fmt;
+
+/// Share of letters that must be non-Latin before input counts as clearly non-English
+const NON_LATIN_THRESHOLD: f32 = 0.5;
+
+/// Writing system a letter belongs to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Script {
+    Latin,
+    Cyrillic,
+    Greek,
+    Arabic,
+    Hebrew,
+    Devanagari,
+    /// Chinese characters, Japanese kana and Korean Hangul
+    Cjk,
+    Other,
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Script::Latin => "Latin",
+            Script::Cyrillic => "Cyrillic",
+            Script::Greek => "Greek",
+            Script::Arabic => "Arabic",
+            Script::Hebrew => "Hebrew",
+            Script::Devanagari => "Devanagari",
+            Script::Cjk => "CJK",
+            Script::Other => "non-Latin",
+        })
+    }
+}
+
+/// Classify a letter by Unicode block
+fn script_of(c: char) -> Script {
+    match c as u32 {
+        0x0041..=0x024F | 0x1E00..=0x1EFF => Script::Latin,
+        0x0370..=0x03FF | 0x1F00..=0x1FFF => Script::Greek,
+        0x0400..=0x052F => Script::Cyrillic,
+        0x0590..=0x05FF => Script::Hebrew,
+        0x0600..=0x06FF | 0x0750..=0x077F => Script::Arabic,
+        0x0900..=0x097F => Script::Devanagari,
+        0x1100..=0x11FF | 0x3040..=0x30FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xAC00..=0xD7AF => Script::Cjk,
+        _ => Script::Other,
+    }
+}
+
+/// Most common non-Latin script among the letters of `text`, if non-Latin letters make up at least
+/// [`NON_LATIN_THRESHOLD`] of them. Digits, punctuation and emoji are ignored.
+pub fn non_latin_script(text: &str) -> Option<Script> {
+    let mut letters = 0usize;
+    let mut counts: Vec<(Script, usize)> = Vec::new();
+    for c in text.chars().filter(|c| c.is_alphabetic()) {
+        letters += 1;
+        let script = script_of(c);
+        if script == Script::Latin {
+            continue;
+        }
+        match counts.iter_mut().find(|(s, _)| *s == script) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((script, 1)),
+        }
+    }
+
+    let non_latin: usize = counts.iter().map(|(_, n)| n).sum();
+    if letters == 0 || (non_latin as f32) < letters as f32 * NON_LATIN_THRESHOLD {
+        return None;
+    }
+    counts.into_iter().max_by_key(|&(_, n)| n).map(|(script, _)| script)
+}
+
+/// Reject text that is clearly not written in English
+pub fn check_english(text: &str) -> Result<(), String> {
+    match non_latin_script(text) {
+        Some(script) => Err(format!(
+            "input looks like {} text, but only English is supported (COLORIZER_STRICT_LANG=en)",
+            script
+        )),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripts_are_told_apart() {
+        assert_eq!(non_latin_script("a calm summer evening"), None);
+        assert_eq!(non_latin_script("café au lait, 2 sugars 🙂"), None);
+        assert_eq!(non_latin_script("спокойный летний вечер"), Some(Script::Cyrillic));
+        assert_eq!(non_latin_script("静かな夏の夜"), Some(Script::Cjk));
+        assert_eq!(non_latin_script("1234 !?"), None);
+
+        assert!(check_english("ocean").is_ok());
+        let error = check_english("океан").unwrap_err();
+        assert!(error.contains("Cyrillic"), "{error}");
+    }
+}
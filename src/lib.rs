@@ -0,0 +1,16 @@
+//! Embedding pipeline shared by the `colorizer` server and `generate_ref_embeddings`, and the
+//! reference matching the server and the benchmarks run on its output.
+//!
+//! Both binaries must embed text identically, otherwise request embeddings and reference
+//! embeddings live in different spaces and similarity scores are meaningless.
+
+pub mod ann;
+pub mod embedding;
+pub mod env;
+pub mod error;
+pub mod matching;
+pub mod metric;
+pub mod precision;
+pub mod profile;
+pub mod reference;
+pub mod session;
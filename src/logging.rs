@@ -0,0 +1,245 @@
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::header::{HeaderMap, HeaderName, HeaderValue},
+    middleware::Next,
+    web, Error, HttpMessage,
+};
+use std::fmt;
+use uuid::Uuid;
+
+use crate::audit::{now_secs, AuditLog, AuditRecord};
+
+/// Header carrying the request ID, read from the client and echoed on every response
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest client-supplied request ID that is reused; longer ones are replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// ID correlating every log line of one request, attached by [`assign_request_id`]
+#[derive(Clone)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// The ID attached to a request, or `-` outside [`assign_request_id`]
+    pub fn of(req: &impl HttpMessage) -> RequestId {
+        req.extensions()
+            .get::<RequestId>()
+            .cloned()
+            .unwrap_or_else(|| RequestId("-".to_string()))
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Middleware reusing the client's `X-Request-Id`, or generating a UUID, and echoing it back.
+///
+/// Client IDs are only reused if they are short printable ASCII, so they can't forge log lines.
+pub async fn assign_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic()))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    req.extensions_mut().insert(RequestId(id.clone()));
+
+    match next.call(req).await {
+        Ok(mut res) => {
+            insert_request_id(res.headers_mut(), &id);
+            Ok(res.map_into_boxed_body())
+        }
+        // Middleware errors (e.g. rate limiting) get their response rendered here so it carries the
+        // ID too. Holding a clone of the request across `next.call` instead would break routing.
+        Err(e) => {
+            let mut response = e.error_response();
+            insert_request_id(response.headers_mut(), &id);
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+fn insert_request_id(headers: &mut HeaderMap, id: &str) {
+    if let Ok(value) = HeaderValue::from_str(id) {
+        headers.insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+}
+
+/// Details of a colorization, attached to the request by the handler and logged by [`log_colorization`]
+#[derive(Clone)]
+pub struct ColorLog {
+    pub text_len: usize,
+    /// Raw input text, only set when `COLORIZER_LOG_TEXT=1`
+    pub text: Option<String>,
+    pub rgb: (u8, u8, u8),
+    /// Reference word of the chosen match, if there was one
+    pub word: Option<String>,
+    pub similarity: Option<f32>,
+}
+
+impl ColorLog {
+    /// Attach this record to the request so the middleware can log it
+    pub fn attach(self, req: &impl HttpMessage) {
+        req.extensions_mut().insert(self);
+    }
+}
+
+/// Middleware emitting one INFO line per colorization, e.g.
+/// `color id=6f1c... len=5 rgb=(255,255,0) similarity=0.9132`, and appending `/color` requests to
+/// the audit log when one is configured
+pub async fn log_colorization(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let res = next.call(req).await?;
+
+    let entry = res.request().extensions().get::<ColorLog>().cloned();
+    if let Some(entry) = entry {
+        let id = RequestId::of(res.request());
+        let (r, g, b) = entry.rgb;
+        let similarity = entry.similarity.map_or("-".to_string(), |s| format!("{:.4}", s));
+        match &entry.text {
+            Some(text) => log::info!(
+                "color id={} len={} rgb=({},{},{}) similarity={} text={:?}",
+                id,
+                entry.text_len,
+                r,
+                g,
+                b,
+                similarity,
+                text
+            ),
+            None => log::info!(
+                "color id={} len={} rgb=({},{},{}) similarity={}",
+                id,
+                entry.text_len,
+                r,
+                g,
+                b,
+                similarity
+            ),
+        }
+
+        let audit_log = res
+            .request()
+            .app_data::<web::Data<Option<AuditLog>>>()
+            .filter(|audit_log| audit_log.is_some() && res.request().path() == "/color")
+            .cloned();
+        if let Some(audit_log) = audit_log {
+            // File writes and rotation block, so they run on the blocking pool rather than the executor
+            let ts = now_secs();
+            let written = web::block(move || {
+                let record = AuditRecord {
+                    ts,
+                    id: &id.to_string(),
+                    text_len: entry.text_len,
+                    word: entry.word.as_deref(),
+                    similarity: entry.similarity,
+                    r,
+                    g,
+                    b,
+                    text: entry.text.as_deref(),
+                };
+                audit_log.as_ref().as_ref().map_or(Ok(()), |audit_log| audit_log.record(&record))
+            })
+            .await;
+            match written {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => log::warn!("failed to write the audit log: {}", e),
+                Err(e) => log::warn!("failed to write the audit log: {}", e),
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{
+        middleware::from_fn,
+        test::{call_service, init_service, read_body, TestRequest},
+        App, HttpRequest, HttpResponse,
+    };
+
+    /// The response's `X-Request-Id` and the ID the handler saw, for a request with `header`
+    async fn ids(header: Option<&str>) -> (String, String) {
+        let app = init_service(
+            App::new()
+                .wrap(from_fn(assign_request_id))
+                .default_service(web::to(|req: HttpRequest| async move {
+                    HttpResponse::Ok().body(RequestId::of(&req).to_string())
+                })),
+        )
+        .await;
+        let mut req = TestRequest::get().uri("/color");
+        if let Some(id) = header {
+            req = req.insert_header((REQUEST_ID_HEADER, id));
+        }
+        let res = call_service(&app, req.to_request()).await;
+        let echoed = res.headers().get(REQUEST_ID_HEADER).unwrap().to_str().unwrap().to_string();
+        let seen = String::from_utf8(read_body(res).await.to_vec()).unwrap();
+        (echoed, seen)
+    }
+
+    #[actix_web::test]
+    async fn client_ids_are_echoed() {
+        assert_eq!(ids(Some("req-42")).await, ("req-42".to_string(), "req-42".to_string()));
+    }
+
+    #[actix_web::test]
+    async fn missing_or_unsafe_ids_are_replaced_with_a_uuid() {
+        let too_long = "a".repeat(MAX_REQUEST_ID_LEN + 1);
+        for header in [None, Some(""), Some("two words"), Some(too_long.as_str())] {
+            let (echoed, seen) = ids(header).await;
+            assert!(Uuid::parse_str(&echoed).is_ok(), "{header:?} became {echoed:?}");
+            assert_eq!(echoed, seen);
+        }
+        assert_ne!(ids(None).await.0, ids(None).await.0);
+    }
+
+    #[actix_web::test]
+    async fn only_color_requests_reach_the_audit_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let audit_log = AuditLog::open(&path, 1 << 20, 1).unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Some(audit_log)))
+                .wrap(from_fn(log_colorization))
+                .wrap(from_fn(assign_request_id))
+                .default_service(web::to(|req: HttpRequest| async move {
+                    ColorLog {
+                        text_len: 5,
+                        text: None,
+                        rgb: (0, 0, 255),
+                        word: Some("ocean".to_string()),
+                        similarity: Some(0.5),
+                    }
+                    .attach(&req);
+                    HttpResponse::Ok().finish()
+                })),
+        )
+        .await;
+        for (path, id) in [("/color", "a"), ("/colors", "b"), ("/color", "c")] {
+            let req = TestRequest::post().uri(path).insert_header((REQUEST_ID_HEADER, id));
+            call_service(&app, req.to_request()).await;
+        }
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let records: Vec<serde_json::Value> =
+            contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let ids: Vec<&str> = records.iter().map(|record| record["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, ["a", "c"]);
+    }
+}
@@ -1,106 +1,2136 @@
+use actix_cors::Cors;
 use actix_files::{Files, NamedFile};
 use actix_governor::{Governor, GovernorConfigBuilder};
-use actix_web::{get, post, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
-use ort::{environment::Environment, session::Session, session::SessionBuilder, tensor::OrtOwnedTensor, value::Value};
+use actix_web::{
+    error::{InternalError, JsonPayloadError, QueryPayloadError},
+    get,
+    http::{
+        header::{self, EntityTag, TryIntoHeaderValue},
+        KeepAlive, StatusCode,
+    },
+    middleware::{from_fn, Compress, Condition, Logger},
+    post, web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder, ResponseError,
+};
+use colorizer::{
+    embedding::l2_normalize,
+    env::{load_config_file, load_profile},
+    error::ColorizerError,
+    metric,
+    profile::Profile,
+    reference::content_hash,
+};
+use futures_util::stream;
+use lru::LruCache;
+use ort::environment::Environment;
 use serde::{Deserialize, Serialize};
-use std::{error::Error, fs::File, path::PathBuf, sync::Arc};
-use tokenizers::Tokenizer;
+use serde_json::error::Category;
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    path::PathBuf,
+    net::IpAddr,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::{Duration, UNIX_EPOCH},
+};
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
-/// Input JSON: `{ "text": "example sentence" }`
-#[derive(Deserialize)]
+mod adjust;
+mod antonyms;
+mod audit;
+mod auth;
+mod batcher;
+mod color_space;
+mod config;
+mod css_names;
+mod cvd;
+mod embedder;
+mod idempotency;
+mod lang;
+mod logging;
+mod metrics;
+mod model;
+mod openapi;
+mod sentence;
+mod shutdown;
+mod swatch;
+mod usage;
+mod ws;
+
+use antonyms::Antonyms;
+use audit::AuditLog;
+use auth::{require_api_key, ApiKey, PROTECTED_PATHS};
+use color_space::{rgb_to_cmyk, rgb_to_hsl, Cmyk, Hsl};
+use config::{Config, RateLimit};
+use css_names::nearest_css_name;
+use cvd::Cvd;
+use embedder::ModelIo;
+use idempotency::{replay_idempotent, Idempotency, IDEMPOTENCY_KEY_HEADER, REPLAYED_HEADER};
+use logging::{assign_request_id, log_colorization, ColorLog, RequestId, REQUEST_ID_HEADER};
+use metrics::{record_request_metrics, Metrics};
+use model::{EmptyReferences, Model};
+use shutdown::{shutdown_on_signal, track_in_flight, InFlight};
+use usage::{track_usage, until_reset, Usage};
+
+/// Input JSON: `{ "text": "example sentence", "k": 3 }`
+#[derive(Deserialize, ToSchema)]
 struct TextInput {
     text: String,
+    /// Number of closest colors to return (defaults to 1)
+    #[serde(default = "default_k")]
+    #[schema(minimum = 1, default = default_k)]
+    k: usize,
+    /// Blend the top matches into one color instead of snapping to the nearest
+    #[serde(default)]
+    blend: bool,
+    /// Return `fallback` instead when the best match's similarity is below this
+    min_similarity: Option<f32>,
+    /// Color returned when no match clears `min_similarity`
+    #[serde(default = "default_fallback")]
+    #[schema(value_type = (u8, u8, u8), default = default_fallback)]
+    fallback: RgbColor,
+    /// Perturb each channel by up to this much, reproducibly for a given `seed`
+    #[serde(default)]
+    jitter: u8,
+    /// Seed for `jitter`; the same seed always yields the same color
+    #[serde(default)]
+    seed: u64,
+    /// Background color; the result is lightened or darkened until it's legible against it
+    #[schema(value_type = Option<(u8, u8, u8)>)]
+    bg: Option<RgbColor>,
+    /// Color each sentence separately instead of pooling the whole text
+    #[serde(default)]
+    split: SplitMode,
+    /// Named model to use instead of the default
+    model: Option<String>,
+    /// Ad-hoc references embedded for this request only and matched alongside the loaded ones
+    #[serde(default)]
+    #[schema(max_items = 32)]
+    extra_refs: Vec<ExtraRef>,
 }
 
-/// Output JSON: `{ "r": 123, "g": 45, "b": 67 }`
-#[derive(Serialize)]
-struct ColorOutput {
+/// Request-supplied reference: `{ "word": "teal", "color": [0, 128, 128] }`
+#[derive(Deserialize, ToSchema)]
+struct ExtraRef {
+    word: String,
+    #[schema(value_type = (u8, u8, u8))]
+    color: RgbColor,
+}
+
+/// Query string of `GET /color?text=ocean&k=3`: the options of [`TextInput`] that fit in a URL
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct TextQuery {
+    /// Text to color
+    text: String,
+    /// Number of closest colors to return
+    #[serde(default = "default_k")]
+    #[param(minimum = 1, default = default_k)]
+    k: usize,
+    /// Blend the top matches into one color
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    blend: bool,
+    /// Return the fallback color when the best match is less similar than this
+    min_similarity: Option<f32>,
+    /// Perturb each channel by up to this much
+    #[serde(default)]
+    jitter: u8,
+    /// Seed for `jitter`
+    #[serde(default)]
+    seed: u64,
+    /// Color each sentence separately
+    #[serde(default)]
+    #[param(inline)]
+    split: SplitMode,
+    /// Named model to use instead of the default
+    model: Option<String>,
+}
+
+impl From<TextQuery> for TextInput {
+    fn from(query: TextQuery) -> Self {
+        TextInput {
+            text: query.text,
+            k: query.k,
+            blend: query.blend,
+            min_similarity: query.min_similarity,
+            fallback: default_fallback(),
+            jitter: query.jitter,
+            seed: query.seed,
+            bg: None,
+            split: query.split,
+            model: query.model,
+            extra_refs: Vec::new(),
+        }
+    }
+}
+
+/// How `/color` divides its input before embedding
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum SplitMode {
+    /// Embed the whole text as one
+    #[default]
+    None,
+    /// One color per sentence, split on `.`, `!` and `?`
+    Sentence,
+}
+
+fn default_k() -> usize {
+    1
+}
+
+fn default_fallback() -> RgbColor {
+    (128, 128, 128)
+}
+
+/// Batch input JSON: `{ "texts": ["first", "second"] }`
+#[derive(Deserialize, ToSchema)]
+struct BatchInput {
+    texts: Vec<String>,
+    /// Named model to use instead of the default
+    model: Option<String>,
+}
+
+/// Reverse lookup input JSON: `{ "r": 250, "g": 240, "b": 10 }`
+#[derive(Deserialize, ToSchema)]
+struct RgbInput {
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+/// Reverse lookup output JSON: `{ "word": "sun", "r": 255, "g": 255, "b": 0, "distance": 15.8 }`
+#[derive(Serialize, ToSchema)]
+struct NearestWordOutput<'a> {
+    word: &'a str,
     r: u8,
     g: u8,
     b: u8,
+    /// Euclidean distance in RGB space between the input and the reference color
+    distance: f32,
 }
 
-/// Reference embedding with an associated RGB color.
-#[derive(Deserialize, Serialize)]
-struct RefEmbedding {
+/// Candidate palette word JSON: `{ "word": "crimson" }`
+#[derive(Deserialize, ToSchema)]
+struct WordInput {
+    word: String,
+    /// Named model to use instead of the default
+    model: Option<String>,
+}
+
+/// Embed input JSON: `{ "text": "ocean waves" }`
+#[derive(Deserialize, ToSchema)]
+struct EmbedInput {
+    text: String,
+    /// Named model to use instead of the default
+    model: Option<String>,
+}
+
+/// Debug input JSON: `{ "text": "sunset", "top": 20 }`
+#[derive(Deserialize, ToSchema)]
+struct DebugInput {
+    text: String,
+    /// Only return this many of the best matches instead of every reference
+    #[schema(minimum = 1)]
+    top: Option<usize>,
+    /// Named model to use instead of the default
+    model: Option<String>,
+}
+
+/// Embed output JSON: `{ "embedding": [0.013, -0.072, ...], "dim": 512 }`
+#[derive(Serialize, ToSchema)]
+struct EmbedOutput {
     embedding: Vec<f32>,
-    color: (u8, u8, u8),
+    dim: usize,
+}
+
+/// Model info JSON: `{ "model": "default", "dimension": 512, "inputs": [...], "outputs": [...] }`
+#[derive(Serialize, ToSchema)]
+struct ModelInfoOutput<'a> {
+    model: &'a str,
+    /// Output dimension from the startup probe; `null` if it failed
+    dimension: Option<usize>,
+    /// Declared inputs and outputs; omitted in mock mode, where no ONNX model is loaded
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    io: Option<ModelIo>,
+}
+
+/// Compare input JSON: `{ "a": "ocean", "b": "sea" }`
+#[derive(Deserialize, ToSchema)]
+struct CompareInput {
+    a: String,
+    b: String,
+    /// Named model to use instead of the default
+    model: Option<String>,
+}
+
+/// Compare output JSON: `{ "similarity": 0.82 }`
+#[derive(Serialize, ToSchema)]
+struct CompareOutput {
+    similarity: f32,
+}
+
+/// Usage output JSON: `{ "quota": 1000, "resets_in_secs": 3600, "clients": [ { "ip": "10.0.0.7", "requests": 42 } ] }`
+#[derive(Serialize, ToSchema)]
+struct UsageOutput {
+    /// `COLORIZER_DAILY_QUOTA`, if set
+    quota: Option<u64>,
+    /// Seconds until counts are cleared at midnight UTC
+    resets_in_secs: u64,
+    /// Today's requests per client IP, busiest first
+    clients: Vec<ClientUsage>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ClientUsage {
+    #[schema(value_type = String)]
+    ip: IpAddr,
+    requests: u64,
+}
+
+/// Identifiers of one loaded model, for `/version`
+#[derive(Serialize, ToSchema)]
+struct ModelVersion {
+    /// FNV-1a hash of the loaded reference JSON, as 16 hex digits
+    palette_hash: String,
+    /// Model file modification time in seconds since the Unix epoch, if known
+    model_modified: Option<u64>,
+    ref_count: usize,
+}
+
+/// Version output JSON: `{ "version": "0.1.0", "models": { "default": { "palette_hash": ..., ... } } }`
+#[derive(Serialize, ToSchema)]
+struct VersionOutput<'a> {
+    version: &'static str,
+    models: BTreeMap<&'a str, ModelVersion>,
+}
+
+/// `?model=emotions` selects a named model instead of the default, on `/reload` and `/model-info`
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ModelQuery {
+    /// Named model to use instead of the default
+    model: Option<String>,
+}
+
+/// Reload output JSON: `{ "model": "default", "ref_count": 244 }`
+#[derive(Serialize, ToSchema)]
+struct ReloadOutput<'a> {
+    model: &'a str,
+    ref_count: usize,
+}
+
+/// Gradient input JSON: `{ "text": "the quick brown fox" }`
+#[derive(Deserialize, ToSchema)]
+struct GradientInput {
+    text: String,
+    /// Named model to use instead of the default
+    model: Option<String>,
+}
+
+/// Gradient options: `?smooth=4` inserts 4 interpolated colors between adjacent words, and
+/// `?dither=1` dithers them to avoid banding
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct GradientQuery {
+    /// Interpolated colors inserted between adjacent words
+    #[serde(default)]
+    smooth: usize,
+    /// Ordered-dither the interpolated colors to avoid banding
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    dither: bool,
+}
+
+/// Interpolation input JSON: `{ "from": "calm", "to": "anger", "steps": 10 }`
+#[derive(Deserialize, ToSchema)]
+struct InterpolateInput {
+    from: String,
+    to: String,
+    /// Number of colors returned, including both endpoints
+    #[serde(default = "default_interpolation_steps")]
+    #[schema(minimum = 2, maximum = 64, default = default_interpolation_steps)]
+    steps: usize,
+    /// Named model to use instead of the default
+    model: Option<String>,
+}
+
+fn default_interpolation_steps() -> usize {
+    10
+}
+
+/// Palette image options: `?cols=16` sets how many swatches fit in a row, and `?labels=1` writes
+/// each word on its swatch
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PaletteImageQuery {
+    /// Swatches per row
+    #[param(minimum = 1, maximum = 256)]
+    cols: Option<u32>,
+    /// Draw each word on its swatch
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    labels: bool,
+}
+
+/// Swatch options: `?text=ocean&size=64`
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SwatchQuery {
+    /// Text to color
+    text: String,
+    /// Side length in pixels
+    #[serde(default = "default_swatch_size")]
+    #[param(minimum = 1, maximum = 4096, default = default_swatch_size)]
+    size: u32,
+    /// Named model to use instead of the default
+    model: Option<String>,
+}
+
+fn default_swatch_size() -> u32 {
+    64
+}
+
+/// Cache key of a rendered swatch; the palette hash keeps a reload from serving stale colors
+#[derive(PartialEq, Eq, Hash)]
+struct SwatchKey {
+    model: String,
+    palette_hash: u64,
+    text: String,
+    size: u32,
+}
+
+/// Largest side length `/swatch` renders, in pixels
+const MAX_SWATCH_SIZE: u32 = 4096;
+
+/// Number of rendered swatches kept for repeated `/swatch` requests
+const SWATCH_CACHE_SIZE: usize = 256;
+
+/// Widest grid `/palette.png` renders, in swatches
+const MAX_PALETTE_COLS: u32 = 256;
+
+/// Largest image `/palette.png` renders, in swatches, so a tall one-column grid stays bounded
+const MAX_PALETTE_CELLS: u32 = 65_536;
+
+/// Maximum number of colors returned by `/interpolate`
+const MAX_INTERPOLATION_STEPS: usize = 64;
+
+/// Maximum number of interpolated steps between two words in `/gradient`
+const MAX_GRADIENT_STEPS: usize = 32;
+
+/// Number of top matches mixed together when `blend` is set
+const BLEND_TOP_N: usize = 3;
+
+/// Maximum number of `extra_refs` in a single `/color` request
+const MAX_EXTRA_REFS: usize = 32;
+
+/// Maximum number of texts accepted by a single `/colors` request
+const MAX_BATCH_SIZE: usize = 256;
+
+/// Maximum number of texts accepted by `/colors/stream`, which never holds the whole response
+const MAX_STREAM_BATCH_SIZE: usize = 4096;
+
+/// Health check JSON: `{ "status": "ok", "ref_count": 86 }`
+#[derive(Serialize, ToSchema)]
+struct HealthOutput {
+    status: &'static str,
+    ref_count: usize,
+}
+
+/// Output JSON: `{ "r": 123, "g": 45, "b": 67 }`, plus any opt-in fields
+#[derive(Serialize, Default, ToSchema)]
+struct ColorOutput {
+    r: u8,
+    g: u8,
+    b: u8,
+    /// Reference word of the best match; only set with `?explain=1`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    word: Option<String>,
+    /// Similarity of the best match; only set with `?explain=1`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    similarity: Option<f32>,
+    /// Whether the best match cleared `min_similarity`; only set when a threshold was given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    matched: Option<bool>,
+    /// Nearest CSS color name; only set with `?name=1`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'static str>,
+    /// Set when the text was mostly unknown tokens and the color comes from a hash of it instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fallback: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hsl: Option<Hsl>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cmyk: Option<Cmyk>,
+    /// WCAG contrast ratio against `bg`; only set when a background was given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    contrast_ratio: Option<f32>,
+    /// Softmax probabilities over the top matches; only set with `?distribution=K`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    distribution: Option<Vec<WordProbability>>,
+}
+
+/// One entry of the `?distribution=K` output: `{ "word": "orange", "probability": 0.41 }`
+#[derive(Serialize, ToSchema)]
+struct WordProbability {
+    word: String,
+    probability: f32,
+}
+
+impl ColorOutput {
+    fn new(rgb: RgbColor) -> Self {
+        ColorOutput {
+            r: rgb.0,
+            g: rgb.1,
+            b: rgb.2,
+            ..Default::default()
+        }
+    }
+
+    fn rgb(&self) -> RgbColor {
+        (self.r, self.g, self.b)
+    }
+
+    /// Add the representations requested by `?space=`
+    fn with_space(mut self, space: ColorSpace) -> Self {
+        let rgb = self.rgb();
+        if matches!(space, ColorSpace::Hsl | ColorSpace::All) {
+            self.hsl = Some(rgb_to_hsl(rgb));
+        }
+        if matches!(space, ColorSpace::Cmyk | ColorSpace::All) {
+            self.cmyk = Some(rgb_to_cmyk(rgb));
+        }
+        self
+    }
+
+    /// Add the nearest CSS color name if `?name=1` asked for it
+    fn with_name(mut self, name: bool) -> Self {
+        if name {
+            self.name = Some(nearest_css_name(self.rgb()));
+        }
+        self
+    }
+}
+
+/// An RGB color triple
+type RgbColor = (u8, u8, u8);
+
+/// How a color is rendered in the response
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum ColorFormat {
+    /// `{ "r": 255, "g": 110, "b": 240 }`
+    #[default]
+    Rgb,
+    /// `"#ff6ef0"`
+    Hex,
+    /// `"rgb(255,110,240)"`
+    Css,
+}
+
+/// Extra color spaces included alongside RGB
+#[derive(Deserialize, Clone, Copy, Default, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum ColorSpace {
+    #[default]
+    Rgb,
+    Hsl,
+    Cmyk,
+    All,
+}
+
+/// Color space used to mix the top matches when `blend` is set
+#[derive(Deserialize, Clone, Copy, Default, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum BlendSpace {
+    /// Weighted average of each RGB channel
+    #[default]
+    Rgb,
+    /// Weighted average in HSL, taking the shorter way around the hue circle
+    Hsl,
+}
+
+/// Query string options: `?format=hex&space=hsl&quantize=3&warmth=0.5&cvd=protanopia&blend_space=hsl&rank=2`
+#[derive(Deserialize, Clone, Copy, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ColorQuery {
+    /// Output format; `Accept: text/plain` selects plain hex when omitted
+    #[param(inline)]
+    format: Option<ColorFormat>,
+    /// Extra color spaces included alongside RGB
+    #[serde(default)]
+    #[param(inline)]
+    space: ColorSpace,
+    /// Snap each channel to `2^quantize` levels (1-8 bits)
+    #[param(minimum = 1, maximum = 8)]
+    quantize: Option<u8>,
+    /// Shift toward orange (positive) or blue (negative), in `[-1, 1]`
+    #[param(minimum = -1, maximum = 1)]
+    warmth: Option<f32>,
+    /// Simulate how the color appears with a color vision deficiency
+    #[serde(default)]
+    #[param(inline)]
+    cvd: Cvd,
+    /// Include the matched reference word and its similarity
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    explain: bool,
+    /// Include a softmax distribution over this many top matches
+    #[param(minimum = 1)]
+    distribution: Option<usize>,
+    /// Softmax temperature for `distribution`; lower is sharper
+    #[serde(default = "default_temp")]
+    #[param(exclusive_minimum = 0, default = default_temp)]
+    temp: f32,
+    /// Color space for `blend`
+    #[serde(default)]
+    #[param(inline)]
+    blend_space: BlendSpace,
+    /// Return the Nth-best match (1-based) instead of the best; `/color` only
+    #[param(minimum = 1)]
+    rank: Option<usize>,
+    /// Include the nearest CSS color name
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    name: bool,
+}
+
+fn default_temp() -> f32 {
+    1.0
+}
+
+/// Accept `1`/`true`/`yes` (and `0`/`false`/`no`) for boolean query flags
+fn deserialize_flag<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" => Ok(true),
+        "0" | "false" | "no" | "" => Ok(false),
+        other => Err(serde::de::Error::custom(format!("expected a boolean flag, got {:?}", other))),
+    }
+}
+
+impl ColorQuery {
+    /// Check option ranges that serde can't express
+    fn validate(&self) -> Result<(), String> {
+        if let Some(bits) = self.quantize {
+            if !(1..=8).contains(&bits) {
+                return Err(format!("quantize must be between 1 and 8, got {}", bits));
+            }
+        }
+        if let Some(warmth) = self.warmth {
+            if !(-1.0..=1.0).contains(&warmth) {
+                return Err(format!("warmth must be between -1 and 1, got {}", warmth));
+            }
+        }
+        if self.distribution == Some(0) {
+            return Err("distribution must be at least 1".to_string());
+        }
+        if self.rank == Some(0) {
+            return Err("rank must be at least 1".to_string());
+        }
+        if !(self.temp.is_finite() && self.temp > 0.0) {
+            return Err(format!("temp must be a positive number, got {}", self.temp));
+        }
+        Ok(())
+    }
+
+    /// Apply the requested post-lookup adjustments to a matched color; quantizing comes last so
+    /// the result stays on the requested grid
+    fn adjust(&self, rgb: RgbColor) -> RgbColor {
+        let rgb = match self.warmth {
+            Some(amount) => adjust::warmth(rgb, amount),
+            None => rgb,
+        };
+        let rgb = cvd::simulate(rgb, self.cvd);
+        match self.quantize {
+            Some(bits) => adjust::quantize(rgb, bits),
+            None => rgb,
+        }
+    }
+
+    /// Resolve the output format, honoring `Accept: text/plain` when no format is given
+    fn resolve(&self, req: &HttpRequest) -> (ColorFormat, bool) {
+        if let Some(format) = self.format {
+            return (format, false);
+        }
+        let wants_plain = req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("text/plain"));
+        if wants_plain {
+            (ColorFormat::Hex, true)
+        } else {
+            (ColorFormat::Rgb, false)
+        }
+    }
+}
+
+/// Render an RGB color as a string in the given format
+fn format_color(rgb: RgbColor, format: ColorFormat) -> String {
+    let (r, g, b) = rgb;
+    match format {
+        ColorFormat::Hex => format!("#{:02x}{:02x}{:02x}", r, g, b),
+        ColorFormat::Css => format!("rgb({},{},{})", r, g, b),
+        ColorFormat::Rgb => format!("{{\"r\":{},\"g\":{},\"b\":{}}}", r, g, b),
+    }
+}
+
+/// Build the response for a single color in the requested format.
+///
+/// String formats only carry the color itself, so any extra output fields are dropped.
+fn color_response(output: ColorOutput, format: ColorFormat, plain: bool) -> HttpResponse {
+    match (format, plain) {
+        (ColorFormat::Rgb, _) => HttpResponse::Ok().json(output),
+        (_, true) => HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .body(format_color(output.rgb(), format)),
+        (_, false) => HttpResponse::Ok().json(format_color(output.rgb(), format)),
+    }
+}
+
+/// Error JSON: `{ "error": "text must not be empty" }`
+#[derive(Serialize, ToSchema)]
+struct ErrorOutput {
+    error: String,
+}
+
+/// Build an error response with a JSON body
+fn error_response(status: StatusCode, message: impl Into<String>) -> HttpResponse {
+    HttpResponse::build(status).json(ErrorOutput { error: message.into() })
+}
+
+/// Reject unparseable JSON bodies with the same `{"error": ...}` shape as every other error
+fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    let (status, detail) = match &err {
+        JsonPayloadError::ContentType => (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "expected Content-Type: application/json".to_string(),
+        ),
+        JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+            (StatusCode::PAYLOAD_TOO_LARGE, err.to_string())
+        }
+        JsonPayloadError::Deserialize(e) => (StatusCode::BAD_REQUEST, describe_json_error(e)),
+        _ => (StatusCode::BAD_REQUEST, err.to_string()),
+    };
+    let response = error_response(status, format!("invalid request body: {}", detail));
+    InternalError::from_response(err, response).into()
+}
+
+/// Parse JSON bodies up to `max_body_bytes`, answering `413` past that before the text is read
+fn json_config(max_body_bytes: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(max_body_bytes)
+        .error_handler(json_error_handler)
+}
+
+/// Render malformed query strings as JSON `400`s, e.g. `GET /color` without `text`
+fn query_error_handler(err: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    let detail = match &err {
+        QueryPayloadError::Deserialize(e) => e.to_string(),
+        _ => err.to_string(),
+    };
+    let response = error_response(StatusCode::BAD_REQUEST, format!("invalid query string: {}", detail));
+    InternalError::from_response(err, response).into()
+}
+
+/// Describe a body deserialization error, e.g. "missing field `text`" for a wrong field name
+fn describe_json_error(e: &serde_json::Error) -> String {
+    match e.classify() {
+        Category::Eof if e.line() == 1 && e.column() == 0 => "body is empty".to_string(),
+        // Field and type errors read better without serde's position suffix
+        Category::Data => {
+            let message = e.to_string();
+            let position = format!(" at line {} column {}", e.line(), e.column());
+            message.strip_suffix(&position).unwrap_or(&message).to_string()
+        }
+        _ => e.to_string(),
+    }
+}
+
+/// Palette entry JSON: `{ "word": "sun", "r": 255, "g": 255, "b": 0 }`
+#[derive(Serialize, ToSchema)]
+struct PaletteEntry<'a> {
+    word: &'a str,
+    #[serde(flatten)]
+    color: ColorOutput,
+}
+
+/// Palette entry JSON with a formatted color: `{ "word": "sun", "color": "#ffff00" }`
+#[derive(Serialize, ToSchema)]
+struct FormattedPaletteEntry<'a> {
+    word: &'a str,
+    color: String,
+}
+
+/// Gradient entry JSON: `{ "word": "fox", "r": 200, "g": 90, "b": 30 }`; interpolated steps have no word
+#[derive(Serialize, ToSchema)]
+struct GradientEntry<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    word: Option<&'a str>,
+    #[serde(flatten)]
+    color: ColorOutput,
+}
+
+/// Interpolation entry JSON: `{ "t": 0.5, "word": "storm", "r": 120, "g": 40, "b": 60 }`, where `word` is
+/// the reference matched at that point
+#[derive(Serialize, ToSchema)]
+struct InterpolationEntry<'a> {
+    t: f32,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    word: &'a str,
+    #[serde(flatten)]
+    color: ColorOutput,
+}
+
+/// Per-sentence entry JSON: `{ "sentence": "I love you.", "r": 255, "g": 20, "b": 60 }`
+#[derive(Serialize, ToSchema)]
+struct SentenceColor<'a> {
+    sentence: &'a str,
+    #[serde(flatten)]
+    color: ColorOutput,
+}
+
+/// Ranked match returned when `k > 1`
+#[derive(Serialize, ToSchema)]
+struct RankedColor {
+    r: u8,
+    g: u8,
+    b: u8,
+    similarity: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    word: Option<String>,
 }
 
 /// Shared application state
 struct AppState {
-    tokenizer: Tokenizer,
-    session: Session,
-    ref_embeddings: Vec<RefEmbedding>,
+    /// Loaded models by name
+    models: BTreeMap<String, Arc<Model>>,
+    /// Model used when a request doesn't name one
+    default_model: String,
+    /// Per-connection limit for WebSocket frames; `None` when rate limiting is disabled
+    rate_limit: Option<RateLimit>,
+    /// Include raw input text in request logs (`COLORIZER_LOG_TEXT=1`)
+    log_text: bool,
+    /// Reject clearly non-English input with 422 (`COLORIZER_STRICT_LANG=en`)
+    strict_english: bool,
+    /// Whether `POST /embed` is enabled (`COLORIZER_EXPOSE_EMBEDDINGS=1`)
+    expose_embeddings: bool,
+    /// Whether `POST /color/debug` is enabled (`COLORIZER_DEBUG=1`)
+    debug: bool,
+    /// Prometheus metrics, shared with the request middleware
+    metrics: web::Data<Metrics>,
+    /// Color returned when there is no reference to match (`COLORIZER_FALLBACK_COLOR`)
+    fallback_color: RgbColor,
+    /// Antonym pairs kept apart in `/color` matches (`COLORIZER_ANTONYMS_PATH`)
+    antonyms: Option<Antonyms>,
+    /// Recently rendered `/swatch` images
+    swatch_cache: Mutex<LruCache<SwatchKey, web::Bytes>>,
+    /// `Cache-Control` sent with tagged `GET` responses (`COLORIZER_CACHE_MAX_AGE_SECS`)
+    cache_control: header::CacheControl,
+}
+
+impl AppState {
+    /// Look up a model by name, or the default model when none is given
+    fn model(&self, name: Option<&str>) -> Result<&Arc<Model>, String> {
+        let name = name.unwrap_or(&self.default_model);
+        self.models.get(name).ok_or_else(|| {
+            let available: Vec<&str> = self.models.keys().map(String::as_str).collect();
+            format!("unknown model {:?} (available: {})", name, available.join(", "))
+        })
+    }
+
+    /// Check the input language when strict mode is on
+    fn check_language(&self, text: &str) -> Result<(), String> {
+        if self.strict_english {
+            lang::check_english(text)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn default_model(&self) -> &Arc<Model> {
+        &self.models[&self.default_model]
+    }
+
+    /// A `304 Not Modified` if the request's `If-None-Match` already holds `etag`
+    fn not_modified(&self, req: &HttpRequest, etag: &EntityTag) -> Option<HttpResponse> {
+        let fresh = match req.get_header::<header::IfNoneMatch>() {
+            Some(header::IfNoneMatch::Any) => true,
+            Some(header::IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+            None => false,
+        };
+        fresh.then(|| {
+            let mut response = HttpResponse::NotModified().finish();
+            self.insert_cache_headers(response.headers_mut(), etag);
+            response
+        })
+    }
+
+    /// Tag a successful response with `etag` and the caching policy; errors are left uncached
+    fn cacheable(&self, mut response: HttpResponse, etag: &EntityTag) -> HttpResponse {
+        if response.status().is_success() {
+            self.insert_cache_headers(response.headers_mut(), etag);
+        }
+        response
+    }
+
+    fn insert_cache_headers(&self, headers: &mut header::HeaderMap, etag: &EntityTag) {
+        if let Ok(value) = header::ETag(etag.clone()).try_into_value() {
+            headers.insert(header::ETAG, value);
+        }
+        if let Ok(value) = self.cache_control.clone().try_into_value() {
+            headers.insert(header::CACHE_CONTROL, value);
+        }
+        // `/color` answers `Accept: text/plain` differently, so caches must key on it
+        headers.insert(header::VARY, header::HeaderValue::from_static("accept"));
+    }
+}
+
+/// Weak ETag of a `GET` response that is fully determined by the request (path, query string and
+/// `Accept`) and the model's state. A reload that changes the palette changes every tag.
+fn input_etag(req: &HttpRequest, model: &Model) -> EntityTag {
+    let accept = req.headers().get(header::ACCEPT).map_or(&b""[..], |v| v.as_bytes());
+    let model_modified = model
+        .model_modified
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos());
+
+    let mut input = Vec::new();
+    for part in [req.path().as_bytes(), req.query_string().as_bytes(), accept] {
+        input.extend_from_slice(part);
+        input.push(0);
+    }
+    input.extend_from_slice(&model.references().content_hash.to_le_bytes());
+    input.extend_from_slice(&model_modified.to_le_bytes());
+    EntityTag::new_weak(format!("{:016x}", content_hash(&input)))
+}
+
+/// Squared Euclidean distance between two colors in RGB space
+fn rgb_distance_squared(a: RgbColor, b: RgbColor) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+/// Softmax of `scores / temperature`; the result is positive and sums to 1.
+///
+/// The max is subtracted before exponentiating so large scores or small temperatures can't overflow.
+fn softmax(scores: &[f32], temperature: f32) -> Vec<f32> {
+    let max = scores.iter().copied().fold(f32::MIN, f32::max);
+    let weights: Vec<f32> = scores.iter().map(|&s| ((s - max) / temperature).exp()).collect();
+    let total: f32 = weights.iter().sum();
+    weights.into_iter().map(|w| w / total).collect()
+}
+
+/// Blend colors by a softmax over their similarities, so weights are always positive and sum to 1
+fn blend_colors(matches: &[(RgbColor, f32)], space: BlendSpace) -> RgbColor {
+    if matches.is_empty() {
+        return (0, 0, 0);
+    }
+
+    let similarities: Vec<f32> = matches.iter().map(|&(_, sim)| sim).collect();
+    let weights = softmax(&similarities, 1.0);
+    if let BlendSpace::Hsl = space {
+        let rgbs: Vec<RgbColor> = matches.iter().map(|&(rgb, _)| rgb).collect();
+        return adjust::blend_hsl(&rgbs, &weights);
+    }
+
+    let mix = |channel: fn(&RgbColor) -> u8| {
+        let value: f32 = matches
+            .iter()
+            .zip(&weights)
+            .map(|((rgb, _), w)| channel(rgb) as f32 * w)
+            .sum::<f32>();
+        value.round().clamp(0.0, 255.0) as u8
+    };
+
+    (mix(|c| c.0), mix(|c| c.1), mix(|c| c.2))
+}
+
+/// POST /color → returns the closest color for input text, or the `k` closest when `k > 1`
+#[utoipa::path(
+    summary = "Closest color for a text",
+    params(
+        ColorQuery,
+        (
+            "Idempotency-Key" = Option<String>,
+            Header,
+            min_length = 1,
+            max_length = 255,
+            description = "Replay the stored response of an earlier identical request with this key",
+        ),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "One color, `k` ranked colors, or one color per sentence",
+            body = openapi::ColorResponse,
+        ),
+        (status = 400, description = "Empty text or invalid options", body = ErrorOutput),
+        (status = 401, description = "Missing or wrong API key", body = ErrorOutput),
+        (
+            status = 422,
+            description = "The text has fewer than COLORIZER_MIN_TOKENS tokens, or the model produced a non-finite \
+                           embedding for it",
+            body = ErrorOutput,
+        ),
+        (status = 429, description = "Rate limit exceeded", body = ErrorOutput),
+        (status = 504, description = "Inference timed out", body = ErrorOutput),
+    ),
+)]
+#[post("/color")]
+async fn color(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    query: web::Query<ColorQuery>,
+    input: web::Json<TextInput>,
+) -> impl Responder {
+    color_text(&req, &data, &query, input.into_inner()).await
+}
+
+/// GET /color?text=ocean → same as the POST, with the text and options in the query string
+#[utoipa::path(
+    summary = "Closest color for a text given in the query string",
+    params(
+        TextQuery,
+        ColorQuery,
+        (
+            "Idempotency-Key" = Option<String>,
+            Header,
+            min_length = 1,
+            max_length = 255,
+            description = "Replay the stored response of an earlier identical request with this key",
+        ),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "One color, `k` ranked colors, or one color per sentence",
+            body = openapi::ColorResponse,
+        ),
+        (status = 304, description = "The client's copy, named by If-None-Match, is current"),
+        (status = 400, description = "Empty text or invalid options", body = ErrorOutput),
+        (status = 401, description = "Missing or wrong API key", body = ErrorOutput),
+        (
+            status = 422,
+            description = "The text has fewer than COLORIZER_MIN_TOKENS tokens, or the model produced a non-finite \
+                           embedding for it",
+            body = ErrorOutput,
+        ),
+        (status = 429, description = "Rate limit exceeded", body = ErrorOutput),
+        (status = 504, description = "Inference timed out", body = ErrorOutput),
+    ),
+)]
+#[get("/color")]
+async fn color_get(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    query: web::Query<ColorQuery>,
+    input: web::Query<TextQuery>,
+) -> impl Responder {
+    // An unknown model is reported by `color_text`
+    let etag = data.model(input.model.as_deref()).ok().map(|model| input_etag(&req, model));
+    if let Some(not_modified) = etag.as_ref().and_then(|etag| data.not_modified(&req, etag)) {
+        return not_modified;
+    }
+    let response = color_text(&req, &data, &query, input.into_inner().into()).await;
+    match &etag {
+        Some(etag) => data.cacheable(response, etag),
+        None => response,
+    }
+}
+
+/// Shared body of `POST /color` and `GET /color`
+async fn color_text(req: &HttpRequest, data: &AppState, query: &ColorQuery, input: TextInput) -> HttpResponse {
+    if input.k == 0 {
+        return error_response(StatusCode::BAD_REQUEST, "k must be at least 1");
+    }
+    if input.text.trim().is_empty() {
+        return ColorizerError::EmptyInput.error_response();
+    }
+    if let Err(e) = data.check_language(&input.text) {
+        return error_response(StatusCode::UNPROCESSABLE_ENTITY, e);
+    }
+    if let Err(e) = query.validate() {
+        return error_response(StatusCode::BAD_REQUEST, e);
+    }
+    let model = match data.model(input.model.as_deref()) {
+        Ok(model) => model,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let refs = model.references();
+    if let Err(e) = check_extra_refs(&input) {
+        return error_response(StatusCode::BAD_REQUEST, e);
+    }
+    let ref_count = refs.embeddings.len() + input.extra_refs.len();
+    let k = input.k.min(ref_count).max(1);
+    if input.split == SplitMode::Sentence && input.k > 1 {
+        return error_response(StatusCode::BAD_REQUEST, "split cannot be combined with k > 1");
+    }
+    if let Some(rank) = query.rank {
+        if input.k > 1 || input.blend || input.split == SplitMode::Sentence {
+            return error_response(StatusCode::BAD_REQUEST, "rank cannot be combined with k > 1, blend or split");
+        }
+        if rank > ref_count {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                format!("rank must be at most the reference count {}, got {}", ref_count, rank),
+            );
+        }
+    }
+
+    // Each distinct extra word is embedded once, however often it's listed
+    let mut extras = Vec::with_capacity(input.extra_refs.len());
+    let mut extra_embeddings: HashMap<&str, Vec<f32>> = HashMap::new();
+    for extra in &input.extra_refs {
+        let embedding = match extra_embeddings.get(extra.word.as_str()) {
+            Some(embedding) => embedding.clone(),
+            None => match model.embed_blocking(&extra.word).await {
+                Ok(embedding) => {
+                    extra_embeddings.insert(&extra.word, embedding.clone());
+                    embedding
+                }
+                Err(e) => return e.error_response(),
+            },
+        };
+        extras.push(refs.extra_reference(extra.word.clone(), embedding, extra.color));
+    }
+    let entry = |i: usize| refs.entry(&extras, i);
+
+    if input.split == SplitMode::Sentence {
+        let sentences = sentence::split_sentences(&input.text);
+        if sentences.is_empty() {
+            return error_response(StatusCode::BAD_REQUEST, "text contains no sentences");
+        }
+        if sentences.len() > MAX_BATCH_SIZE {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                format!("too many sentences: got {}, maximum is {}", sentences.len(), MAX_BATCH_SIZE),
+            );
+        }
+
+        let mut sentence_embs = Vec::with_capacity(sentences.len());
+        for sentence in &sentences {
+            match model.embed_blocking(sentence).await {
+                Ok(emb) => sentence_embs.push(emb),
+                Err(e) => return e.error_response(),
+            }
+        }
+        let entries: Vec<SentenceColor> = sentences
+            .iter()
+            .zip(refs.best_matches(sentence_embs))
+            .map(|(sentence, m)| {
+                let rgb = query.adjust(m.map_or(data.fallback_color, |m| refs.embeddings[m.index].color));
+                SentenceColor {
+                    sentence,
+                    color: ColorOutput::new(rgb).with_space(query.space).with_name(query.name),
+                }
+            })
+            .collect();
+        return HttpResponse::Ok().json(entries);
+    }
+
+    if input.k == 1 {
+        match model.is_out_of_vocabulary(&input.text) {
+            Ok(true) => return hash_color_response(req, data, query, &input),
+            Ok(false) => {}
+            Err(e) => return e.error_response(),
+        }
+    }
+
+    match model.embed_blocking(&input.text).await {
+        Ok(sentence_emb) => {
+            if input.k == 1 {
+                let blend_n = if input.blend { BLEND_TOP_N } else { 1 };
+                let rank = query.rank.unwrap_or(1);
+                let wanted = blend_n.max(rank).max(query.distribution.unwrap_or(0));
+                let mut top = refs.top_matches_with(sentence_emb, wanted, &extras);
+                if let Some(antonyms) = &data.antonyms {
+                    antonyms.separate(&mut top, |i| entry(i).word.as_str());
+                }
+                // The match the color comes from: the best one unless `?rank=` asks for another
+                let chosen = top.get(rank - 1);
+                let matched = match input.min_similarity {
+                    Some(min) => chosen.is_some_and(|m| m.similarity >= min),
+                    None => true,
+                };
+
+                let best_color = if !matched {
+                    input.fallback
+                } else if input.blend && !top.is_empty() {
+                    let weighted: Vec<(RgbColor, f32)> = top
+                        .iter()
+                        .take(BLEND_TOP_N)
+                        .map(|m| (entry(m.index).color, m.similarity))
+                        .collect();
+                    blend_colors(&weighted, query.blend_space)
+                } else {
+                    chosen.map_or(data.fallback_color, |m| entry(m.index).color)
+                };
+                let best_color = match input.jitter {
+                    0 => best_color,
+                    amount => adjust::jitter(best_color, input.seed, amount),
+                };
+                let best_color = query.adjust(best_color);
+                let (best_color, contrast_ratio) = match input.bg {
+                    Some(bg) => {
+                        let (rgb, ratio) = adjust::ensure_contrast(best_color, bg, adjust::MIN_CONTRAST);
+                        (rgb, Some(ratio))
+                    }
+                    None => (best_color, None),
+                };
+
+                if let Some(best) = top.first() {
+                    data.metrics.best_similarity.observe(best.similarity as f64);
+                }
+                ColorLog {
+                    text_len: input.text.chars().count(),
+                    text: data.log_text.then(|| input.text.clone()),
+                    rgb: best_color,
+                    word: chosen.map(|m| entry(m.index).word.clone()),
+                    similarity: chosen.map(|m| m.similarity),
+                }
+                .attach(req);
+
+                let explained = chosen.filter(|_| query.explain);
+                let distribution = query.distribution.map(|n| {
+                    let top_n = &top[..n.min(top.len())];
+                    let similarities: Vec<f32> = top_n.iter().map(|m| m.similarity).collect();
+                    top_n
+                        .iter()
+                        .zip(softmax(&similarities, query.temp))
+                        .map(|(m, probability)| WordProbability {
+                            word: entry(m.index).word.clone(),
+                            probability,
+                        })
+                        .collect()
+                });
+                let output = ColorOutput {
+                    contrast_ratio,
+                    distribution,
+                    word: explained.map(|m| entry(m.index).word.clone()),
+                    similarity: explained.map(|m| m.similarity),
+                    matched: input.min_similarity.map(|_| matched),
+                    ..ColorOutput::new(best_color)
+                }
+                .with_space(query.space)
+                .with_name(query.name);
+                let (format, plain) = query.resolve(req);
+                return color_response(output, format, plain);
+            }
+
+            let mut matches = refs.top_matches_with(sentence_emb, k, &extras);
+            if let Some(antonyms) = &data.antonyms {
+                antonyms.separate(&mut matches, |i| entry(i).word.as_str());
+            }
+
+            let ranked: Vec<RankedColor> = matches
+                .iter()
+                .map(|m| {
+                    let ref_emb = entry(m.index);
+                    let (r, g, b) = query.adjust(ref_emb.color);
+                    RankedColor {
+                        r,
+                        g,
+                        b,
+                        similarity: m.similarity,
+                        word: (!ref_emb.word.is_empty()).then(|| ref_emb.word.clone()),
+                    }
+                })
+                .collect();
+            HttpResponse::Ok().json(ranked)
+        }
+        Err(e) => e.error_response(),
+    }
+}
+
+/// `/color` answer for text that's mostly unknown tokens: a color derived from a hash of the raw
+/// text, flagged `"fallback": true`, rather than whatever reference the meaningless embedding lands near
+fn hash_color_response(req: &HttpRequest, data: &AppState, query: &ColorQuery, input: &TextInput) -> HttpResponse {
+    let [r, g, b, ..] = content_hash(input.text.trim().as_bytes()).to_le_bytes();
+    let rgb = query.adjust((r, g, b));
+    let (rgb, contrast_ratio) = match input.bg {
+        Some(bg) => {
+            let (rgb, ratio) = adjust::ensure_contrast(rgb, bg, adjust::MIN_CONTRAST);
+            (rgb, Some(ratio))
+        }
+        None => (rgb, None),
+    };
+    ColorLog {
+        text_len: input.text.chars().count(),
+        text: data.log_text.then(|| input.text.clone()),
+        rgb,
+        word: None,
+        similarity: None,
+    }
+    .attach(req);
+
+    let output = ColorOutput {
+        contrast_ratio,
+        fallback: Some(true),
+        ..ColorOutput::new(rgb)
+    }
+    .with_space(query.space)
+    .with_name(query.name);
+    let (format, plain) = query.resolve(req);
+    color_response(output, format, plain)
+}
+
+/// Validate `extra_refs`: bounded in number, non-empty words, and not mixed with `split`
+fn check_extra_refs(input: &TextInput) -> Result<(), String> {
+    if input.extra_refs.is_empty() {
+        return Ok(());
+    }
+    if input.split == SplitMode::Sentence {
+        return Err("extra_refs cannot be combined with split".to_string());
+    }
+    if input.extra_refs.len() > MAX_EXTRA_REFS {
+        return Err(format!(
+            "too many extra_refs: got {}, maximum is {}",
+            input.extra_refs.len(),
+            MAX_EXTRA_REFS
+        ));
+    }
+    match input.extra_refs.iter().position(|extra| extra.word.trim().is_empty()) {
+        Some(i) => Err(format!("extra_refs[{}].word must not be empty", i)),
+        None => Ok(()),
+    }
+}
+
+/// Validate the texts of a batch request, returning the status and message to reject it with
+fn check_batch(data: &AppState, texts: &[String], max_size: usize) -> Result<(), (StatusCode, String)> {
+    if texts.len() > max_size {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("too many texts: got {}, maximum is {}", texts.len(), max_size),
+        ));
+    }
+    if let Some(i) = texts.iter().position(|t| t.trim().is_empty()) {
+        return Err((StatusCode::BAD_REQUEST, format!("texts[{}] must not be empty", i)));
+    }
+    for (i, text) in texts.iter().enumerate() {
+        if let Err(e) = data.check_language(text) {
+            return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("texts[{}]: {}", i, e)));
+        }
+    }
+    Ok(())
+}
+
+/// POST /colors → returns the closest color for each input text, in order
+#[utoipa::path(
+    summary = "Closest color for each text, in order",
+    params(ColorQuery),
+    responses(
+        (status = 200, description = "One color per text", body = openapi::ColorsResponse),
+        (status = 400, description = "Too many texts, an empty text or invalid options", body = ErrorOutput),
+        (status = 401, description = "Missing or wrong API key", body = ErrorOutput),
+    ),
+)]
+#[post("/colors")]
+async fn colors(
+    data: web::Data<AppState>,
+    query: web::Query<ColorQuery>,
+    input: web::Json<BatchInput>,
+) -> impl Responder {
+    if let Err((status, e)) = check_batch(&data, &input.texts, MAX_BATCH_SIZE) {
+        return error_response(status, e);
+    }
+    if let Err(e) = query.validate() {
+        return error_response(StatusCode::BAD_REQUEST, e);
+    }
+
+    let model = match data.model(input.model.as_deref()) {
+        Ok(model) => model,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let refs = model.references();
+
+    let mut sentence_embs = Vec::with_capacity(input.texts.len());
+    for text in &input.texts {
+        match model.embed_blocking(text).await {
+            Ok(emb) => sentence_embs.push(emb),
+            Err(e) => return e.error_response(),
+        }
+    }
+
+    let best_colors = refs
+        .best_matches(sentence_embs)
+        .into_iter()
+        .map(|m| query.adjust(m.map_or(data.fallback_color, |m| refs.embeddings[m.index].color)));
+
+    match query.format.unwrap_or_default() {
+        ColorFormat::Rgb => {
+            let outputs: Vec<ColorOutput> = best_colors
+                .map(|rgb| ColorOutput::new(rgb).with_space(query.space).with_name(query.name))
+                .collect();
+            HttpResponse::Ok().json(outputs)
+        }
+        format => {
+            let outputs: Vec<String> = best_colors.map(|rgb| format_color(rgb, format)).collect();
+            HttpResponse::Ok().json(outputs)
+        }
+    }
+}
+
+/// POST /colors/stream → like `/colors`, but writes one JSON color per line as each text is done.
+///
+/// Validation errors are returned as a normal JSON error before streaming starts. If inference
+/// fails mid-stream the status is already sent, so the last line is `{"error": ...}` instead.
+#[utoipa::path(
+    summary = "Like /colors, but writes one JSON color per line as each text is done",
+    params(ColorQuery),
+    responses(
+        (
+            status = 200,
+            description = "Newline-delimited colors; a failure mid-stream ends with an error line",
+            body = ColorOutput,
+            content_type = "application/x-ndjson",
+        ),
+        (status = 400, description = "Too many texts, an empty text or invalid options", body = ErrorOutput),
+        (status = 401, description = "Missing or wrong API key", body = ErrorOutput),
+    ),
+)]
+#[post("/colors/stream")]
+async fn colors_stream(
+    data: web::Data<AppState>,
+    query: web::Query<ColorQuery>,
+    input: web::Json<BatchInput>,
+) -> impl Responder {
+    if let Err((status, e)) = check_batch(&data, &input.texts, MAX_STREAM_BATCH_SIZE) {
+        return error_response(status, e);
+    }
+    if let Err(e) = query.validate() {
+        return error_response(StatusCode::BAD_REQUEST, e);
+    }
+
+    let input = input.into_inner();
+    let query = query.into_inner();
+    let model = match data.model(input.model.as_deref()) {
+        Ok(model) => Arc::clone(model),
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let refs = model.references();
+    let fallback_color = data.fallback_color;
+
+    // State is the remaining texts and whether an error line was just sent, which ends the stream
+    let lines = stream::unfold((input.texts.into_iter().enumerate(), false), move |(mut texts, failed)| {
+        let model = Arc::clone(&model);
+        let refs = Arc::clone(&refs);
+        async move {
+            if failed {
+                return None;
+            }
+            let (i, text) = texts.next()?;
+            let (line, failed) = match model.embed_blocking(&text).await {
+                Ok(emb) => {
+                    let rgb = refs
+                        .top_matches(emb, 1)
+                        .first()
+                        .map_or(fallback_color, |m| refs.embeddings[m.index].color);
+                    let rgb = query.adjust(rgb);
+                    let line = match query.format.unwrap_or_default() {
+                        ColorFormat::Rgb => {
+                            let output = ColorOutput::new(rgb).with_space(query.space).with_name(query.name);
+                            serde_json::to_string(&output)
+                        }
+                        format => serde_json::to_string(&format_color(rgb, format)),
+                    };
+                    (line, false)
+                }
+                Err(e) => {
+                    let error = format!("texts[{}]: {}", i, e);
+                    (serde_json::to_string(&ErrorOutput { error }), true)
+                }
+            };
+            let mut line = line.unwrap_or_default();
+            line.push('\n');
+            Some((Ok::<_, actix_web::Error>(web::Bytes::from(line)), (texts, failed)))
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        // Compression would buffer lines until the encoder fills a block, defeating the streaming
+        .insert_header(header::ContentEncoding::Identity)
+        .streaming(lines)
+}
+
+/// POST /nearest-word → returns the reference word whose color is closest to the given RGB value
+#[utoipa::path(
+    summary = "Reference word whose color is closest to an RGB value",
+    responses(
+        (status = 200, description = "Closest reference word", body = NearestWordOutput),
+        (status = 503, description = "No references loaded", body = ErrorOutput),
+    ),
+)]
+#[post("/nearest-word")]
+async fn nearest_word(data: web::Data<AppState>, input: web::Json<RgbInput>) -> impl Responder {
+    let target = (input.r, input.g, input.b);
+    let refs = data.default_model().references();
+
+    // Squared distances order the same as distances, so the sqrt is only taken for the winner;
+    // ties go to the lexicographically first word
+    let nearest = refs
+        .embeddings
+        .iter()
+        .enumerate()
+        .min_by_key(|&(i, r)| (rgb_distance_squared(target, r.color), refs.word_ranks[i]));
+
+    match nearest {
+        Some((_, r)) => HttpResponse::Ok().json(NearestWordOutput {
+            word: &r.word,
+            r: r.color.0,
+            g: r.color.1,
+            b: r.color.2,
+            distance: (rgb_distance_squared(target, r.color) as f32).sqrt(),
+        }),
+        None => error_response(StatusCode::SERVICE_UNAVAILABLE, "no reference colors loaded"),
+    }
+}
+
+/// POST /palette/nearest → the reference word whose embedding is closest to a candidate word, so
+/// palette authors can spot a word that would duplicate an existing entry
+#[utoipa::path(
+    summary = "Reference word whose embedding is closest to a candidate word",
+    responses(
+        (status = 200, description = "Closest reference word", body = RankedColor),
+        (status = 400, description = "Empty word or unknown model", body = ErrorOutput),
+        (status = 401, description = "Missing or wrong API key", body = ErrorOutput),
+        (status = 503, description = "No references loaded", body = ErrorOutput),
+    ),
+)]
+#[post("/palette/nearest")]
+async fn palette_nearest(data: web::Data<AppState>, input: web::Json<WordInput>) -> impl Responder {
+    if input.word.trim().is_empty() {
+        return ColorizerError::EmptyInput.error_response();
+    }
+    let model = match data.model(input.model.as_deref()) {
+        Ok(model) => model,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let refs = model.references();
+    if refs.embeddings.is_empty() {
+        return error_response(StatusCode::SERVICE_UNAVAILABLE, "no reference colors loaded");
+    }
+
+    match model.embed_blocking(&input.word).await {
+        Ok(word_emb) => {
+            let best = refs.top_matches(word_emb, 1)[0];
+            let ref_emb = &refs.embeddings[best.index];
+            let (r, g, b) = ref_emb.color;
+            HttpResponse::Ok().json(RankedColor {
+                r,
+                g,
+                b,
+                similarity: best.similarity,
+                word: Some(ref_emb.word.clone()),
+            })
+        }
+        Err(e) => e.error_response(),
+    }
+}
+
+/// POST /gradient → colors each whitespace-separated word, optionally interpolating between them
+#[utoipa::path(
+    summary = "One color per word of a text",
+    params(GradientQuery),
+    responses(
+        (status = 200, description = "Colors in word order", body = Vec<GradientEntry>),
+        (status = 400, description = "Empty text or invalid options", body = ErrorOutput),
+        (status = 401, description = "Missing or wrong API key", body = ErrorOutput),
+    ),
+)]
+#[post("/gradient")]
+async fn gradient(
+    data: web::Data<AppState>,
+    query: web::Query<GradientQuery>,
+    input: web::Json<GradientInput>,
+) -> impl Responder {
+    let words: Vec<&str> = input.text.split_whitespace().collect();
+    if words.is_empty() {
+        return ColorizerError::EmptyInput.error_response();
+    }
+    if let Err(e) = data.check_language(&input.text) {
+        return error_response(StatusCode::UNPROCESSABLE_ENTITY, e);
+    }
+    if words.len() > MAX_BATCH_SIZE {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            format!("too many words: got {}, maximum is {}", words.len(), MAX_BATCH_SIZE),
+        );
+    }
+    if query.smooth > MAX_GRADIENT_STEPS {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            format!("smooth must be at most {}, got {}", MAX_GRADIENT_STEPS, query.smooth),
+        );
+    }
+
+    let model = match data.model(input.model.as_deref()) {
+        Ok(model) => model,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let refs = model.references();
+
+    // Repeated words hit the embedding cache
+    let mut word_embs = Vec::with_capacity(words.len());
+    for word in &words {
+        match model.embed_blocking(word).await {
+            Ok(emb) => word_embs.push(emb),
+            Err(e) => return e.error_response(),
+        }
+    }
+    let word_colors: Vec<RgbColor> = refs
+        .best_matches(word_embs)
+        .into_iter()
+        .map(|m| m.map_or(data.fallback_color, |m| refs.embeddings[m.index].color))
+        .collect();
+
+    let mut entries = Vec::with_capacity(words.len() + (words.len() - 1) * query.smooth);
+    for (i, (word, &rgb)) in words.iter().zip(&word_colors).enumerate() {
+        if i > 0 {
+            let prev = word_colors[i - 1];
+            for step in 1..=query.smooth {
+                let t = step as f32 / (query.smooth + 1) as f32;
+                let mixed = if query.dither {
+                    adjust::interpolate_dithered(prev, rgb, t, entries.len())
+                } else {
+                    adjust::interpolate(prev, rgb, t)
+                };
+                entries.push(GradientEntry {
+                    word: None,
+                    color: ColorOutput::new(mixed),
+                });
+            }
+        }
+        entries.push(GradientEntry {
+            word: Some(word),
+            color: ColorOutput::new(rgb),
+        });
+    }
+
+    HttpResponse::Ok().json(entries)
+}
+
+/// POST /interpolate → colors evenly spaced points on the line between two texts' embeddings
+#[utoipa::path(
+    summary = "Colors along the path between two texts in embedding space",
+    responses(
+        (status = 200, description = "Colors from `from` to `to`", body = Vec<InterpolationEntry>),
+        (status = 400, description = "Empty text or invalid step count", body = ErrorOutput),
+        (status = 401, description = "Missing or wrong API key", body = ErrorOutput),
+    ),
+)]
+#[post("/interpolate")]
+async fn interpolate(data: web::Data<AppState>, input: web::Json<InterpolateInput>) -> impl Responder {
+    for (field, text) in [("from", &input.from), ("to", &input.to)] {
+        if text.trim().is_empty() {
+            return error_response(StatusCode::BAD_REQUEST, format!("{} must not be empty", field));
+        }
+        if let Err(e) = data.check_language(text) {
+            return error_response(StatusCode::UNPROCESSABLE_ENTITY, format!("{}: {}", field, e));
+        }
+    }
+    if !(2..=MAX_INTERPOLATION_STEPS).contains(&input.steps) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            format!("steps must be between 2 and {}, got {}", MAX_INTERPOLATION_STEPS, input.steps),
+        );
+    }
+
+    let model = match data.model(input.model.as_deref()) {
+        Ok(model) => model,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let refs = model.references();
+
+    let mut endpoints = Vec::with_capacity(2);
+    for text in [&input.from, &input.to] {
+        match model.embed_blocking(text).await {
+            Ok(mut emb) => {
+                // For cosine, normalize first so a longer embedding doesn't pull the midpoint
+                // towards its end; other metrics score the raw magnitudes
+                if refs.metric.normalizes() {
+                    l2_normalize(&mut emb);
+                }
+                endpoints.push(emb);
+            }
+            Err(e) => return e.error_response(),
+        }
+    }
+    let (from, to) = (&endpoints[0], &endpoints[1]);
+
+    let ts: Vec<f32> = (0..input.steps).map(|i| i as f32 / (input.steps - 1) as f32).collect();
+    let points = ts
+        .iter()
+        .map(|&t| from.iter().zip(to).map(|(a, b)| a + (b - a) * t).collect())
+        .collect();
+
+    let entries: Vec<InterpolationEntry> = refs
+        .best_matches(points)
+        .into_iter()
+        .zip(ts)
+        .map(|(m, t)| {
+            let (word, rgb) = m.map_or(("", data.fallback_color), |m| {
+                let r = &refs.embeddings[m.index];
+                (r.word.as_str(), r.color)
+            });
+            InterpolationEntry {
+                t,
+                word,
+                color: ColorOutput::new(rgb),
+            }
+        })
+        .collect();
+
+    HttpResponse::Ok().json(entries)
+}
+
+/// GET /palette → lists every reference word and its color
+#[utoipa::path(
+    summary = "Every reference word and its color",
+    params(ColorQuery),
+    responses(
+        (status = 200, description = "The loaded palette", body = openapi::PaletteResponse),
+        (status = 400, description = "Invalid options", body = ErrorOutput),
+    ),
+)]
+#[get("/palette")]
+async fn palette(data: web::Data<AppState>, query: web::Query<ColorQuery>) -> impl Responder {
+    let refs = data.default_model().references();
+    match query.format.unwrap_or_default() {
+        ColorFormat::Rgb => {
+            let entries: Vec<PaletteEntry> = refs
+                .embeddings
+                .iter()
+                .map(|r| PaletteEntry {
+                    word: &r.word,
+                    color: ColorOutput::new(r.color).with_space(query.space).with_name(query.name),
+                })
+                .collect();
+            HttpResponse::Ok().json(entries)
+        }
+        format => {
+            let entries: Vec<FormattedPaletteEntry> = refs
+                .embeddings
+                .iter()
+                .map(|r| FormattedPaletteEntry {
+                    word: &r.word,
+                    color: format_color(r.color, format),
+                })
+                .collect();
+            HttpResponse::Ok().json(entries)
+        }
+    }
+}
+
+/// GET /palette.png → every reference color as a grid of swatches, in palette order
+#[utoipa::path(
+    summary = "The palette rendered as a grid of swatches",
+    params(PaletteImageQuery),
+    responses(
+        (status = 200, description = "PNG image", content_type = "image/png"),
+        (status = 400, description = "Invalid options", body = ErrorOutput),
+    ),
+)]
+#[get("/palette.png")]
+async fn palette_png(data: web::Data<AppState>, query: web::Query<PaletteImageQuery>) -> impl Responder {
+    let refs = data.default_model().references();
+    let rgbs: Vec<RgbColor> = refs.embeddings.iter().map(|r| r.color).collect();
+
+    // Default to a roughly square grid
+    let cols = query.cols.unwrap_or_else(|| (rgbs.len() as f64).sqrt().ceil().max(1.0) as u32);
+    if !(1..=MAX_PALETTE_COLS).contains(&cols) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            format!("cols must be between 1 and {}, got {}", MAX_PALETTE_COLS, cols),
+        );
+    }
+    let cells = (rgbs.len() as u32).div_ceil(cols).max(1) * cols;
+    if cells > MAX_PALETTE_CELLS {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            format!("grid of {} swatches is too large, use more cols (maximum {})", cells, MAX_PALETTE_CELLS),
+        );
+    }
+
+    let words: Vec<&str> = refs.embeddings.iter().map(|r| r.word.as_str()).collect();
+    match swatch::render_png(&rgbs, query.labels.then_some(&words[..]), cols) {
+        Ok(png) => HttpResponse::Ok().content_type("image/png").body(png),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+/// GET /swatch?text=ocean&size=64 → a solid PNG square of the text's closest color
+#[utoipa::path(
+    summary = "A solid square of the color matched to a text",
+    params(SwatchQuery),
+    responses(
+        (status = 200, description = "PNG image", content_type = "image/png"),
+        (status = 304, description = "The client's copy, named by If-None-Match, is current"),
+        (status = 400, description = "Empty text or invalid size", body = ErrorOutput),
+        (status = 401, description = "Missing or wrong API key", body = ErrorOutput),
+    ),
+)]
+#[get("/swatch")]
+async fn swatch_png(req: HttpRequest, data: web::Data<AppState>, query: web::Query<SwatchQuery>) -> impl Responder {
+    let text = query.text.trim();
+    if text.is_empty() {
+        return ColorizerError::EmptyInput.error_response();
+    }
+    if !(1..=MAX_SWATCH_SIZE).contains(&query.size) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            format!("size must be between 1 and {}, got {}", MAX_SWATCH_SIZE, query.size),
+        );
+    }
+    if let Err(e) = data.check_language(text) {
+        return error_response(StatusCode::UNPROCESSABLE_ENTITY, e);
+    }
+    let model = match data.model(query.model.as_deref()) {
+        Ok(model) => model,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let refs = model.references();
+    let etag = input_etag(&req, model);
+    if let Some(not_modified) = data.not_modified(&req, &etag) {
+        return not_modified;
+    }
+
+    let key = SwatchKey {
+        model: query.model.clone().unwrap_or_else(|| data.default_model.clone()),
+        palette_hash: refs.content_hash,
+        text: text.to_string(),
+        size: query.size,
+    };
+    if let Some(png) = data.swatch_cache.lock().unwrap().get(&key) {
+        return data.cacheable(HttpResponse::Ok().content_type("image/png").body(png.clone()), &etag);
+    }
+
+    let top = match model.embed_blocking(text).await {
+        Ok(emb) => refs.top_matches(emb, 1),
+        Err(e) => return e.error_response(),
+    };
+    let rgb = top.first().map_or(data.fallback_color, |m| refs.embeddings[m.index].color);
+    ColorLog {
+        text_len: text.chars().count(),
+        text: data.log_text.then(|| text.to_string()),
+        rgb,
+        word: top.first().map(|m| refs.embeddings[m.index].word.clone()),
+        similarity: top.first().map(|m| m.similarity),
+    }
+    .attach(&req);
+
+    // Large swatches take a while to encode, so keep them off the async worker
+    let size = query.size;
+    let png = match web::block(move || swatch::render_solid_png(rgb, size)).await {
+        Ok(Ok(png)) => web::Bytes::from(png),
+        Ok(Err(e)) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    data.swatch_cache.lock().unwrap().put(key, png.clone());
+    data.cacheable(HttpResponse::Ok().content_type("image/png").body(png), &etag)
+}
+
+/// POST /color/debug → every reference's similarity to the text, best first, when enabled.
+///
+/// The same scan `/color` runs, without adjustments, so palette tuning can see why a word won.
+#[utoipa::path(
+    summary = "Similarity of every reference word to a text, best first",
+    responses(
+        (status = 200, description = "The scoring table", body = Vec<RankedColor>),
+        (status = 400, description = "Empty text, top of 0 or unknown model", body = ErrorOutput),
+        (status = 401, description = "Missing or wrong API key", body = ErrorOutput),
+        (status = 404, description = "COLORIZER_DEBUG is not set", body = ErrorOutput),
+    ),
+)]
+#[post("/color/debug")]
+async fn color_debug(data: web::Data<AppState>, input: web::Json<DebugInput>) -> impl Responder {
+    if !data.debug {
+        return error_response(StatusCode::NOT_FOUND, "debug endpoint is disabled; set COLORIZER_DEBUG=1 to enable it");
+    }
+    if input.text.trim().is_empty() {
+        return ColorizerError::EmptyInput.error_response();
+    }
+    if input.top == Some(0) {
+        return error_response(StatusCode::BAD_REQUEST, "top must be at least 1");
+    }
+    let model = match data.model(input.model.as_deref()) {
+        Ok(model) => model,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let refs = model.references();
+    let count = input.top.map_or(refs.embeddings.len(), |top| top.min(refs.embeddings.len()));
+
+    match model.embed_blocking(&input.text).await {
+        Ok(sentence_emb) => {
+            let scores: Vec<RankedColor> = refs
+                .top_matches(sentence_emb, count)
+                .iter()
+                .map(|m| {
+                    let ref_emb = &refs.embeddings[m.index];
+                    let (r, g, b) = ref_emb.color;
+                    RankedColor {
+                        r,
+                        g,
+                        b,
+                        similarity: m.similarity,
+                        word: Some(ref_emb.word.clone()),
+                    }
+                })
+                .collect();
+            HttpResponse::Ok().json(scores)
+        }
+        Err(e) => e.error_response(),
+    }
+}
+
+/// POST /embed → returns the raw pooled embedding of the input text, when enabled
+#[utoipa::path(
+    summary = "Raw sentence embedding of a text",
+    responses(
+        (status = 200, description = "The embedding", body = EmbedOutput),
+        (status = 400, description = "Empty text", body = ErrorOutput),
+        (status = 401, description = "Missing or wrong API key", body = ErrorOutput),
+        (status = 404, description = "COLORIZER_EXPOSE_EMBEDDINGS is not set", body = ErrorOutput),
+    ),
+)]
+#[post("/embed")]
+async fn embed(data: web::Data<AppState>, input: web::Json<EmbedInput>) -> impl Responder {
+    if !data.expose_embeddings {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            "embedding endpoint is disabled; set COLORIZER_EXPOSE_EMBEDDINGS=1 to enable it",
+        );
+    }
+    if input.text.trim().is_empty() {
+        return ColorizerError::EmptyInput.error_response();
+    }
+    let model = match data.model(input.model.as_deref()) {
+        Ok(model) => model,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+
+    match model.embed_blocking(&input.text).await {
+        Ok(embedding) => HttpResponse::Ok().json(EmbedOutput {
+            dim: embedding.len(),
+            embedding,
+        }),
+        Err(e) => e.error_response(),
+    }
+}
+
+/// GET /model-info → the ONNX model's input and output names, shapes and types, and its dimension
+#[utoipa::path(
+    summary = "Input and output names, shapes and types of the ONNX model",
+    params(ModelQuery),
+    responses(
+        (status = 200, description = "Model description", body = ModelInfoOutput),
+        (status = 400, description = "Unknown model", body = ErrorOutput),
+        (status = 401, description = "Missing or wrong API key", body = ErrorOutput),
+        (status = 404, description = "COLORIZER_EXPOSE_EMBEDDINGS is not set", body = ErrorOutput),
+    ),
+)]
+#[get("/model-info")]
+async fn model_info(data: web::Data<AppState>, query: web::Query<ModelQuery>) -> impl Responder {
+    if !data.expose_embeddings {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            "model info is disabled; set COLORIZER_EXPOSE_EMBEDDINGS=1 to enable it",
+        );
+    }
+    let name = query.model.as_deref().unwrap_or(&data.default_model);
+    let model = match data.model(Some(name)) {
+        Ok(model) => model,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    HttpResponse::Ok().json(ModelInfoOutput {
+        model: name,
+        dimension: model.dimension(),
+        io: model.io(),
+    })
+}
+
+/// GET /usage → today's request count per client IP and the daily quota
+#[utoipa::path(
+    summary = "Today's request count per client IP",
+    responses(
+        (status = 200, description = "Usage since midnight UTC", body = UsageOutput),
+        (status = 401, description = "Missing or wrong API key", body = ErrorOutput),
+        (status = 403, description = "Usage is hidden because no API key is set", body = ErrorOutput),
+    ),
+)]
+#[get("/usage")]
+async fn usage_report(usage: web::Data<Usage>, api_key: web::Data<ApiKey>) -> impl Responder {
+    // Client IPs are only shown to callers holding the key
+    if api_key.0.is_none() {
+        return error_response(StatusCode::FORBIDDEN, "usage is hidden; set COLORIZER_API_KEY to enable /usage");
+    }
+    let clients = usage
+        .snapshot()
+        .into_iter()
+        .map(|(ip, requests)| ClientUsage { ip, requests })
+        .collect();
+    HttpResponse::Ok().json(UsageOutput {
+        quota: usage.quota(),
+        resets_in_secs: until_reset().as_secs(),
+        clients,
+    })
+}
+
+/// POST /compare → cosine similarity between the embeddings of two texts
+#[utoipa::path(
+    summary = "Cosine similarity between the embeddings of two texts",
+    responses(
+        (status = 200, description = "Similarity in [-1, 1]", body = CompareOutput),
+        (status = 400, description = "Empty text", body = ErrorOutput),
+        (status = 401, description = "Missing or wrong API key", body = ErrorOutput),
+    ),
+)]
+#[post("/compare")]
+async fn compare(data: web::Data<AppState>, input: web::Json<CompareInput>) -> impl Responder {
+    for (field, text) in [("a", &input.a), ("b", &input.b)] {
+        if text.trim().is_empty() {
+            return error_response(StatusCode::BAD_REQUEST, format!("{} must not be empty", field));
+        }
+        if let Err(e) = data.check_language(text) {
+            return error_response(StatusCode::UNPROCESSABLE_ENTITY, format!("{}: {}", field, e));
+        }
+    }
+    let model = match data.model(input.model.as_deref()) {
+        Ok(model) => model,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+
+    let mut embeddings = Vec::with_capacity(2);
+    for text in [&input.a, &input.b] {
+        match model.embed_blocking(text).await {
+            Ok(embedding) => embeddings.push(embedding),
+            Err(e) => return e.error_response(),
+        }
+    }
+    HttpResponse::Ok().json(CompareOutput {
+        similarity: metric::cosine_similarity(&embeddings[0], &embeddings[1]),
+    })
 }
 
-/// Compute cosine similarity between two embeddings
-fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
-    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
-    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
-    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+/// POST /reload → re-reads a model's reference embeddings from disk and swaps them in.
+///
+/// Only available when `COLORIZER_API_KEY` is set, since it is otherwise unauthenticated.
+#[utoipa::path(
+    summary = "Reload a model's reference embeddings from disk",
+    params(ModelQuery),
+    responses(
+        (status = 200, description = "Reloaded model", body = ReloadOutput),
+        (status = 400, description = "Unknown model", body = ErrorOutput),
+        (status = 401, description = "Missing or wrong API key", body = ErrorOutput),
+        (status = 403, description = "Reloading is disabled because no API key is set", body = ErrorOutput),
+        (status = 500, description = "The reference file couldn't be loaded", body = ErrorOutput),
+        (status = 503, description = "The reference file is empty; the old ones stay in place", body = ErrorOutput),
+    ),
+)]
+#[post("/reload")]
+async fn reload(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    api_key: web::Data<ApiKey>,
+    query: web::Query<ModelQuery>,
+) -> impl Responder {
+    if api_key.0.is_none() {
+        return error_response(StatusCode::FORBIDDEN, "reloading is disabled; set COLORIZER_API_KEY to enable /reload");
+    }
+    let name = query.model.as_deref().unwrap_or(&data.default_model);
+    let model = match data.model(Some(name)) {
+        Ok(model) => Arc::clone(model),
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
 
-    if norm_a == 0.0 || norm_b == 0.0 {
-        0.0
-    } else {
-        dot / (norm_a * norm_b)
+    // Parsing and re-indexing the file is blocking work
+    match web::block(move || model.reload()).await {
+        Ok(Ok(ref_count)) => {
+            log::info!("reload id={} model={:?} ref_count={}", RequestId::of(&req), name, ref_count);
+            HttpResponse::Ok().json(ReloadOutput { model: name, ref_count })
+        }
+        // The old references stay in place, so the server keeps answering; the new file is what's wrong
+        Ok(Err(e)) if e.is::<EmptyReferences>() => {
+            error_response(StatusCode::SERVICE_UNAVAILABLE, format!("reload failed: {}", e))
+        }
+        Ok(Err(e)) => error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("reload failed: {}", e)),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
     }
 }
 
-/// Generate an embedding for a sentence using the tokenizer + ONNX model
-fn get_embedding(
-    tokenizer: &Tokenizer,
-    session: &Session,
-    sentence: &str,
-) -> Result<Vec<f32>, Box<dyn Error + Send + Sync>> {
-    let encoding = tokenizer.encode(sentence, true)?;
-    let input_ids: Vec<i64> = encoding.get_ids().iter().map(|&id| id as i64).collect();
-    let attention_mask: Vec<i64> = encoding.get_attention_mask().iter().map(|&m| m as i64).collect();
-
-    let seq_len = input_ids.len();
-    let input_ids_arr = ndarray::Array2::from_shape_vec((1, seq_len), input_ids)?.into_dyn();
-    let attention_mask_arr = ndarray::Array2::from_shape_vec((1, seq_len), attention_mask)?.into_dyn();
-
-    let input_ids_cow = ndarray::CowArray::from(input_ids_arr);
-    let attention_mask_cow = ndarray::CowArray::from(attention_mask_arr);
-
-    let input_ids_val = Value::from_array(session.allocator(), &input_ids_cow)?;
-    let attention_mask_val = Value::from_array(session.allocator(), &attention_mask_cow)?;
-
-    let outputs = session.run(vec![input_ids_val, attention_mask_val])?;
-    let tensor: OrtOwnedTensor<f32, _> = outputs[0].try_extract()?;
-    let arr = tensor.view();
-
-    // Pooling by averaging token embeddings
-    let summed = arr.index_axis(ndarray::Axis(0), 0).sum_axis(ndarray::Axis(0));
-    let pooled = summed.clone() / summed.len() as f32;
+/// GET /metrics → Prometheus text exposition
+#[utoipa::path(
+    summary = "Prometheus metrics",
+    responses(
+        (status = 200, description = "Prometheus text format", body = String, content_type = "text/plain"),
+    ),
+)]
+#[get("/metrics")]
+async fn metrics_endpoint(metrics: web::Data<Metrics>) -> impl Responder {
+    match metrics.render() {
+        Ok(body) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4; charset=utf-8")
+            .body(body),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
 
-    Ok(pooled.into_raw_vec())
+/// GET /healthz → 200 once every model has run a probe inference, 503 otherwise
+#[utoipa::path(
+    summary = "Readiness of every model",
+    responses(
+        (status = 200, description = "All models ready", body = HealthOutput),
+        (status = 503, description = "A model failed to load", body = HealthOutput),
+    ),
+)]
+#[get("/healthz")]
+async fn healthz(data: web::Data<AppState>) -> impl Responder {
+    let ref_count = data.default_model().references().embeddings.len();
+    if data.models.values().all(|model| model.model_ready) {
+        HttpResponse::Ok().json(HealthOutput { status: "ok", ref_count })
+    } else {
+        HttpResponse::ServiceUnavailable().json(HealthOutput {
+            status: "unavailable",
+            ref_count,
+        })
+    }
 }
 
-/// POST /color → returns the closest color for input text
-#[post("/color")]
-async fn color(data: web::Data<AppState>, input: web::Json<TextInput>) -> impl Responder {
-    match get_embedding(&data.tokenizer, &data.session, &input.text) {
-        Ok(sentence_emb) => {
-            let (mut best_color, mut best_sim) = ((0, 0, 0), f32::MIN);
+/// GET /version → crate version plus the palette hash, model timestamp and reference count of each model
+#[utoipa::path(
+    summary = "Crate version and identifiers of each loaded model",
+    responses(
+        (status = 200, description = "Version information", body = VersionOutput),
+    ),
+)]
+#[get("/version")]
+async fn version(data: web::Data<AppState>) -> impl Responder {
+    let models = data
+        .models
+        .iter()
+        .map(|(name, model)| {
+            let refs = model.references();
+            let model_modified = model
+                .model_modified
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs());
+            let entry = ModelVersion {
+                palette_hash: format!("{:016x}", refs.content_hash),
+                model_modified,
+                ref_count: refs.embeddings.len(),
+            };
+            (name.as_str(), entry)
+        })
+        .collect();
 
-            for ref_emb in &data.ref_embeddings {
-                let sim = cosine_similarity(&sentence_emb, &ref_emb.embedding);
-                if sim > best_sim {
-                    best_sim = sim;
-                    best_color = ref_emb.color;
-                }
-            }
+    HttpResponse::Ok().json(VersionOutput {
+        version: env!("CARGO_PKG_VERSION"),
+        models,
+    })
+}
 
-            HttpResponse::Ok().json(ColorOutput {
-                r: best_color.0,
-                g: best_color.1,
-                b: best_color.2,
-            })
-        }
-        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
-    }
+/// GET /openapi.json → OpenAPI 3 description of every endpoint
+#[get("/openapi.json")]
+async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(openapi::ApiDoc::openapi())
 }
 
 /// GET / → serves `static/index.html` if available
@@ -114,46 +2144,1218 @@ async fn index(req: HttpRequest) -> actix_web::Result<impl Responder> {
     }
 }
 
+/// Build the CORS policy; `*` allows any origin, no origins means same-origin only
+fn cors(allowed_origins: &[String]) -> Cors {
+    let request_id = header::HeaderName::from_static(REQUEST_ID_HEADER);
+    let idempotency_key = header::HeaderName::from_static(IDEMPOTENCY_KEY_HEADER);
+    let replayed = header::HeaderName::from_static(REPLAYED_HEADER);
+    let cors = Cors::default()
+        .allowed_methods(vec!["GET", "POST"])
+        .allowed_headers(vec![header::CONTENT_TYPE, header::AUTHORIZATION, request_id.clone(), idempotency_key])
+        .expose_headers(vec![request_id, replayed])
+        .max_age(3600);
+
+    if allowed_origins.iter().any(|origin| origin == "*") {
+        cors.allow_any_origin()
+    } else {
+        allowed_origins.iter().fold(cors, |cors, origin| cors.allowed_origin(origin))
+    }
+}
+
+/// Run a startup step up to `COLORIZER_LOAD_ATTEMPTS` times, waiting `COLORIZER_LOAD_RETRY_MS`
+/// (doubled after each failure) in between, so files on a volume that mounts late still load
+fn retry_startup<T>(
+    what: &str,
+    config: &Config,
+    mut step: impl FnMut() -> Result<T, Box<dyn Error + Send + Sync>>,
+) -> Result<T, Box<dyn Error + Send + Sync>> {
+    let mut delay = Duration::from_millis(config.load_retry_ms);
+    let mut attempt = 1;
+    loop {
+        match step() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < config.load_attempts.get() => {
+                eprintln!(
+                    "⚠️  {} failed (attempt {}/{}): {}; retrying in {}ms",
+                    what,
+                    attempt,
+                    config.load_attempts,
+                    e,
+                    delay.as_millis()
+                );
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 /// Application entrypoint
 #[actix_web::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
-    println!("🚀 Server starting at: http://localhost:8090/");
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    // Load tokenizer + ONNX model
-    let tokenizer = Tokenizer::from_file("models/tokenizer.json")?;
-    let environment = Arc::new(Environment::builder().with_name("default").build()?);
-    let session = SessionBuilder::new(&environment)?.with_model_from_file("models/model.onnx")?;
+    let config_file = load_config_file()?;
+    let profile = load_profile()?;
+    let config = Config::from_env()?;
+    println!("🚀 Server starting at: http://{}:{}/", config.bind_addr, config.port);
+    if let Some(file) = &config_file {
+        let overridden = file.settings.iter().filter(|(_, overridden)| *overridden).count();
+        println!(
+            "  • config file:    {} ({} settings, {} overridden by the environment)",
+            file.path.display(),
+            file.settings.len(),
+            overridden
+        );
+        for (key, overridden) in &file.settings {
+            let source = if *overridden { "environment" } else { "config file" };
+            log::info!("{} from {}", key, source);
+        }
+    }
+    if profile != Profile::Custom {
+        println!("  • profile:        {}", profile);
+    }
+    if let Some(path) = &config.models_file {
+        println!("  • models file:    {}", path.display());
+    }
+    println!("  • pooling:        {}", config.embedding.pooling);
+    if let Some(cls_index) = config.embedding.cls_index {
+        println!("  • CLS index:      {}", cls_index);
+    }
+    println!("  • max tokens:     {}", config.embedding.max_tokens);
+    println!("  • lowercase:      {}", config.embedding.lowercase);
+    if config.embedding.stem {
+        println!("  • stemming:       enabled (English)");
+    }
+    if config.embedding.pre_pool_norm {
+        println!("  • token norm:     L2 before pooling");
+    }
+    if config.embedding.exclude_special {
+        println!("  • special tokens: excluded from pooling");
+    }
+    if !config.embedding.aliases.is_empty() {
+        println!("  • aliases:        {}", config.embedding.aliases.len());
+    }
+    if !config.embedding.idf.is_empty() {
+        println!("  • IDF weights:    {} tokens", config.embedding.idf.len());
+    }
+    println!("  • provider:       {}", config.provider);
+    println!("  • metric:         {}", config.metric);
+    if let Some((r, g, b)) = config.fallback_color {
+        println!("  • fallback color: {},{},{}", r, g, b);
+    }
+    if config.allow_empty_refs {
+        println!("  • empty palettes: allowed");
+    }
+    println!("  • max body:       {} bytes", config.max_body_bytes);
+    if config.min_tokens > 1 {
+        println!("  • min tokens:     {}", config.min_tokens);
+    }
+    if config.unk_threshold < 1.0 {
+        println!("  • unk threshold:  {}", config.unk_threshold);
+    } else {
+        println!("  • unk threshold:  disabled");
+    }
+    if config.inference_timeout_ms > 0 {
+        println!("  • infer timeout:  {}ms", config.inference_timeout_ms);
+    }
+    if config.mock {
+        println!("  • mock:           enabled (no model is loaded; colors are derived from a hash of the text)");
+    }
+    if config.load_attempts.get() > 1 {
+        println!("  • load attempts:  {} ({}ms initial backoff)", config.load_attempts, config.load_retry_ms);
+    }
+    retry_startup("Finding model files", &config, || config.check_files())?;
+
+    // Mock mode never touches ONNX Runtime
+    let environment = if config.mock {
+        None
+    } else {
+        Some(Arc::new(Environment::builder().with_name("default").build()?))
+    };
+    let metrics = web::Data::new(Metrics::new()?);
+
+    // Load every configured model with its tokenizer and reference embeddings
+    let mut models = BTreeMap::new();
+    for (name, spec) in &config.models {
+        let default_marker = if *name == config.default_model { " (default)" } else { "" };
+        println!("  • model {:?}{}:", name, default_marker);
+        if !config.mock {
+            println!("    – tokenizer:      {}", spec.tokenizer.display());
+            println!("    – model:          {}", spec.model.display());
+        }
+        println!("    – ref embeddings: {}", spec.ref_embeddings.display());
+        let model = retry_startup(&format!("Loading model {:?}", name), &config, || {
+            Model::load(environment.as_ref(), spec, &config, metrics.clone())
+        })?;
+
+        // A failed probe already marks the model unavailable, so there is nothing to warm up
+        if model.model_ready && !config.skip_warmup {
+            match model.warm_up() {
+                Ok(elapsed) => println!("    – warmup:         {}ms", elapsed.as_millis()),
+                Err(e) => eprintln!("⚠️  Warmup failed for model {:?}: {}", name, e),
+            }
+        }
+        models.insert(name.clone(), Arc::new(model));
+    }
 
-    // Load reference embeddings
-    let file = File::open("custom/ref_embeddings.json")?;
-    let ref_embeddings: Vec<RefEmbedding> = serde_json::from_reader(file)?;
+    let antonyms = match &config.antonyms_path {
+        Some(path) => {
+            let antonyms = Antonyms::load(path, config.antonym_penalty)?;
+            println!(
+                "  • antonyms:       {} pair(s) from {} (penalty {})",
+                antonyms.pair_count(),
+                path.display(),
+                config.antonym_penalty
+            );
+            Some(antonyms)
+        }
+        None => None,
+    };
 
     // Shared app state
     let state = web::Data::new(AppState {
-        tokenizer,
-        session,
-        ref_embeddings,
+        models,
+        default_model: config.default_model.clone(),
+        rate_limit: config.rate_limit,
+        log_text: config.log_text,
+        strict_english: config.strict_english,
+        expose_embeddings: config.expose_embeddings,
+        debug: config.debug,
+        metrics: metrics.clone(),
+        fallback_color: config.fallback_color.unwrap_or_default(),
+        antonyms,
+        swatch_cache: Mutex::new(LruCache::new(NonZeroUsize::new(SWATCH_CACHE_SIZE).unwrap())),
+        cache_control: header::CacheControl(vec![
+            // Shared caches mustn't hand an authenticated response to other clients
+            if config.api_key.is_some() {
+                header::CacheDirective::Private
+            } else {
+                header::CacheDirective::Public
+            },
+            header::CacheDirective::MaxAge(config.cache_max_age_secs),
+        ]),
     });
 
-    // Rate limiting
+    // Rate limiting; the governor is always built but only wrapped in when enabled
+    match config.rate_limit {
+        Some(limit) => println!(
+            "  • rate limit:     1 request per {}ms, burst {}",
+            limit.milliseconds_per_request, limit.burst
+        ),
+        None => println!("  • rate limit:     disabled"),
+    }
+    let rate_limit = config.rate_limit.unwrap_or(RateLimit {
+        milliseconds_per_request: 1,
+        burst: 1,
+    });
     let governor_conf = GovernorConfigBuilder::default()
-        .milliseconds_per_request(200)
-        .burst_size(10)
+        .milliseconds_per_request(rate_limit.milliseconds_per_request)
+        .burst_size(rate_limit.burst)
         .finish()
         .unwrap();
+    let rate_limited = config.rate_limit.is_some();
+
+    let allowed_origins = config.allowed_origins.clone();
+    if !allowed_origins.is_empty() {
+        println!("  • CORS origins:   {}", allowed_origins.join(", "));
+    }
+
+    if config.strict_english {
+        println!("  • strict lang:    en (non-English input is rejected)");
+    }
+    if config.expose_embeddings {
+        println!("  • raw embeddings: exposed on /embed and /model-info");
+    }
+    if config.debug {
+        println!("  • debug:          scoring table exposed on /color/debug");
+    }
+    if config.api_key.is_some() {
+        println!("  • API key:        required on {}", PROTECTED_PATHS.join(", "));
+    }
+    let api_key = web::Data::new(ApiKey(config.api_key.clone()));
+
+    // Models live in `AppState` behind an `Arc`, so extra workers share them rather than loading copies
+    println!("  • workers:        {}", config.workers);
+    if config.batch_window_ms > 0 {
+        println!("  • batching:       {}ms window, up to {} texts", config.batch_window_ms, config.batch_max);
+    }
+    let keep_alive = match config.keep_alive_secs {
+        0 => {
+            println!("  • keep-alive:     disabled");
+            KeepAlive::Disabled
+        }
+        secs => {
+            println!("  • keep-alive:     {}s", secs);
+            KeepAlive::Timeout(Duration::from_secs(secs))
+        }
+    };
+
+    if config.daily_quota > 0 {
+        println!("  • daily quota:    {} requests per IP", config.daily_quota);
+    }
+    let usage_counts = web::Data::new(Usage::new((config.daily_quota > 0).then_some(config.daily_quota)));
+
+    let max_body_bytes = config.max_body_bytes;
+    let idempotency = web::Data::new(Idempotency::new(
+        Duration::from_secs(config.idempotency_ttl_secs),
+        max_body_bytes,
+    ));
+    let audit_log = match &config.audit_log {
+        Some(path) => {
+            let audit_log = AuditLog::open(path, config.audit_log_max_bytes, config.audit_log_keep)
+                .map_err(|e| format!("cannot open audit log {}: {}", path.display(), e))?;
+            println!(
+                "  • audit log:      {} (rotated at {} bytes, {} kept)",
+                path.display(),
+                config.audit_log_max_bytes,
+                config.audit_log_keep
+            );
+            Some(audit_log)
+        }
+        None => None,
+    };
+    let audit_log = web::Data::new(audit_log);
+    let in_flight = web::Data::new(InFlight::default());
+    let server_in_flight = in_flight.clone();
 
     // Launch server
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
             .app_data(state.clone())
-            .wrap(Governor::new(&governor_conf))
-            .service(Files::new("/static", "./static").show_files_listing())
-            .service(index)
-            .service(color)
+            .app_data(server_in_flight.clone())
+            .app_data(metrics.clone())
+            .app_data(api_key.clone())
+            .app_data(usage_counts.clone())
+            .app_data(idempotency.clone())
+            .app_data(audit_log.clone())
+            .app_data(json_config(max_body_bytes))
+            .app_data(web::PayloadConfig::new(max_body_bytes))
+            .app_data(web::QueryConfig::default().error_handler(query_error_handler))
+            // Innermost, so the ID header is already on the response when `Logger` formats it
+            .wrap(from_fn(assign_request_id))
+            .wrap(Compress::default())
+            .wrap(Logger::new(r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T id=%{x-request-id}o"#))
+            .wrap(cors(&allowed_origins))
+            .wrap(from_fn(track_in_flight))
+            // Registered outside the scope below so scrapes are neither counted nor rate-limited
+            .service(metrics_endpoint)
+            .service(
+                web::scope("")
+                    // Innermost, so replays are still authenticated, counted and rate-limited
+                    .wrap(from_fn(replay_idempotent))
+                    .wrap(from_fn(require_api_key))
+                    // Inside the rate limiter, so requests it rejects don't count toward the quota
+                    .wrap(from_fn(track_usage))
+                    .wrap(Condition::new(rate_limited, Governor::new(&governor_conf)))
+                    .wrap(from_fn(log_colorization))
+                    .wrap(from_fn(record_request_metrics))
+                    .service(Files::new("/static", "./static").show_files_listing())
+                    .service(index)
+                    .service(healthz)
+                    .service(version)
+                    .service(openapi_json)
+                    .service(color)
+                    .service(color_get)
+                    .service(color_debug)
+                    .service(colors)
+                    .service(colors_stream)
+                    .service(gradient)
+                    .service(interpolate)
+                    .service(nearest_word)
+                    .service(palette)
+                    .service(palette_nearest)
+                    .service(palette_png)
+                    .service(swatch_png)
+                    .service(reload)
+                    .service(embed)
+                    .service(model_info)
+                    .service(compare)
+                    .service(usage_report)
+                    .service(ws::ws),
+            )
     })
-    .bind(("0.0.0.0", 8090))?
-    .run()
-    .await?;
+    .workers(config.workers)
+    .keep_alive(keep_alive)
+    .bind((config.bind_addr.as_str(), config.port))?
+    .shutdown_timeout(config.shutdown_timeout_secs)
+    .disable_signals()
+    .run();
+
+    // Signals are handled here so the number of drained requests can be logged
+    actix_web::rt::spawn(shutdown_on_signal(server.handle(), in_flight));
+    server.await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use colorizer::reference::ReferenceSet;
+    use embedder::Embedder;
+    use model::tests::{
+        rgb_references, test_metrics, test_model, test_model_with, test_spec, write_references, TableEmbedder,
+    };
+    use serde_json::{json, Value};
+    use std::sync::atomic::Ordering;
+
+    /// App state serving `model` as the default, with every optional feature off
+    fn state_with(model: Model) -> web::Data<AppState> {
+        web::Data::new(app_state(model))
+    }
+
+    /// Like [`state_with`], for tests that switch a feature on with struct update syntax
+    fn app_state(model: Model) -> AppState {
+        AppState {
+            models: BTreeMap::from([("default".to_string(), Arc::new(model))]),
+            default_model: "default".to_string(),
+            rate_limit: None,
+            log_text: false,
+            strict_english: false,
+            expose_embeddings: false,
+            debug: false,
+            metrics: web::Data::new(Metrics::new().unwrap()),
+            fallback_color: (0, 0, 0),
+            antonyms: None,
+            swatch_cache: Mutex::new(LruCache::new(NonZeroUsize::new(SWATCH_CACHE_SIZE).unwrap())),
+            cache_control: header::CacheControl(vec![header::CacheDirective::Public]),
+        }
+    }
+
+    /// State for a model over red, green and blue that embeds the given words as fixed vectors
+    fn rgb_state(table: &[(&str, Vec<f32>)]) -> web::Data<AppState> {
+        state_with(test_model(Arc::new(TableEmbedder::new(table)), rgb_references()))
+    }
+
+    /// Send `req` to an app with every handler registered, returning the status and JSON body
+    async fn call(state: &web::Data<AppState>, req: TestRequest) -> (StatusCode, Value) {
+        let app = init_service(
+            App::new()
+                .app_data(state.clone())
+                .app_data(web::Data::new(ApiKey(None)))
+                .app_data(web::Data::new(Usage::new(None)))
+                .app_data(json_config(Config::for_tests().max_body_bytes))
+                .app_data(web::QueryConfig::default().error_handler(query_error_handler))
+                .service(color)
+                .service(color_get)
+                .service(color_debug)
+                .service(colors)
+                .service(gradient)
+                .service(interpolate)
+                .service(nearest_word)
+                .service(palette_nearest)
+                .service(embed)
+                .service(model_info)
+                .service(compare),
+        )
+        .await;
+        let response = call_service(&app, req.to_request()).await;
+        let status = response.status();
+        let body = read_body(response).await;
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    fn post(uri: &str, body: Value) -> TestRequest {
+        TestRequest::post().uri(uri).set_json(body)
+    }
+
+    #[test]
+    fn hex_output_is_zero_padded() {
+        assert_eq!(format_color((0, 0, 0), ColorFormat::Hex), "#000000");
+        assert_eq!(format_color((1, 10, 15), ColorFormat::Hex), "#010a0f");
+        assert_eq!(format_color((255, 255, 255), ColorFormat::Hex), "#ffffff");
+        assert_eq!(format_color((0, 0, 0), ColorFormat::Css), "rgb(0,0,0)");
+    }
+
+    #[test]
+    fn equally_similar_colors_blend_to_their_midpoint() {
+        let matches = [((0, 100, 200), 0.7), ((100, 200, 0), 0.7)];
+        assert_eq!(blend_colors(&matches, BlendSpace::Rgb), (50, 150, 100));
+        // 127.5 rounds up rather than truncating
+        assert_eq!(blend_colors(&[((0, 0, 0), 0.2), ((255, 255, 255), 0.2)], BlendSpace::Rgb), (128, 128, 128));
+    }
+
+    #[test]
+    fn blending_in_hsl_keeps_hues_saturated() {
+        let (red, yellow, green) = ((255, 0, 0), (255, 255, 0), (0, 255, 0));
+        let close = |a: RgbColor, b: RgbColor| {
+            a.0.abs_diff(b.0) <= 1 && a.1.abs_diff(b.1) <= 1 && a.2.abs_diff(b.2) <= 1
+        };
+
+        let orange = blend_colors(&[(red, 0.5), (yellow, 0.5)], BlendSpace::Hsl);
+        assert!(close(orange, (255, 128, 0)), "{orange:?}");
+        // Red and green meet at yellow on the hue circle, not at RGB's olive
+        let midpoint = blend_colors(&[(red, 0.5), (green, 0.5)], BlendSpace::Hsl);
+        assert!(close(midpoint, yellow), "{midpoint:?}");
+        assert_eq!(blend_colors(&[(red, 0.5), (green, 0.5)], BlendSpace::Rgb), (128, 128, 0));
+    }
+
+    #[test]
+    fn softmax_sums_to_one_even_for_extreme_scores() {
+        let sum = |p: &[f32]| p.iter().sum::<f32>();
+        let probabilities = softmax(&[0.9, 0.8, 0.1], 1.0);
+        assert!((sum(&probabilities) - 1.0).abs() < 1e-6);
+        assert!(probabilities[0] > probabilities[1] && probabilities[1] > probabilities[2]);
+
+        // Without subtracting the max, e^(1000 / 0.01) would overflow to infinity
+        let probabilities = softmax(&[1000.0, 999.0, -1000.0], 0.01);
+        assert!(probabilities.iter().all(|p| p.is_finite()), "{probabilities:?}");
+        assert!((sum(&probabilities) - 1.0).abs() < 1e-6);
+        assert_eq!(probabilities[0], 1.0);
+
+        assert_eq!(softmax(&[0.5, 0.5], 1.0), vec![0.5, 0.5]);
+    }
+
+    #[actix_web::test]
+    async fn min_similarity_falls_back_below_the_threshold() {
+        let state = rgb_state(&[("fire", vec![1.0, 0.1, 0.0]), ("mud", vec![1.0, 1.0, 1.0])]);
+
+        let (status, body) = call(&state, post("/color", json!({ "text": "fire", "min_similarity": 0.9 }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "r": 255, "g": 0, "b": 0, "matched": true }));
+
+        let request = json!({ "text": "mud", "min_similarity": 0.9, "fallback": [10, 20, 30] });
+        let (status, body) = call(&state, post("/color", request)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "r": 10, "g": 20, "b": 30, "matched": false }));
+    }
+
+    #[actix_web::test]
+    async fn empty_and_whitespace_text_is_rejected_but_an_emoji_is_colored() {
+        let state = rgb_state(&[("🎨", vec![0.0, 0.2, 1.0])]);
+        for text in ["", "  \n\t "] {
+            let (status, body) = call(&state, post("/color", json!({ "text": text }))).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(body, json!({ "error": "text must not be empty" }));
+        }
+
+        let (status, body) = call(&state, post("/color", json!({ "text": "🎨" }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "r": 0, "g": 0, "b": 255 }));
+    }
+
+    #[actix_web::test]
+    async fn very_long_text_is_still_colored() {
+        let state = rgb_state(&[("red", vec![1.0, 0.0, 0.0])]);
+        let (status, body) = call(&state, post("/color", json!({ "text": "red sky ".repeat(7_000) }))).await;
+        assert_eq!(status, StatusCode::OK);
+        for channel in ["r", "g", "b"] {
+            assert!(body[channel].is_u64(), "{body}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn nearest_word_finds_exact_and_closest_colors() {
+        let state = rgb_state(&[("red", vec![1.0, 0.0, 0.0])]);
+
+        let (status, body) = call(&state, post("/nearest-word", json!({ "r": 255, "g": 0, "b": 0 }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "word": "red", "r": 255, "g": 0, "b": 0, "distance": 0.0 }));
+
+        // A 3-4-5 triangle away from blue, and much further from red or green
+        let (status, body) = call(&state, post("/nearest-word", json!({ "r": 30, "g": 40, "b": 255 }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["word"], "blue");
+        assert_eq!(body["distance"], 50.0);
+    }
+
+    #[actix_web::test]
+    async fn crimson_is_nearest_to_the_red_reference() {
+        let state = rgb_state(&[("crimson", vec![0.9, 0.05, 0.2])]);
+        let (status, body) = call(&state, post("/palette/nearest", json!({ "word": "crimson" }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["word"], "red");
+        assert_eq!((&body["r"], &body["g"], &body["b"]), (&json!(255), &json!(0), &json!(0)));
+        let similarity = body["similarity"].as_f64().unwrap();
+        assert!(similarity > 0.9 && similarity < 1.0, "{similarity}");
+
+        let (status, _) = call(&state, post("/palette/nearest", json!({ "word": "  " }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn a_nan_embedding_is_unprocessable() {
+        let state = rgb_state(&[("red", vec![1.0, 0.0, 0.0]), ("glitch", vec![f32::NAN, 0.5, 0.5])]);
+
+        let (status, body) = call(&state, post("/color", json!({ "text": "glitch" }))).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"], ColorizerError::NonFiniteEmbedding.to_string());
+        // Not cached either, so the failure repeats instead of turning into a black color
+        let (status, _) = call(&state, post("/color", json!({ "text": "glitch" }))).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    /// Embeds everything as red, after sleeping for the given time
+    struct SlowEmbedder(Duration);
+
+    impl Embedder for SlowEmbedder {
+        fn embed(&self, _text: &str) -> Result<Vec<f32>, ColorizerError> {
+            std::thread::sleep(self.0);
+            Ok(vec![1.0, 0.0, 0.0])
+        }
+    }
+
+    /// Embeds everything as red, counting words outside `known` as unknown tokens
+    struct VocabEmbedder {
+        known: &'static [&'static str],
+    }
+
+    impl Embedder for VocabEmbedder {
+        fn embed(&self, _text: &str) -> Result<Vec<f32>, ColorizerError> {
+            Ok(vec![1.0, 0.0, 0.0])
+        }
+
+        fn count_tokens(&self, text: &str) -> Result<embedder::TokenCounts, ColorizerError> {
+            let words: Vec<&str> = text.split_whitespace().collect();
+            Ok(embedder::TokenCounts {
+                real: words.len(),
+                unknown: words.iter().filter(|word| !self.known.contains(word)).count(),
+            })
+        }
+    }
+
+    #[actix_web::test]
+    async fn gibberish_gets_a_hash_color_flagged_as_fallback() {
+        let state = state_with(test_model(Arc::new(VocabEmbedder { known: &["red"] }), rgb_references()));
+        let (status, body) = call(&state, post("/color", json!({ "text": "xqzt vlorp" }))).await;
+        assert_eq!(status, StatusCode::OK);
+        let [r, g, b, ..] = content_hash(b"xqzt vlorp").to_le_bytes();
+        assert_eq!(body, json!({ "r": r, "g": g, "b": b, "fallback": true }));
+        assert_eq!(call(&state, post("/color", json!({ "text": " xqzt vlorp " }))).await.1, body);
+
+        // Half unknown is not more than the default threshold of 0.5
+        let (status, body) = call(&state, post("/color", json!({ "text": "red xqzt" }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "r": 255, "g": 0, "b": 0 }));
+    }
+
+    #[actix_web::test]
+    async fn a_slow_inference_times_out_with_504() {
+        let mut config = Config::for_tests();
+        config.inference_timeout_ms = 20;
+        let embedder = Arc::new(SlowEmbedder(Duration::from_millis(300)));
+        let state = state_with(test_model_with(embedder, rgb_references(), &config));
+
+        let (status, body) = call(&state, post("/color", json!({ "text": "molasses" }))).await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(body["error"], "inference timed out after 20ms");
+    }
+
+    #[actix_web::test]
+    async fn distribution_lists_top_words_with_probabilities_summing_to_one() {
+        let state = rgb_state(&[("orange", vec![1.0, 0.6, 0.0])]);
+
+        let (status, body) = call(&state, post("/color?distribution=3&temp=0.5", json!({ "text": "orange" }))).await;
+        assert_eq!(status, StatusCode::OK);
+        let distribution = body["distribution"].as_array().unwrap();
+        let words: Vec<&str> = distribution.iter().map(|p| p["word"].as_str().unwrap()).collect();
+        assert_eq!(words, ["red", "green", "blue"]);
+        let total: f64 = distribution.iter().map(|p| p["probability"].as_f64().unwrap()).sum();
+        assert!((total - 1.0).abs() < 1e-5, "{total}");
+    }
+
+    #[actix_web::test]
+    async fn rank_picks_the_nth_best_match() {
+        let state = rgb_state(&[("orange", vec![1.0, 0.6, 0.0])]);
+        let orange = || json!({ "text": "orange" });
+
+        let (status, best) = call(&state, post("/color", orange())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(best, json!({ "r": 255, "g": 0, "b": 0 }));
+        assert_eq!(call(&state, post("/color?rank=1", orange())).await, (StatusCode::OK, best));
+
+        let (status, second) = call(&state, post("/color?rank=2&explain=1", orange())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((&second["word"], &second["g"]), (&json!("green"), &json!(255)));
+
+        let (status, body) = call(&state, post("/color?rank=4", orange())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "rank must be at most the reference count 3, got 4");
+        let (status, _) = call(&state, post("/color?rank=0", orange())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = call(&state, post("/color?rank=2", json!({ "text": "orange", "k": 2 }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn debug_scores_every_reference_best_first() {
+        let table = [("orange", vec![1.0, 0.6, 0.1])];
+        let debug = || post("/color/debug", json!({ "text": "orange" }));
+        let (status, _) = call(&rgb_state(&table), debug()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let model = test_model(Arc::new(TableEmbedder::new(&table)), rgb_references());
+        let state = web::Data::new(AppState { debug: true, ..app_state(model) });
+        let (status, body) = call(&state, debug()).await;
+        assert_eq!(status, StatusCode::OK);
+        let scores = body.as_array().unwrap();
+        let words: Vec<&str> = scores.iter().map(|s| s["word"].as_str().unwrap()).collect();
+        assert_eq!(words, ["red", "green", "blue"]);
+        let similarities: Vec<f64> = scores.iter().map(|s| s["similarity"].as_f64().unwrap()).collect();
+        assert!(similarities.windows(2).all(|w| w[0] >= w[1]), "{similarities:?}");
+        assert_eq!((&scores[0]["r"], &scores[0]["g"], &scores[0]["b"]), (&json!(255), &json!(0), &json!(0)));
+
+        let (_, body) = call(&state, post("/color/debug", json!({ "text": "orange", "top": 2 }))).await;
+        assert_eq!(body.as_array().unwrap()[..], scores[..2]);
+        let (status, _) = call(&state, post("/color/debug", json!({ "text": "orange", "top": 0 }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn an_extra_ref_can_win_the_match_for_one_request() {
+        let table = [("lagoon", vec![0.1, 0.8, 0.6]), ("teal", vec![0.0, 0.8, 0.6])];
+        let embedder = Arc::new(TableEmbedder::new(&table));
+        let state = state_with(test_model(embedder.clone(), rgb_references()));
+        let lagoon = || post("/color?explain=1", json!({ "text": "lagoon" }));
+
+        let (status, body) = call(&state, lagoon()).await;
+        assert_eq!((status, &body["word"]), (StatusCode::OK, &json!("green")));
+
+        let teal = json!({ "word": "teal", "color": [0, 128, 128] });
+        let calls = embedder.calls.load(Ordering::SeqCst);
+        let request = json!({ "text": "lagoon", "extra_refs": [teal, teal] });
+        let (status, body) = call(&state, post("/color?explain=1", request)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["word"], "teal");
+        assert_eq!([&body["r"], &body["g"], &body["b"]], [0, 128, 128]);
+        // "lagoon" was cached by the first request, and "teal" is embedded once though it's listed twice
+        assert_eq!(embedder.calls.load(Ordering::SeqCst) - calls, 1);
+
+        let (_, body) = call(&state, lagoon()).await;
+        assert_eq!(body["word"], "green");
+    }
+
+    #[actix_web::test]
+    async fn comparing_a_text_with_itself_gives_similarity_one() {
+        let state = rgb_state(&[("ocean", vec![0.1, 0.4, 0.9]), ("fire", vec![0.9, 0.2, 0.0])]);
+        let similarity = |body: &Value| body["similarity"].as_f64().unwrap();
+
+        let (status, body) = call(&state, post("/compare", json!({ "a": "ocean", "b": "ocean" }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!((similarity(&body) - 1.0).abs() < 1e-6, "{body}");
+
+        let (status, body) = call(&state, post("/compare", json!({ "a": "ocean", "b": "fire" }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(similarity(&body) < 0.5, "{body}");
+
+        let (status, body) = call(&state, post("/compare", json!({ "a": "ocean", "b": " " }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "b must not be empty");
+    }
+
+    #[actix_web::test]
+    async fn a_stopword_alone_is_too_few_tokens() {
+        let mut config = Config::for_tests();
+        config.min_tokens = 2;
+        let table = [("the", vec![0.6, 0.6, 0.5]), ("stormy ocean waves", vec![0.1, 0.3, 0.9])];
+        let state = state_with(test_model_with(Arc::new(TableEmbedder::new(&table)), rgb_references(), &config));
+
+        let (status, body) = call(&state, post("/color", json!({ "text": "the" }))).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"], ColorizerError::TooFewTokens { found: 1, min: 2 }.to_string());
+
+        let (status, body) = call(&state, post("/color", json!({ "text": "stormy ocean waves" }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "r": 0, "g": 0, "b": 255 }));
+    }
+
+    #[actix_web::test]
+    async fn a_matching_etag_gets_304_until_a_reload_changes_the_palette() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("refs.json");
+        let sun = json!({ "word": "sun", "embedding": [0.6, 0.8], "color": [255, 200, 0] });
+        write_references(&path, json!([sun]));
+        let model = Model::load(None, &test_spec(path.clone()), &Config::for_tests(), test_metrics()).unwrap();
+        let state = state_with(model);
+        let app = init_service(App::new().app_data(state.clone()).service(color_get)).await;
+        let get = |if_none_match: &str| {
+            TestRequest::get()
+                .uri("/color?text=sunny")
+                .insert_header((header::IF_NONE_MATCH, if_none_match))
+                .to_request()
+        };
+
+        fn etag_of<B>(response: &actix_web::dev::ServiceResponse<B>) -> &str {
+            response.headers().get(header::ETAG).unwrap().to_str().unwrap()
+        }
+
+        let response = call_service(&app, get("W/\"stale\"")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(header::CACHE_CONTROL).unwrap(), "public");
+        let etag = etag_of(&response).to_string();
+
+        let response = call_service(&app, get(&etag)).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(etag_of(&response), etag);
+        assert!(read_body(response).await.is_empty());
+
+        let sea = json!({ "word": "sea", "embedding": [0.0, 1.0], "color": [0, 80, 200] });
+        write_references(&path, json!([sun, sea]));
+        state.default_model().reload().unwrap();
+        let response = call_service(&app, get(&etag)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(etag_of(&response), etag);
+    }
+
+    #[actix_web::test]
+    async fn reloading_an_empty_file_is_503_and_keeps_the_old_palette() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("refs.json");
+        write_references(&path, json!([{ "word": "sun", "embedding": [0.6, 0.8], "color": [255, 200, 0] }]));
+        let model = Model::load(None, &test_spec(path.clone()), &Config::for_tests(), test_metrics()).unwrap();
+        let state = state_with(model);
+        let app = init_service(
+            App::new()
+                .app_data(state.clone())
+                .app_data(web::Data::new(ApiKey(Some("secret".to_string()))))
+                .service(reload),
+        )
+        .await;
+
+        write_references(&path, json!([]));
+        let response = call_service(&app, TestRequest::post().uri("/reload").to_request()).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: Value = serde_json::from_slice(&read_body(response).await).unwrap();
+        assert!(body["error"].as_str().unwrap().contains("no reference colors loaded"), "{}", body);
+        assert_eq!(state.default_model().references().embeddings.len(), 1);
+    }
+
+    #[test]
+    fn loading_retries_until_the_file_appears() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("refs.json");
+        let mut config = Config::for_tests();
+        config.load_retry_ms = 1;
+        let sun = json!({ "word": "sun", "embedding": [0.6, 0.8], "color": [255, 200, 0] });
+        // The file shows up just before attempt `appears_at`, like a volume that mounts late
+        let load = |config: &Config, appears_at: usize| {
+            let mut attempts = 0;
+            let result = retry_startup("Loading model", config, || {
+                attempts += 1;
+                if attempts == appears_at {
+                    write_references(&path, json!([sun]));
+                }
+                Model::load(None, &test_spec(path.clone()), config, test_metrics())
+            });
+            (result, attempts)
+        };
+
+        config.load_attempts = NonZeroUsize::new(2).unwrap();
+        let (result, attempts) = load(&config, 3);
+        assert!(result.is_err());
+        assert_eq!(attempts, 2);
+
+        config.load_attempts = NonZeroUsize::new(5).unwrap();
+        let (result, attempts) = load(&config, 3);
+        assert_eq!(result.unwrap().references().embeddings.len(), 1);
+        assert_eq!(attempts, 3);
+    }
+
+    #[actix_web::test]
+    async fn a_repeated_idempotency_key_replays_the_response_without_inference() {
+        let mut config = Config::for_tests();
+        // Without the embedding cache, any second inference would show in the call count
+        config.embed_cache_size = 0;
+        let embedder = Arc::new(TableEmbedder::new(&[("fire", vec![1.0, 0.2, 0.0])]));
+        let state = state_with(test_model_with(embedder.clone(), rgb_references(), &config));
+        let app = init_service(
+            App::new()
+                .app_data(state)
+                .app_data(web::Data::new(Idempotency::new(Duration::from_secs(60), 64 * 1024)))
+                .wrap(from_fn(replay_idempotent))
+                .service(color),
+        )
+        .await;
+        let send = |key: &str, text: &str| {
+            post("/color?explain=1", json!({ "text": text }))
+                .insert_header((IDEMPOTENCY_KEY_HEADER, key))
+                .to_request()
+        };
+
+        let calls = embedder.calls.load(Ordering::SeqCst);
+        let first = call_service(&app, send("retry-1", "fire")).await;
+        assert_eq!(first.status(), StatusCode::OK);
+        assert!(first.headers().get(REPLAYED_HEADER).is_none());
+        let first = read_body(first).await;
+        assert_eq!(embedder.calls.load(Ordering::SeqCst), calls + 1);
+
+        let retry = call_service(&app, send("retry-1", "fire")).await;
+        assert_eq!(retry.status(), StatusCode::OK);
+        assert_eq!(retry.headers().get(REPLAYED_HEADER).unwrap(), "true");
+        assert_eq!(read_body(retry).await, first);
+        assert_eq!(embedder.calls.load(Ordering::SeqCst), calls + 1);
+
+        let reused = call_service(&app, send("retry-1", "ice")).await;
+        assert_eq!(reused.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let other_key = call_service(&app, send("retry-2", "fire")).await;
+        assert!(other_key.headers().get(REPLAYED_HEADER).is_none());
+        assert_eq!(embedder.calls.load(Ordering::SeqCst), calls + 2);
+    }
+
+    #[actix_web::test]
+    async fn embed_returns_the_models_dimension_only_when_enabled() {
+        let table = [("ocean", vec![0.1, 0.4, 0.9])];
+        let (status, _) = call(&rgb_state(&table), post("/embed", json!({ "text": "ocean" }))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let model = test_model(Arc::new(TableEmbedder::new(&table)), rgb_references());
+        let dimension = model.dimension();
+        let state = web::Data::new(AppState { expose_embeddings: true, ..app_state(model) });
+        let (status, body) = call(&state, post("/embed", json!({ "text": "ocean" }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(Some(body["dim"].as_u64().unwrap() as usize), dimension);
+        assert_eq!(body["embedding"].as_array().unwrap().len(), 3);
+    }
+
+    /// Embeds everything as red and declares ONNX-style inputs and outputs
+    struct DeclaredEmbedder;
+
+    impl Embedder for DeclaredEmbedder {
+        fn embed(&self, _text: &str) -> Result<Vec<f32>, ColorizerError> {
+            Ok(vec![1.0, 0.0, 0.0])
+        }
+
+        fn io(&self) -> Option<ModelIo> {
+            let tensor = |name: &str, shape: Vec<Option<usize>>, element_type: &str| embedder::TensorInfo {
+                name: name.to_string(),
+                shape,
+                element_type: element_type.to_string(),
+            };
+            Some(ModelIo {
+                inputs: vec![tensor("input_ids", vec![None, None], "Int64")],
+                outputs: vec![tensor("last_hidden_state", vec![None, None, Some(3)], "Float32")],
+            })
+        }
+    }
+
+    #[actix_web::test]
+    async fn model_info_lists_inputs_outputs_and_dimension_only_when_enabled() {
+        let model = || test_model(Arc::new(DeclaredEmbedder), rgb_references());
+        let (status, _) = call(&state_with(model()), TestRequest::get().uri("/model-info")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let state = web::Data::new(AppState { expose_embeddings: true, ..app_state(model()) });
+        let (status, body) = call(&state, TestRequest::get().uri("/model-info")).await;
+        assert_eq!(status, StatusCode::OK);
+        let expected = json!({
+            "model": "default",
+            "dimension": 3,
+            "inputs": [{ "name": "input_ids", "shape": [null, null], "type": "Int64" }],
+            "outputs": [{ "name": "last_hidden_state", "shape": [null, null, 3], "type": "Float32" }],
+        });
+        assert_eq!(body, expected);
+
+        let (status, _) = call(&state, TestRequest::get().uri("/model-info?model=seasons")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // Mock mode has no ONNX model to describe
+        let model = test_model(Arc::new(TableEmbedder::new(&[("red", vec![1.0, 0.0, 0.0])])), rgb_references());
+        let state = web::Data::new(AppState { expose_embeddings: true, ..app_state(model) });
+        let (_, body) = call(&state, TestRequest::get().uri("/model-info")).await;
+        assert_eq!(body, json!({ "model": "default", "dimension": 3 }));
+    }
+
+    #[actix_web::test]
+    async fn strict_english_rejects_russian_and_japanese() {
+        let table = [("ocean", vec![0.0, 0.2, 1.0])];
+        let model = test_model(Arc::new(TableEmbedder::new(&table)), rgb_references());
+        let strict = web::Data::new(AppState { strict_english: true, ..app_state(model) });
+        let (status, _) = call(&strict, post("/color", json!({ "text": "ocean" }))).await;
+        assert_eq!(status, StatusCode::OK);
+        for text in ["океан", "海の色"] {
+            let (status, body) = call(&strict, post("/color", json!({ "text": text }))).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{text}");
+            assert!(body["error"].as_str().unwrap().contains("only English is supported"), "{body}");
+        }
+
+        // Permissive by default
+        let (status, _) = call(&rgb_state(&table), post("/color", json!({ "text": "океан" }))).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn capitalization_does_not_change_the_color_when_lowercasing() {
+        let table = [("love", vec![1.0, 0.1, 0.1])];
+        let mut config = Config::for_tests();
+        config.embedding.lowercase = true;
+        let state = state_with(test_model_with(Arc::new(TableEmbedder::new(&table)), rgb_references(), &config));
+
+        let (_, lower) = call(&state, post("/color", json!({ "text": "love" }))).await;
+        let (status, capitalized) = call(&state, post("/color", json!({ "text": "Love" }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(capitalized, lower);
+        assert_eq!(lower, json!({ "r": 255, "g": 0, "b": 0 }));
+    }
+
+    #[actix_web::test]
+    async fn interpolating_in_two_steps_returns_the_endpoints_colors() {
+        let state = rgb_state(&[("calm", vec![0.1, 0.2, 1.0]), ("anger", vec![1.0, 0.1, 0.0])]);
+        let (_, calm) = call(&state, post("/color", json!({ "text": "calm" }))).await;
+        let (_, anger) = call(&state, post("/color", json!({ "text": "anger" }))).await;
+
+        let request = json!({ "from": "calm", "to": "anger", "steps": 2 });
+        let (status, body) = call(&state, post("/interpolate", request)).await;
+        assert_eq!(status, StatusCode::OK);
+        let entries = body.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0]["t"].as_f64(), entries[1]["t"].as_f64()), (Some(0.0), Some(1.0)));
+        assert_eq!((&entries[0]["word"], &entries[1]["word"]), (&json!("blue"), &json!("red")));
+        for (entry, endpoint) in entries.iter().zip([calm, anger]) {
+            assert_eq!([&entry["r"], &entry["g"], &entry["b"]], [&endpoint["r"], &endpoint["g"], &endpoint["b"]]);
+        }
+
+        let request = json!({ "from": "calm", "to": "anger", "steps": 1 });
+        let (status, _) = call(&state, post("/interpolate", request)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    /// A model whose "dusk" and "dawn" references are close grays, so a smooth gradient between them bands
+    fn dusk_state() -> web::Data<AppState> {
+        let entries = [("dusk", vec![1.0, 0.0], (100, 100, 100)), ("dawn", vec![0.0, 1.0], (104, 100, 96))];
+        let entries = entries.map(|(word, embedding, rgb)| (word.to_string(), embedding, rgb));
+        let set = ReferenceSet::from_entries(entries, metric::SimilarityMetric::Cosine, false, 1).unwrap();
+        let table = [("dusk", vec![1.0, 0.0]), ("dawn", vec![0.0, 1.0])];
+        state_with(test_model(Arc::new(TableEmbedder::new(&table)), set))
+    }
+
+    #[actix_web::test]
+    async fn dithered_gradients_stay_within_one_of_the_undithered_values() {
+        let state = dusk_state();
+        let rgb = |entry: &Value| {
+            let channel = |name: &str| entry[name].as_u64().unwrap() as u8;
+            (channel("r"), channel("g"), channel("b"))
+        };
+        // A flat gradient between two equal colors, and a shallow one that bands when rounded
+        for (text, expect_nudged) in [("dusk dusk", false), ("dusk dawn", true)] {
+            let request = || json!({ "text": text });
+            let (status, plain) = call(&state, post("/gradient?smooth=16", request())).await;
+            assert_eq!(status, StatusCode::OK);
+            let (status, dithered) = call(&state, post("/gradient?smooth=16&dither=1", request())).await;
+            assert_eq!(status, StatusCode::OK);
+            let (plain, dithered) = (plain.as_array().unwrap(), dithered.as_array().unwrap());
+            assert_eq!(dithered.len(), plain.len());
+            assert_eq!(dithered.len(), 18);
+
+            let mut nudged = 0;
+            for (i, (entry, undithered)) in dithered.iter().zip(plain).enumerate() {
+                assert_eq!(entry["word"], undithered["word"]);
+                let (got, undithered) = (rgb(entry), rgb(undithered));
+                let within_one = got.0.abs_diff(undithered.0) <= 1
+                    && got.1.abs_diff(undithered.1) <= 1
+                    && got.2.abs_diff(undithered.2) <= 1;
+                assert!(within_one, "{text:?} step {i}: {got:?} vs {undithered:?}");
+                nudged += usize::from(got != undithered);
+            }
+            assert_eq!(nudged > 0, expect_nudged, "{text:?}");
+            // The words' own colors are never dithered
+            assert_eq!((rgb(&dithered[0]), rgb(&dithered[17])), (rgb(&plain[0]), rgb(&plain[17])));
+        }
+    }
+
+    #[actix_web::test]
+    async fn malformed_bodies_get_a_json_error() {
+        let state = rgb_state(&[("red", vec![1.0, 0.0, 0.0])]);
+        let raw = |body: &'static str| {
+            TestRequest::post()
+                .uri("/color")
+                .insert_header(header::ContentType::json())
+                .set_payload(body)
+        };
+
+        let (status, body) = call(&state, raw("")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, json!({ "error": "invalid request body: body is empty" }));
+
+        let (status, body) = call(&state, raw("ocean waves")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, json!({ "error": "invalid request body: expected value at line 1 column 1" }));
+
+        let (status, body) = call(&state, post("/color", json!({ "txt": "ocean" }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, json!({ "error": "invalid request body: missing field `text`" }));
+    }
+
+    #[actix_web::test]
+    async fn a_body_over_the_limit_is_rejected_with_413() {
+        let embedder = Arc::new(TableEmbedder::new(&[("a", vec![1.0, 0.0, 0.0])]));
+        let state = state_with(test_model(embedder.clone(), rgb_references()));
+        let limit = Config::for_tests().max_body_bytes;
+        assert_eq!(limit, 64 * 1024);
+        let calls = embedder.calls.load(Ordering::SeqCst);
+
+        let (status, body) = call(&state, post("/color", json!({ "text": "a".repeat(limit) }))).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(body["error"].as_str().unwrap().starts_with("invalid request body: "), "{body}");
+        assert_eq!(embedder.calls.load(Ordering::SeqCst), calls);
+
+        let (status, _) = call(&state, post("/color", json!({ "text": "a".repeat(limit - 100) }))).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn large_batches_are_gzipped_and_round_trip_to_the_same_json() {
+        use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+        use std::io::{Read, Write};
+
+        let state = rgb_state(&[("fire", vec![1.0, 0.2, 0.0]), ("sea", vec![0.0, 0.2, 1.0])]);
+        let app = init_service(
+            App::new()
+                .app_data(state)
+                .app_data(json_config(64 * 1024))
+                .wrap(Compress::default())
+                .service(colors),
+        )
+        .await;
+        let texts: Vec<&str> = ["fire", "sea", "grass"].into_iter().cycle().take(MAX_BATCH_SIZE).collect();
+        let batch = json!({ "texts": texts });
+
+        let plain = call_service(&app, post("/colors", batch.clone()).to_request()).await;
+        assert_eq!(plain.status(), StatusCode::OK);
+        assert!(plain.headers().get(header::CONTENT_ENCODING).is_none());
+        let plain = read_body(plain).await;
+
+        let gzipped = post("/colors", batch.clone()).insert_header((header::ACCEPT_ENCODING, "gzip"));
+        let gzipped = call_service(&app, gzipped.to_request()).await;
+        assert_eq!(gzipped.status(), StatusCode::OK);
+        assert_eq!(gzipped.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+        let gzipped = read_body(gzipped).await;
+        assert!(gzipped.len() * 4 < plain.len(), "{} vs {} bytes", gzipped.len(), plain.len());
+        let mut decoded = Vec::new();
+        GzDecoder::new(&gzipped[..]).read_to_end(&mut decoded).unwrap();
+        let plain: Value = serde_json::from_slice(&plain).unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&decoded).unwrap(), plain);
+        assert_eq!(plain.as_array().unwrap().len(), MAX_BATCH_SIZE);
+
+        // A gzip-encoded request body is decompressed before parsing
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(batch.to_string().as_bytes()).unwrap();
+        let request = TestRequest::post()
+            .uri("/colors")
+            .insert_header(header::ContentType::json())
+            .insert_header((header::CONTENT_ENCODING, "gzip"))
+            .set_payload(encoder.finish().unwrap());
+        let response = call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(serde_json::from_slice::<Value>(&read_body(response).await).unwrap(), plain);
+    }
+
+    #[actix_web::test]
+    async fn split_with_k_is_rejected_before_embedding() {
+        let embedder = Arc::new(TableEmbedder::new(&[("sea", vec![0.0, 0.0, 1.0])]));
+        let state = state_with(test_model(embedder.clone(), rgb_references()));
+        let probes = embedder.calls.load(Ordering::SeqCst);
+
+        let request = json!({ "text": "Calm sea. Red sky.", "split": "sentence", "k": 2 });
+        let (status, body) = call(&state, post("/color", request)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, json!({ "error": "split cannot be combined with k > 1" }));
+        assert_eq!(embedder.calls.load(Ordering::SeqCst), probes, "nothing should be embedded");
+    }
+
+    #[actix_web::test]
+    async fn split_sentences_get_a_color_each() {
+        let state = rgb_state(&[("I love you.", vec![1.0, 0.2, 0.1]), ("I hate rain.", vec![0.1, 0.2, 1.0])]);
+
+        let request = json!({ "text": "I love you. I hate rain.", "split": "sentence" });
+        let (status, body) = call(&state, post("/color", request)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!([
+                { "sentence": "I love you.", "r": 255, "g": 0, "b": 0 },
+                { "sentence": "I hate rain.", "r": 0, "g": 0, "b": 255 }
+            ])
+        );
+    }
+
+    #[actix_web::test]
+    async fn an_alias_gets_its_canonical_words_color() {
+        let table = [("love", vec![1.0, 0.1, 0.1])];
+        let mut config = Config::for_tests();
+        config.embedding.aliases = HashMap::from([("luv".to_string(), "love".to_string())]);
+        let state = state_with(test_model_with(Arc::new(TableEmbedder::new(&table)), rgb_references(), &config));
+
+        let (_, canonical) = call(&state, post("/color", json!({ "text": "love" }))).await;
+        let (status, aliased) = call(&state, post("/color", json!({ "text": "luv" }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(aliased, canonical);
+        assert_eq!(aliased, json!({ "r": 255, "g": 0, "b": 0 }));
+    }
+
+    #[actix_web::test]
+    async fn concurrent_requests_all_get_their_own_color() {
+        let table = [("fire", vec![1.0, 0.2, 0.0]), ("moss", vec![0.1, 1.0, 0.2]), ("sea", vec![0.0, 0.3, 1.0])];
+        let expected = [(255, 0, 0), (0, 255, 0), (0, 0, 255)];
+        // Both straight to the blocking pool and through the micro-batcher
+        for batch_window_ms in [0, 2] {
+            let mut config = Config::for_tests();
+            config.batch_window_ms = batch_window_ms;
+            let state = state_with(test_model_with(Arc::new(TableEmbedder::new(&table)), rgb_references(), &config));
+            let app = init_service(
+                App::new()
+                    .app_data(state.clone())
+                    .app_data(web::Data::new(ApiKey(None)))
+                    .app_data(web::Data::new(Usage::new(None)))
+                    .service(color),
+            )
+            .await;
+
+            let requests = (0..96).map(|i| {
+                let request = post("/color", json!({ "text": table[i % 3].0 }));
+                call_service(&app, request.to_request())
+            });
+            let responses = futures_util::future::join_all(requests).await;
+            for (i, response) in responses.into_iter().enumerate() {
+                assert_eq!(response.status(), StatusCode::OK);
+                let body: Value = serde_json::from_slice(&read_body(response).await).unwrap();
+                let (r, g, b) = expected[i % 3];
+                assert_eq!(body, json!({ "r": r, "g": g, "b": b }), "request {i}, window {batch_window_ms}ms");
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn mock_mode_colors_text_without_a_model() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("refs.json");
+        let references = json!([
+            { "word": "red", "embedding": [1.0, 0.0, 0.0], "color": [255, 0, 0] },
+            { "word": "green", "embedding": [0.0, 1.0, 0.0], "color": [0, 255, 0] },
+            { "word": "blue", "embedding": [0.0, 0.0, 1.0], "color": [0, 0, 255] }
+        ]);
+        std::fs::write(&path, references.to_string()).unwrap();
+        // No ONNX environment, so no tokenizer or model file is read
+        let metrics = web::Data::new(Metrics::new().unwrap());
+        let model = Model::load(None, &test_spec(path), &Config::for_tests(), metrics).unwrap();
+        let state = state_with(model);
+
+        let mut seen = std::collections::HashSet::new();
+        for text in ["ocean", "fire", "forest", "midnight", "sunrise", "storm", "honey", "moss"] {
+            let (status, first) = call(&state, post("/color", json!({ "text": text }))).await;
+            assert_eq!(status, StatusCode::OK);
+            let (_, again) = call(&state, post("/color", json!({ "text": text }))).await;
+            assert_eq!(again, first, "{text} must color the same every time");
+            seen.insert(first.to_string());
+        }
+        assert!(seen.len() > 1, "hash-derived embeddings should spread over the palette");
+    }
+
+    #[actix_web::test]
+    async fn an_empty_palette_answers_with_the_custom_fallback() {
+        let empty = ReferenceSet::from_entries([], metric::SimilarityMetric::Cosine, false, 1).unwrap();
+        let model = test_model(Arc::new(TableEmbedder::new(&[("ocean", vec![0.0, 0.0, 1.0])])), empty);
+        let state = web::Data::new(AppState { fallback_color: (10, 20, 30), ..app_state(model) });
+
+        let (status, body) = call(&state, post("/color", json!({ "text": "ocean" }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "r": 10, "g": 20, "b": 30 }));
+        let request = json!({ "from": "ocean", "to": "sky", "steps": 2 });
+        let (status, body) = call(&state, post("/interpolate", request)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((&body[0]["r"], &body[1]["b"]), (&json!(10), &json!(30)));
+    }
+}
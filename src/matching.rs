@@ -0,0 +1,355 @@
+//! Scoring a query embedding against the loaded references.
+
+use half::f16;
+use rayon::prelude::*;
+use std::{
+    cmp::Ordering,
+    cmp::Reverse,
+    collections::BinaryHeap,
+    slice,
+};
+
+use crate::{
+    ann::IvfIndex,
+    embedding::l2_normalize,
+    metric::{cosine_similarity_with_norm, dot_product, l2_norm, neg_euclidean, ScoreFn, SimilarityMetric},
+    reference::{RefEmbedding, ReferenceSet, Vector},
+};
+
+/// Reference count above which the similarity scan runs in parallel; smaller palettes aren't
+/// worth the thread overhead
+pub const PARALLEL_SCAN_THRESHOLD: usize = 2048;
+
+/// Reference embeddings prepared for scoring; replaced as a whole by
+/// [`Model::reload`](crate::model::Model::reload)
+pub struct References {
+    pub embeddings: Vec<RefEmbedding>,
+    /// Lexicographic rank of each reference word, for deterministic tie-breaking
+    pub word_ranks: Vec<usize>,
+    /// FNV-1a hash of the reference JSON these were loaded from
+    pub content_hash: u64,
+    /// Metric the references were prepared for
+    pub metric: SimilarityMetric,
+    /// Whether every reference embedding was L2-normalized at load time
+    normalized: bool,
+    /// Approximate nearest-neighbor index, built when `COLORIZER_ANN=1`
+    pub ann_index: Option<IvfIndex>,
+}
+
+/// How a query is scored against one reference
+#[derive(Clone, Copy)]
+enum Scorer {
+    /// The metric's scoring function for each precision references can be stored in
+    Plain { f32: ScoreFn<f32>, f16: ScoreFn<f16> },
+    /// Cosine against references that couldn't all be normalized, using their cached norms
+    CosineWithNorm,
+}
+
+impl Scorer {
+    /// Score `query` (whose L2 norm is `query_norm`) against `reference`
+    fn score(&self, query: &[f32], query_norm: f32, reference: &RefEmbedding) -> f32 {
+        match (self, &reference.embedding) {
+            (Scorer::Plain { f32, .. }, Vector::F32(v)) => f32(query, v),
+            (Scorer::Plain { f16, .. }, Vector::F16(v)) => f16(query, v),
+            (Scorer::CosineWithNorm, Vector::F32(v)) => {
+                cosine_similarity_with_norm(query, query_norm, v, reference.norm)
+            }
+            (Scorer::CosineWithNorm, Vector::F16(v)) => {
+                cosine_similarity_with_norm(query, query_norm, v, reference.norm)
+            }
+        }
+    }
+}
+
+/// Similarity of a reference embedding to the query, ordered so the best match is greatest
+#[derive(Clone, Copy)]
+pub struct Match {
+    pub index: usize,
+    pub similarity: f32,
+    /// Position of the reference word in lexicographic order, used to break ties
+    pub rank: usize,
+}
+
+impl Ord for Match {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Higher similarity wins; on ties the lexicographically first word wins
+        self.similarity
+            .total_cmp(&other.similarity)
+            .then_with(|| other.rank.cmp(&self.rank))
+    }
+}
+
+/// Rank each reference by `(word, position)` so ties resolve the same way regardless of file order
+fn word_ranks(ref_embeddings: &[RefEmbedding]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..ref_embeddings.len()).collect();
+    order.sort_by(|&a, &b| ref_embeddings[a].word.cmp(&ref_embeddings[b].word).then(a.cmp(&b)));
+
+    let mut ranks = vec![0; ref_embeddings.len()];
+    for (rank, i) in order.into_iter().enumerate() {
+        ranks[i] = rank;
+    }
+    ranks
+}
+
+/// Min-heap of the best matches seen so far
+type MatchHeap = BinaryHeap<Reverse<Match>>;
+
+/// Push a match, dropping the worst one if the heap grows past `k`
+fn push_bounded(heap: &mut MatchHeap, m: Match, k: usize) {
+    heap.push(Reverse(m));
+    if heap.len() > k {
+        heap.pop();
+    }
+}
+
+impl PartialOrd for Match {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Match {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Match {}
+
+impl References {
+    /// Normalize-and-index results from [`ReferenceSet`], plus tie-breaking ranks and norms
+    pub fn new(mut set: ReferenceSet) -> Self {
+        set.embeddings.iter_mut().for_each(RefEmbedding::cache_norm);
+        References {
+            word_ranks: word_ranks(&set.embeddings),
+            content_hash: set.content_hash,
+            embeddings: set.embeddings,
+            metric: set.metric,
+            normalized: set.normalized,
+            ann_index: set.ann_index,
+        }
+    }
+
+    /// Embedding dimension, or `None` if there are no references
+    pub fn dimension(&self) -> Option<usize> {
+        self.embeddings.first().map(|r| r.embedding.len())
+    }
+
+    /// Scoring functions for the configured metric. With cosine over normalized references the
+    /// queries are normalized here, once, so each reference only costs a dot product.
+    fn scorer(&self, sentence_embs: &mut [Vec<f32>]) -> Scorer {
+        match self.metric {
+            SimilarityMetric::Cosine if self.normalized => {
+                sentence_embs.iter_mut().for_each(|emb| {
+                    l2_normalize(emb);
+                });
+                Scorer::Plain {
+                    f32: dot_product,
+                    f16: dot_product,
+                }
+            }
+            SimilarityMetric::Cosine => Scorer::CosineWithNorm,
+            SimilarityMetric::Dot => Scorer::Plain {
+                f32: dot_product,
+                f16: dot_product,
+            },
+            SimilarityMetric::NegEuclidean => Scorer::Plain {
+                f32: neg_euclidean,
+                f16: neg_euclidean,
+            },
+        }
+    }
+
+    /// Return the `k` closest reference embeddings, best first.
+    ///
+    /// Uses a bounded min-heap so only `k` matches are kept while scanning. When the ANN index is
+    /// enabled only the references in the probed clusters are scored, falling back to the exact
+    /// scan if they hold fewer than `k` references. Scans over more than
+    /// [`PARALLEL_SCAN_THRESHOLD`] references are split across threads.
+    pub fn top_matches(&self, sentence_emb: Vec<f32>, k: usize) -> Vec<Match> {
+        self.top_matches_with(sentence_emb, k, &[])
+    }
+
+    /// Like [`References::top_matches`], also scoring `extras` from [`References::extra_reference`].
+    ///
+    /// A match on `extras[j]` has index `embeddings.len() + j`; look matches up with
+    /// [`References::entry`]. On an exact tie a loaded reference beats an extra one.
+    pub fn top_matches_with(&self, mut sentence_emb: Vec<f32>, k: usize, extras: &[RefEmbedding]) -> Vec<Match> {
+        let score = self.scorer(slice::from_mut(&mut sentence_emb));
+        let query_norm = l2_norm(&sentence_emb);
+
+        // Probed ANN clusters, or `None` to scan every reference
+        let candidates = self
+            .ann_index
+            .as_ref()
+            .map(|ivf| ivf.candidates(&sentence_emb))
+            .filter(|candidates| candidates.len() >= k);
+        let count = candidates.as_ref().map_or(self.embeddings.len(), Vec::len);
+
+        let score_at = |i: usize| Match {
+            index: i,
+            similarity: score.score(&sentence_emb, query_norm, &self.embeddings[i]),
+            rank: self.word_ranks[i],
+        };
+
+        let mut heap = if count > PARALLEL_SCAN_THRESHOLD {
+            // Each thread keeps its own top `k`, then the heaps are merged. `Match` is totally
+            // ordered, so the result doesn't depend on how the work was split.
+            let fold = |mut heap: MatchHeap, i: usize| {
+                push_bounded(&mut heap, score_at(i), k);
+                heap
+            };
+            let merge = |mut a: MatchHeap, b: MatchHeap| {
+                for Reverse(m) in b {
+                    push_bounded(&mut a, m, k);
+                }
+                a
+            };
+            match candidates {
+                Some(candidates) => candidates.into_par_iter().fold(MatchHeap::new, fold).reduce(MatchHeap::new, merge),
+                None => (0..count).into_par_iter().fold(MatchHeap::new, fold).reduce(MatchHeap::new, merge),
+            }
+        } else {
+            let mut heap = MatchHeap::with_capacity(k + 1);
+            let candidates: Box<dyn Iterator<Item = usize>> = match candidates {
+                Some(candidates) => Box::new(candidates.into_iter()),
+                None => Box::new(0..count),
+            };
+            for i in candidates {
+                push_bounded(&mut heap, score_at(i), k);
+            }
+            heap
+        };
+
+        for (j, extra) in extras.iter().enumerate() {
+            let m = Match {
+                index: self.embeddings.len() + j,
+                similarity: score.score(&sentence_emb, query_norm, extra),
+                rank: self.word_ranks.len() + j,
+            };
+            push_bounded(&mut heap, m, k);
+        }
+
+        heap.into_sorted_vec().into_iter().map(|Reverse(m)| m).collect()
+    }
+
+    /// Prepare a reference supplied with a request the way loaded ones are: normalized when the
+    /// metric needs it, with its norm cached
+    pub fn extra_reference(&self, word: String, mut embedding: Vec<f32>, color: (u8, u8, u8)) -> RefEmbedding {
+        if self.normalized {
+            l2_normalize(&mut embedding);
+        }
+        let mut extra = RefEmbedding {
+            word,
+            embedding: Vector::F32(embedding),
+            color,
+            norm: 0.0,
+        };
+        extra.cache_norm();
+        extra
+    }
+
+    /// The reference a match from [`References::top_matches_with`] points to
+    pub fn entry<'a>(&'a self, extras: &'a [RefEmbedding], index: usize) -> &'a RefEmbedding {
+        match index.checked_sub(self.embeddings.len()) {
+            Some(j) => &extras[j],
+            None => &self.embeddings[index],
+        }
+    }
+
+    /// Return the best match for each embedding, through the same (ANN-aware, parallel) scan as
+    /// [`References::top_matches`]
+    pub fn best_matches(&self, sentence_embs: Vec<Vec<f32>>) -> Vec<Option<Match>> {
+        sentence_embs
+            .into_iter()
+            .map(|emb| self.top_matches(emb, 1).first().copied())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn references(entries: &[(&str, [f32; 3])]) -> References {
+        references_with(entries, SimilarityMetric::Cosine)
+    }
+
+    fn references_with(entries: &[(&str, [f32; 3])], metric: SimilarityMetric) -> References {
+        let entries = entries.iter().map(|&(word, v)| (word.to_string(), v.to_vec(), (0, 0, 0)));
+        References::new(ReferenceSet::from_entries(entries, metric, false, 1).unwrap())
+    }
+
+    #[test]
+    fn ties_go_to_the_lexicographically_first_word() {
+        let refs = references(&[("zebra", [1.0, 1.0, 0.0]), ("apple", [2.0, 2.0, 0.0]), ("mango", [0.0, 0.0, 1.0])]);
+
+        let best = refs.top_matches(vec![1.0, 1.0, 0.0], 1)[0];
+        assert_eq!(refs.embeddings[best.index].word, "apple");
+        let ranked: Vec<&str> = refs
+            .top_matches(vec![1.0, 1.0, 0.0], 3)
+            .iter()
+            .map(|m| refs.embeddings[m.index].word.as_str())
+            .collect();
+        assert_eq!(ranked, ["apple", "zebra", "mango"]);
+    }
+
+    #[test]
+    fn parallel_scan_matches_a_full_sort() {
+        use rand_pcg::{
+            rand_core::{RngCore, SeedableRng},
+            Pcg32,
+        };
+
+        let mut rng = Pcg32::seed_from_u64(1);
+        let mut random_vector = || (0..8).map(|_| rng.next_u32() as f32 / u32::MAX as f32 - 0.5).collect::<Vec<f32>>();
+        let count = PARALLEL_SCAN_THRESHOLD * 2;
+        let entries: Vec<_> = (0..count).map(|i| (format!("w{i}"), random_vector(), (0, 0, 0))).collect();
+        let refs = References::new(ReferenceSet::from_entries(entries, SimilarityMetric::Cosine, false, 1).unwrap());
+
+        for _ in 0..5 {
+            let query = random_vector();
+            let mut normalized = query.clone();
+            let score = refs.scorer(slice::from_mut(&mut normalized));
+            let norm = l2_norm(&normalized);
+            let mut expected: Vec<Match> = (0..count)
+                .map(|i| Match {
+                    index: i,
+                    similarity: score.score(&normalized, norm, &refs.embeddings[i]),
+                    rank: refs.word_ranks[i],
+                })
+                .collect();
+            expected.sort_by(|a, b| b.cmp(a));
+
+            let indices = |matches: &[Match]| matches.iter().map(|m| m.index).collect::<Vec<_>>();
+            assert_eq!(indices(&refs.top_matches(query.clone(), 10)), indices(&expected[..10]));
+            assert_eq!(refs.best_matches(vec![query])[0].map(|m| m.index), Some(expected[0].index));
+        }
+    }
+
+    #[test]
+    fn each_metric_ranks_and_breaks_ties() {
+        let entries = [
+            ("zebra", [1.0, 0.0, 0.0]),
+            ("long", [3.0, 1.0, 0.0]),
+            ("apple", [1.0, 0.0, 0.0]),
+            ("near", [0.9, 0.5, 0.0]),
+        ];
+        for (metric, expected) in [
+            // Direction only: "long" points closer to the query than "near"
+            (SimilarityMetric::Cosine, ["apple", "zebra", "long", "near"]),
+            // Magnitude counts, so "long" wins outright
+            (SimilarityMetric::Dot, ["long", "apple", "zebra", "near"]),
+            // Position: "near" is 0.51 away, "long" 2.24
+            (SimilarityMetric::NegEuclidean, ["apple", "zebra", "near", "long"]),
+        ] {
+            let refs = references_with(&entries, metric);
+            let ranked: Vec<&str> = refs
+                .top_matches(vec![1.0, 0.0, 0.0], 4)
+                .iter()
+                .map(|m| refs.embeddings[m.index].word.as_str())
+                .collect();
+            assert_eq!(ranked, expected, "{metric}");
+        }
+    }
+}
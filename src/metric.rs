@@ -0,0 +1,110 @@
+//! Similarity metrics used to score reference embeddings against a query.
+
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Scoring function against references stored as `T`; higher always means more similar
+pub type ScoreFn<T> = fn(&[f32], &[T]) -> f32;
+
+/// How a query embedding is compared to the references (`COLORIZER_METRIC`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SimilarityMetric {
+    /// Cosine similarity in `[-1, 1]`; references are L2-normalized at load time
+    #[default]
+    Cosine,
+    /// Raw dot product, so longer embeddings score higher
+    Dot,
+    /// Negated Euclidean distance, so the closest reference scores highest (at most 0)
+    NegEuclidean,
+}
+
+impl SimilarityMetric {
+    /// Whether references are L2-normalized at load time, which the ANN index also requires
+    pub fn normalizes(self) -> bool {
+        self == SimilarityMetric::Cosine
+    }
+}
+
+impl FromStr for SimilarityMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cosine" => Ok(SimilarityMetric::Cosine),
+            "dot" => Ok(SimilarityMetric::Dot),
+            "euclidean" => Ok(SimilarityMetric::NegEuclidean),
+            other => Err(format!("unknown similarity metric {:?} (expected cosine, dot or euclidean)", other)),
+        }
+    }
+}
+
+impl fmt::Display for SimilarityMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SimilarityMetric::Cosine => "cosine",
+            SimilarityMetric::Dot => "dot",
+            SimilarityMetric::NegEuclidean => "euclidean",
+        })
+    }
+}
+
+/// L2 norm of an embedding
+pub fn l2_norm<T: Copy + Into<f32>>(v: &[T]) -> f32 {
+    v.iter().map(|&x| x.into() * x.into()).sum::<f32>().sqrt()
+}
+
+/// Cosine similarity between two embeddings, or 0 if either is all zeros
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    cosine_similarity_with_norm(a, l2_norm(a), b, l2_norm(b))
+}
+
+/// Cosine similarity between two embeddings whose norms were computed up front, so scoring a
+/// query against every reference costs one dot product each rather than three passes
+pub fn cosine_similarity_with_norm<T: Copy + Into<f32>>(a: &[f32], norm_a: f32, b: &[T], norm_b: f32) -> f32 {
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot_product(a, b) / (norm_a * norm_b)
+    }
+}
+
+/// Dot product of two embeddings; equals cosine similarity when both are L2-normalized
+pub fn dot_product<T: Copy + Into<f32>>(a: &[f32], b: &[T]) -> f32 {
+    a.iter().zip(b).map(|(&x, &y)| x * y.into()).sum()
+}
+
+/// Negated Euclidean distance between two embeddings
+pub fn neg_euclidean<T: Copy + Into<f32>>(a: &[f32], b: &[T]) -> f32 {
+    -a.iter()
+        .zip(b)
+        .map(|(&x, &y)| (x - y.into()) * (x - y.into()))
+        .sum::<f32>()
+        .sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedding::l2_normalize;
+
+    #[test]
+    fn dot_product_of_normalized_vectors_is_cosine() {
+        let a = vec![0.3, -1.2, 4.0, 0.05];
+        let b = vec![2.5, 0.7, -0.4, 1.1];
+        let (mut na, mut nb) = (a.clone(), b.clone());
+        assert!(l2_normalize(&mut na) && l2_normalize(&mut nb));
+
+        assert!((dot_product(&na, &nb) - cosine_similarity(&a, &b)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn each_metric_scores_fixed_vectors() {
+        let (a, b) = ([1.0, 2.0, 2.0], [2.0, 0.0, 0.0]);
+        assert!((cosine_similarity(&a, &b) - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!(dot_product(&a, &b), 2.0);
+        // Distance √(1 + 4 + 4) = 3, negated so closer scores higher
+        assert_eq!(neg_euclidean(&a, &b), -3.0);
+        assert_eq!(neg_euclidean(&a, &a), 0.0);
+        assert_eq!(cosine_similarity(&a, &[0.0, 0.0, 0.0]), 0.0);
+    }
+}
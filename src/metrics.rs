@@ -0,0 +1,74 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web, Error,
+};
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, Registry, TextEncoder};
+use std::time::Instant;
+
+/// Prometheus metrics exposed on `GET /metrics`
+pub struct Metrics {
+    registry: Registry,
+    pub requests_total: IntCounter,
+    pub request_duration: Histogram,
+    pub inference_duration: Histogram,
+    pub best_similarity: Histogram,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new_custom(Some("colorizer".to_string()), None)?;
+
+        let requests_total = IntCounter::new("requests_total", "Total HTTP requests handled")?;
+        let request_duration = Histogram::with_opts(HistogramOpts::new(
+            "request_duration_seconds",
+            "End-to-end HTTP request latency",
+        ))?;
+        let inference_duration = Histogram::with_opts(
+            HistogramOpts::new("inference_duration_seconds", "Time spent computing an embedding")
+                .buckets(prometheus::exponential_buckets(0.001, 2.0, 12)?),
+        )?;
+        let best_similarity = Histogram::with_opts(
+            HistogramOpts::new("best_similarity", "Similarity of the best reference match")
+                .buckets(prometheus::linear_buckets(-1.0, 0.1, 21)?),
+        )?;
+
+        registry.register(Box::new(requests_total.clone()))?;
+        registry.register(Box::new(request_duration.clone()))?;
+        registry.register(Box::new(inference_duration.clone()))?;
+        registry.register(Box::new(best_similarity.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            requests_total,
+            request_duration,
+            inference_duration,
+            best_similarity,
+        })
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+/// Middleware counting requests and recording their latency
+pub async fn record_request_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+    let start = Instant::now();
+
+    let res = next.call(req).await;
+
+    if let Some(metrics) = metrics {
+        metrics.requests_total.inc();
+        metrics.request_duration.observe(start.elapsed().as_secs_f64());
+    }
+    res
+}
@@ -0,0 +1,530 @@
+use actix_web::web;
+use colorizer::{
+    embedding::EmbeddingOptions,
+    error::ColorizerError,
+    matching::References,
+    metric::SimilarityMetric,
+    precision::Precision,
+    reference::{content_hash, ConflictPolicy, ReferenceSet},
+};
+use lru::LruCache;
+use ort::environment::Environment;
+use std::{
+    error::Error,
+    fs,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+
+use crate::{
+    batcher::Batcher,
+    config::{Config, ModelSpec},
+    embedder::{Embedder, MockEmbedder, ModelIo, OnnxEmbedder},
+    metrics::Metrics,
+};
+
+/// Fixed string embedded once at startup to confirm the model runs
+const HEALTH_PROBE_TEXT: &str = "health";
+
+/// Throwaway inferences run at startup so ONNX Runtime's lazy allocations don't hit the first request
+const WARMUP_RUNS: usize = 2;
+
+/// Fixed text embedded during warmup
+const WARMUP_TEXT: &str = "warming up the colorizer";
+
+/// An embedding model together with the reference colors embedded by it
+pub struct Model {
+    embedder: Arc<dyn Embedder + Send + Sync>,
+    /// Collects concurrent requests into batched runs; `None` when `COLORIZER_BATCH_WINDOW_MS` is 0
+    batcher: Option<Batcher>,
+    embedding_options: EmbeddingOptions,
+    /// Current references; requests clone the `Arc` so a reload never changes them mid-request
+    references: RwLock<Arc<References>>,
+    /// Model file, for error messages
+    model_path: PathBuf,
+    /// Modification time of the model file when it was loaded, if the filesystem reports one
+    pub model_modified: Option<SystemTime>,
+    /// Where the references are (re)loaded from: a file, a comma-separated list or a directory
+    ref_path: PathBuf,
+    ref_conflict: ConflictPolicy,
+    metric: SimilarityMetric,
+    ann: bool,
+    ann_probes: usize,
+    /// Output dimension of the model, from the startup probe
+    dimension: Option<usize>,
+    /// Whether the startup probe inference succeeded
+    pub model_ready: bool,
+    /// Shared Prometheus metrics, for inference timings
+    metrics: web::Data<Metrics>,
+    /// LRU cache of embeddings keyed on the trimmed input text; `None` when disabled
+    embed_cache: Option<Mutex<LruCache<String, Vec<f32>>>>,
+    /// Limit on a single [`Model::embed_blocking`] call; `None` waits indefinitely
+    inference_timeout: Option<Duration>,
+    /// Texts with fewer real tokens are rejected before inference; 0 skips the check
+    min_tokens: usize,
+    /// Texts with a larger share of unknown tokens get a hash color instead of a match
+    unk_threshold: f32,
+    /// Accept a reference file with no entries; only when `COLORIZER_FALLBACK_COLOR` says what to return
+    allow_empty_refs: bool,
+}
+
+impl Model {
+    /// Load the reference embeddings and embedding backend for one model, then probe it once so
+    /// `/healthz` doesn't run inference on every call.
+    ///
+    /// Without an ONNX environment (`COLORIZER_MOCK=1`) the tokenizer and model aren't loaded, and
+    /// a [`MockEmbedder`] matching the references' dimension is used instead.
+    pub fn load(
+        environment: Option<&Arc<Environment>>,
+        spec: &ModelSpec,
+        config: &Config,
+        metrics: web::Data<Metrics>,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        // Load reference embeddings (normalized for cosine, and indexed when ANN is enabled)
+        let set = ReferenceSet::load(
+            &spec.ref_embeddings,
+            config.ref_conflict,
+            config.metric,
+            config.ann,
+            config.ann_probes,
+            model_fingerprint(&spec.model),
+        )?;
+        if set.precision != Precision::F32 {
+            println!("  • ref precision:  {}", set.precision);
+        }
+        let references = References::new(set);
+        check_not_empty(&references, &spec.ref_embeddings, config.allow_empty_refs)?;
+        if let Some(ivf) = &references.ann_index {
+            println!("  • ANN index:      {} clusters, {} probed", ivf.list_count(), config.ann_probes);
+        }
+
+        let embedder: Arc<dyn Embedder + Send + Sync> = match environment {
+            Some(environment) => Arc::new(OnnxEmbedder::load(environment, spec, config)?),
+            None => Arc::new(MockEmbedder::new(references.dimension())),
+        };
+        Self::new(embedder, references, spec, config, metrics)
+    }
+
+    /// Probe `embedder` and check it against the already loaded `references`
+    fn new(
+        embedder: Arc<dyn Embedder + Send + Sync>,
+        references: References,
+        spec: &ModelSpec,
+        config: &Config,
+        metrics: web::Data<Metrics>,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let (model_ready, dimension) = match embedder.embed(HEALTH_PROBE_TEXT) {
+            Ok(probe) => (true, Some(probe.len())),
+            Err(e) => {
+                eprintln!("⚠️  Model probe failed, /healthz will report unavailable: {}", e);
+                (false, None)
+            }
+        };
+        check_dimension(dimension, &references, &spec.model, &spec.ref_embeddings)?;
+
+        let batcher = (config.batch_window_ms > 0).then(|| {
+            let window = Duration::from_millis(config.batch_window_ms);
+            Batcher::spawn(Arc::clone(&embedder), window, config.batch_max, metrics.clone())
+        });
+
+        Ok(Model {
+            embedder,
+            batcher,
+            embedding_options: config.embedding.clone(),
+            references: RwLock::new(Arc::new(references)),
+            model_path: spec.model.clone(),
+            model_modified: fs::metadata(&spec.model).and_then(|m| m.modified()).ok(),
+            ref_path: spec.ref_embeddings.clone(),
+            ref_conflict: config.ref_conflict,
+            metric: config.metric,
+            ann: config.ann,
+            ann_probes: config.ann_probes,
+            dimension,
+            model_ready,
+            metrics,
+            embed_cache: NonZeroUsize::new(config.embed_cache_size).map(|size| Mutex::new(LruCache::new(size))),
+            inference_timeout: (config.inference_timeout_ms > 0).then(|| Duration::from_millis(config.inference_timeout_ms)),
+            min_tokens: config.min_tokens,
+            unk_threshold: config.unk_threshold,
+            allow_empty_refs: config.allow_empty_refs,
+        })
+    }
+}
+
+/// Identifies a model file without reading all of it: a hash of its size and modification time,
+/// or 0 if it can't be inspected (as in mock mode, where it may not exist)
+fn model_fingerprint(path: &Path) -> u64 {
+    let Ok(metadata) = fs::metadata(path) else {
+        return 0;
+    };
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos());
+    let mut bytes = metadata.len().to_le_bytes().to_vec();
+    bytes.extend_from_slice(&modified.to_le_bytes());
+    content_hash(&bytes)
+}
+
+/// Reject an embedding with NaN or infinite values, whichever backend produced it, so it is
+/// neither cached nor scored into a misleading color
+fn check_finite(emb: Vec<f32>) -> Result<Vec<f32>, ColorizerError> {
+    if emb.iter().all(|x| x.is_finite()) {
+        Ok(emb)
+    } else {
+        Err(ColorizerError::NonFiniteEmbedding)
+    }
+}
+
+/// A reference file without entries, refused unless `COLORIZER_ALLOW_EMPTY_REFS` is set
+#[derive(Debug, Error)]
+#[error(
+    "no reference colors loaded: {} is empty (set COLORIZER_ALLOW_EMPTY_REFS=1 to serve an empty palette anyway)",
+    .0.display()
+)]
+pub struct EmptyReferences(PathBuf);
+
+/// Fail on a reference file without entries unless empty palettes are allowed; every lookup would
+/// otherwise silently return the fallback color
+fn check_not_empty(references: &References, ref_path: &Path, allow_empty: bool) -> Result<(), EmptyReferences> {
+    if references.embeddings.is_empty() && !allow_empty {
+        return Err(EmptyReferences(ref_path.to_path_buf()));
+    }
+    Ok(())
+}
+
+/// Fail if the model's output dimension differs from the references'; a mismatched model would
+/// otherwise silently score only the overlapping dimensions
+fn check_dimension(
+    dimension: Option<usize>,
+    references: &References,
+    model_path: &Path,
+    ref_path: &Path,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match (dimension, references.dimension()) {
+        (Some(model_dim), Some(ref_dim)) if model_dim != ref_dim => Err(format!(
+            "embedding dimension mismatch: model {} produces {}, but {} has {} (regenerate the reference embeddings for this model)",
+            model_path.display(),
+            model_dim,
+            ref_path.display(),
+            ref_dim
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+impl Model {
+    /// Snapshot of the current references
+    pub fn references(&self) -> Arc<References> {
+        Arc::clone(&self.references.read().unwrap())
+    }
+
+    /// Output dimension found by the startup probe; `None` if the probe failed
+    pub fn dimension(&self) -> Option<usize> {
+        self.dimension
+    }
+
+    /// The ONNX model's declared inputs and outputs; `None` in mock mode
+    pub fn io(&self) -> Option<ModelIo> {
+        self.embedder.io()
+    }
+
+    /// Run [`WARMUP_RUNS`] inferences per session on a fixed string, returning how long they took.
+    ///
+    /// Bypasses the embedding cache and latency metrics, so it neither fills the cache nor skews
+    /// the inference histogram.
+    pub fn warm_up(&self) -> Result<Duration, Box<dyn Error + Send + Sync>> {
+        let started = Instant::now();
+        self.embedder.warm_up(WARMUP_TEXT, WARMUP_RUNS)?;
+        Ok(started.elapsed())
+    }
+
+    /// Re-read the reference embeddings from disk and swap them in, returning the new count.
+    ///
+    /// Requests already holding a snapshot finish against the old references. On any error,
+    /// including a dimension mismatch with the model, the current references are kept.
+    pub fn reload(&self) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let set = ReferenceSet::load(
+            &self.ref_path,
+            self.ref_conflict,
+            self.metric,
+            self.ann,
+            self.ann_probes,
+            model_fingerprint(&self.model_path),
+        )?;
+        let references = References::new(set);
+        check_not_empty(&references, &self.ref_path, self.allow_empty_refs)?;
+        check_dimension(self.dimension, &references, &self.model_path, &self.ref_path)?;
+
+        let count = references.embeddings.len();
+        *self.references.write().unwrap() = Arc::new(references);
+        Ok(count)
+    }
+
+    /// Embed a sentence, reusing a cached embedding when the same text was seen recently.
+    ///
+    /// Blocks for the whole inference; async code goes through [`Model::embed_blocking`] instead.
+    fn embed(&self, sentence: &str) -> Result<Vec<f32>, ColorizerError> {
+        let key = self.cache_key(sentence);
+        self.check_tokens(&key)?;
+        if let Some(emb) = self.cached(&key) {
+            return Ok(emb);
+        }
+
+        // Run inference without holding the lock
+        let emb = check_finite(self.infer(&key)?)?;
+        self.remember(key, &emb);
+        Ok(emb)
+    }
+
+    /// Like [`Model::embed`], but the inference joins the next batch instead of running alone
+    async fn embed_batched(&self, batcher: &Batcher, sentence: &str) -> Result<Vec<f32>, ColorizerError> {
+        let key = self.cache_key(sentence);
+        self.check_tokens(&key)?;
+        if let Some(emb) = self.cached(&key) {
+            return Ok(emb);
+        }
+
+        let emb = check_finite(batcher.embed(key.clone()).await?)?;
+        self.remember(key, &emb);
+        Ok(emb)
+    }
+
+    /// Reject texts with fewer real tokens than `COLORIZER_MIN_TOKENS`, cached or not
+    fn check_tokens(&self, text: &str) -> Result<(), ColorizerError> {
+        if self.min_tokens == 0 {
+            return Ok(());
+        }
+        let found = self.embedder.count_tokens(text)?.real;
+        if found < self.min_tokens {
+            return Err(ColorizerError::TooFewTokens {
+                found,
+                min: self.min_tokens,
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether more than `COLORIZER_UNK_THRESHOLD` of the text's tokens are unknown to the
+    /// tokenizer, so its embedding says little about the text and any match would be misleading
+    pub fn is_out_of_vocabulary(&self, text: &str) -> Result<bool, ColorizerError> {
+        if self.unk_threshold >= 1.0 {
+            return Ok(false);
+        }
+        let counts = self.embedder.count_tokens(&self.cache_key(text))?;
+        Ok(counts.unknown_ratio() > self.unk_threshold)
+    }
+
+    /// Key on the normalized text so e.g. "Love" and "love" share an entry when lowercasing
+    fn cache_key(&self, sentence: &str) -> String {
+        self.embedding_options.normalize_text(sentence.trim())
+    }
+
+    /// Cached embedding for `key`, if the cache is enabled and holds one
+    fn cached(&self, key: &str) -> Option<Vec<f32>> {
+        self.embed_cache.as_ref()?.lock().unwrap().get(key).cloned()
+    }
+
+    /// Store an embedding in the cache, if it's enabled
+    fn remember(&self, key: String, emb: &[f32]) {
+        if let Some(cache) = &self.embed_cache {
+            cache.lock().unwrap().put(key, emb.to_vec());
+        }
+    }
+
+    /// Embed a sentence on the blocking thread pool so inference doesn't stall the async worker,
+    /// batched with concurrent requests when micro-batching is enabled.
+    ///
+    /// Fails with [`ColorizerError::InferenceTimeout`] if it takes longer than the configured limit. The ONNX run
+    /// can't be interrupted, so a timed-out inference still finishes in the background (and, unless
+    /// batched, fills the cache) but the request no longer waits for it.
+    pub async fn embed_blocking(self: &Arc<Self>, sentence: &str) -> Result<Vec<f32>, ColorizerError> {
+        let model = Arc::clone(self);
+        let sentence = sentence.to_string();
+        let inference = async move {
+            if let Some(batcher) = &model.batcher {
+                return model.embed_batched(batcher, &sentence).await;
+            }
+            tokio::task::spawn_blocking(move || model.embed(&sentence))
+                .await
+                // Only a panic inside the inference task gets here
+                .map_err(|e| ColorizerError::Inference(e.to_string()))?
+        };
+
+        match self.inference_timeout {
+            Some(limit) => tokio::time::timeout(limit, inference)
+                .await
+                .unwrap_or(Err(ColorizerError::InferenceTimeout(limit))),
+            None => inference.await,
+        }
+    }
+
+    /// Run the model on a sentence, recording the inference latency
+    fn infer(&self, sentence: &str) -> Result<Vec<f32>, ColorizerError> {
+        let _timer = self.metrics.inference_duration.start_timer();
+        self.embedder.embed(sentence)
+    }
+}
+
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicUsize, Ordering as AtomicOrdering},
+    };
+
+    /// Embeds the words it was given as fixed vectors, and anything else as a hash-derived one,
+    /// counting every call
+    pub struct TableEmbedder {
+        table: HashMap<String, Vec<f32>>,
+        fallback: MockEmbedder,
+        pub calls: AtomicUsize,
+    }
+
+    impl TableEmbedder {
+        pub fn new(table: &[(&str, Vec<f32>)]) -> Self {
+            let dimension = table.first().map(|(_, v)| v.len());
+            TableEmbedder {
+                table: table.iter().map(|(word, v)| (word.to_string(), v.clone())).collect(),
+                fallback: MockEmbedder::new(dimension),
+                calls: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    impl Embedder for TableEmbedder {
+        fn embed(&self, text: &str) -> Result<Vec<f32>, ColorizerError> {
+            self.calls.fetch_add(1, AtomicOrdering::SeqCst);
+            match self.table.get(text) {
+                Some(v) => Ok(v.clone()),
+                None => self.fallback.embed(text),
+            }
+        }
+    }
+
+    /// Red, green and blue references along the three axes
+    pub fn rgb_references() -> ReferenceSet {
+        let entries = [
+            ("red", vec![1.0, 0.0, 0.0], (255, 0, 0)),
+            ("green", vec![0.0, 1.0, 0.0], (0, 255, 0)),
+            ("blue", vec![0.0, 0.0, 1.0], (0, 0, 255)),
+        ];
+        let entries = entries.map(|(word, embedding, color)| (word.to_string(), embedding, color));
+        ReferenceSet::from_entries(entries, SimilarityMetric::Cosine, false, 1).unwrap()
+    }
+
+    /// A model over `set` whose embeddings come from `embedder`, with the default configuration
+    pub fn test_model(embedder: Arc<dyn Embedder + Send + Sync>, set: ReferenceSet) -> Model {
+        test_model_with(embedder, set, &Config::for_tests())
+    }
+
+    pub fn test_model_with(embedder: Arc<dyn Embedder + Send + Sync>, set: ReferenceSet, config: &Config) -> Model {
+        let spec = test_spec(PathBuf::from("ref_embeddings.json"));
+        Model::new(embedder, References::new(set), &spec, config, test_metrics()).unwrap()
+    }
+
+    /// A spec with no tokenizer or model files, reading references from `ref_embeddings`
+    pub fn test_spec(ref_embeddings: PathBuf) -> ModelSpec {
+        ModelSpec {
+            tokenizer: PathBuf::from("tokenizer.json"),
+            model: PathBuf::from("model.onnx"),
+            ref_embeddings,
+        }
+    }
+
+    pub fn test_metrics() -> web::Data<Metrics> {
+        web::Data::new(Metrics::new().unwrap())
+    }
+
+    /// Overwrite the reference JSON at `path` with `entries`
+    pub fn write_references(path: &Path, entries: serde_json::Value) {
+        fs::write(path, entries.to_string()).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn repeated_text_is_embedded_once() {
+        let embedder = Arc::new(TableEmbedder::new(&[("love", vec![1.0, 0.0, 0.0])]));
+        let model = Arc::new(test_model(embedder.clone(), rgb_references()));
+        let probes = embedder.calls.load(AtomicOrdering::SeqCst);
+
+        let first = model.embed_blocking("love").await.unwrap();
+        let second = model.embed_blocking("love").await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(embedder.calls.load(AtomicOrdering::SeqCst) - probes, 1);
+    }
+
+    #[test]
+    fn mismatched_reference_dimension_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("refs.json");
+        let json = serde_json::json!([{ "word": "sun", "embedding": [0.6, 0.8], "color": [255, 200, 0] }]);
+        fs::write(&path, json.to_string()).unwrap();
+        let set = ReferenceSet::load(&path, ConflictPolicy::First, SimilarityMetric::Cosine, false, 1, 0).unwrap();
+        let embedder = Arc::new(TableEmbedder::new(&[("sun", vec![1.0, 0.0, 0.0])]));
+
+        let config = Config::for_tests();
+        let result = Model::new(embedder, References::new(set), &test_spec(path), &config, test_metrics());
+        let error = result.err().expect("a 3-dimensional model must not load 2-dimensional references");
+        assert!(error.to_string().contains("produces 3, but"), "{error}");
+        assert!(error.to_string().contains("has 2"), "{error}");
+    }
+
+    #[test]
+    fn an_empty_reference_file_is_refused_at_startup_unless_explicitly_allowed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("refs.json");
+        write_references(&path, serde_json::json!([]));
+        let mut config = Config::for_tests();
+
+        let error = Model::load(None, &test_spec(path.clone()), &config, test_metrics())
+            .err()
+            .expect("an empty reference file must not start");
+        assert!(error.to_string().starts_with("no reference colors loaded: "), "{error}");
+        assert!(error.to_string().contains("refs.json is empty"), "{error}");
+
+        // A fallback color alone doesn't make an empty palette acceptable
+        config.fallback_color = Some((10, 10, 10));
+        assert!(Model::load(None, &test_spec(path.clone()), &config, test_metrics()).is_err());
+
+        config.allow_empty_refs = true;
+        let model = Model::load(None, &test_spec(path), &config, test_metrics()).unwrap();
+        assert!(model.references().embeddings.is_empty());
+        assert_eq!(model.reload().unwrap(), 0);
+    }
+
+    #[test]
+    fn reload_swaps_in_a_changed_file_and_keeps_the_old_one_on_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("refs.json");
+        let sun = serde_json::json!({ "word": "sun", "embedding": [0.6, 0.8], "color": [255, 200, 0] });
+        let sea = serde_json::json!({ "word": "sea", "embedding": [0.0, 1.0], "color": [0, 80, 200] });
+        write_references(&path, serde_json::json!([sun]));
+        let model = Model::load(None, &test_spec(path.clone()), &Config::for_tests(), test_metrics()).unwrap();
+        let in_flight = model.references();
+
+        write_references(&path, serde_json::json!([sun, sea]));
+        assert_eq!(model.reload().unwrap(), 2);
+        assert_eq!(model.references().embeddings[1].word, "sea");
+        assert_eq!(in_flight.embeddings.len(), 1, "a request's snapshot must not change under it");
+
+        let sky = serde_json::json!({ "word": "sky", "embedding": [0.0, 0.6, 0.8], "color": [0, 0, 255] });
+        write_references(&path, serde_json::json!([sky]));
+        let error = model.reload().expect_err("a 3-dimensional file must not replace 2-dimensional references");
+        assert!(error.to_string().contains("dimension mismatch"), "{error}");
+
+        write_references(&path, serde_json::json!([]));
+        let error = model.reload().expect_err("an empty file must not replace the references");
+        assert!(error.to_string().contains("no reference colors loaded"), "{error}");
+
+        let current = model.references();
+        let words: Vec<&str> = current.embeddings.iter().map(|r| r.word.as_str()).collect();
+        assert_eq!(words, ["sun", "sea"], "failed reloads keep serving the last good references");
+    }
+}
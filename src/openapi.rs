@@ -0,0 +1,229 @@
+//! OpenAPI 3 description of the HTTP API, served at `/openapi.json`.
+//!
+//! Operations come from the `#[utoipa::path]` attribute on each handler in `main.rs` and schemas
+//! from the request and response types they name, so the document follows the code.
+
+use utoipa::{
+    openapi::{
+        security::{HttpAuthScheme, HttpBuilder, SecurityRequirement, SecurityScheme},
+        OpenApi as Document,
+    },
+    Modify, OpenApi, ToSchema,
+};
+
+use crate::{auth::PROTECTED_PATHS, ColorOutput, FormattedPaletteEntry, PaletteEntry, RankedColor, SentenceColor};
+
+/// Every route the server registers
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Colorizer",
+        description = "Maps text to colors by nearest-neighbor search over reference word embeddings",
+    ),
+    paths(
+        crate::color,
+        crate::color_get,
+        crate::color_debug,
+        crate::colors,
+        crate::colors_stream,
+        crate::nearest_word,
+        crate::palette_nearest,
+        crate::gradient,
+        crate::interpolate,
+        crate::palette,
+        crate::palette_png,
+        crate::swatch_png,
+        crate::embed,
+        crate::model_info,
+        crate::compare,
+        crate::reload,
+        crate::usage_report,
+        crate::healthz,
+        crate::version,
+        crate::metrics_endpoint,
+    ),
+    modifiers(&BearerAuth),
+)]
+pub struct ApiDoc;
+
+/// Declares the `bearerAuth` scheme and requires it on every operation under [`PROTECTED_PATHS`]
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, doc: &mut Document) {
+        let scheme = HttpBuilder::new()
+            .scheme(HttpAuthScheme::Bearer)
+            .description(Some("Required on these routes when `COLORIZER_API_KEY` is set"))
+            .build();
+        doc.components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme("bearerAuth", SecurityScheme::Http(scheme));
+
+        let requirement = SecurityRequirement::new("bearerAuth", Vec::<String>::new());
+        for (path, item) in doc.paths.paths.iter_mut() {
+            if !PROTECTED_PATHS.contains(&path.as_str()) {
+                continue;
+            }
+            let operations = [&mut item.get, &mut item.post].into_iter().flatten();
+            for operation in operations {
+                operation.security = Some(vec![requirement.clone()]);
+            }
+        }
+    }
+}
+
+// The handlers below pick their body's shape at runtime from the query string, so these enums only
+// exist to describe the alternatives; none is ever built.
+
+/// Body of `/color`: one color, a hex or CSS string with `format=hex|css`, `k` ranked colors, or one
+/// color per sentence with `split=sentence`
+#[allow(dead_code)]
+#[derive(ToSchema)]
+#[serde(untagged)]
+pub enum ColorResponse {
+    Color(ColorOutput),
+    Formatted(String),
+    Ranked(Vec<RankedColor>),
+    Sentences(Vec<SentenceColor<'static>>),
+}
+
+/// Body of `/colors`: one color per text, as objects or as strings with `format=hex|css`
+#[allow(dead_code)]
+#[derive(ToSchema)]
+#[serde(untagged)]
+pub enum ColorsResponse {
+    Colors(Vec<ColorOutput>),
+    Formatted(Vec<String>),
+}
+
+/// Body of `/palette`: every reference word with its color, formatted with `format=hex|css`
+#[allow(dead_code)]
+#[derive(ToSchema)]
+#[serde(untagged)]
+pub enum PaletteResponse {
+    Entries(Vec<PaletteEntry<'static>>),
+    Formatted(Vec<FormattedPaletteEntry<'static>>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color_space::{rgb_to_cmyk, rgb_to_hsl},
+        openapi_json, WordProbability,
+    };
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, init_service, read_body_json, TestRequest},
+        App,
+    };
+    use serde_json::{json, Value};
+
+    fn document() -> Value {
+        serde_json::to_value(ApiDoc::openapi()).unwrap()
+    }
+
+    /// Names of the `parameters` of one operation, in order
+    fn parameter_names(operation: &Value) -> Vec<&str> {
+        operation["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["name"].as_str().unwrap())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn document_is_served_at_openapi_json() {
+        let app = init_service(App::new().service(openapi_json)).await;
+        let res = call_service(&app, TestRequest::get().uri("/openapi.json").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let served: Value = read_body_json(res).await;
+        assert_eq!(served, document());
+        assert_eq!(served["info"]["version"], env!("CARGO_PKG_VERSION"));
+        for path in ["/color", "/colors", "/colors/stream", "/palette", "/interpolate", "/healthz"] {
+            assert!(served["paths"][path].is_object(), "{} is missing", path);
+        }
+    }
+
+    #[test]
+    fn exactly_the_protected_paths_require_the_bearer_token() {
+        let doc = document();
+        assert_eq!(doc["components"]["securitySchemes"]["bearerAuth"]["scheme"], "bearer");
+        for (path, item) in doc["paths"].as_object().unwrap() {
+            for (method, operation) in item.as_object().unwrap() {
+                let secured = operation["security"] == json!([{ "bearerAuth": [] }]);
+                assert_eq!(secured, PROTECTED_PATHS.contains(&path.as_str()), "{} {}", method, path);
+            }
+        }
+    }
+
+    #[test]
+    fn color_output_schema_lists_every_serialized_field() {
+        let rgb = (255, 0, 0);
+        let output = ColorOutput {
+            word: Some("red".to_string()),
+            similarity: Some(0.9),
+            matched: Some(true),
+            name: Some("red"),
+            fallback: Some(false),
+            hsl: Some(rgb_to_hsl(rgb)),
+            cmyk: Some(rgb_to_cmyk(rgb)),
+            contrast_ratio: Some(4.0),
+            distribution: Some(vec![WordProbability {
+                word: "red".to_string(),
+                probability: 1.0,
+            }]),
+            ..ColorOutput::new(rgb)
+        };
+        let serialized = serde_json::to_value(output).unwrap();
+        let mut fields: Vec<&String> = serialized.as_object().unwrap().keys().collect();
+        fields.sort();
+
+        let doc = document();
+        let schema = &doc["components"]["schemas"]["ColorOutput"];
+        let mut documented: Vec<&String> = schema["properties"].as_object().unwrap().keys().collect();
+        documented.sort();
+        assert_eq!(documented, fields);
+        assert_eq!(schema["required"], json!(["r", "g", "b"]));
+    }
+
+    #[test]
+    fn color_takes_text_input_and_color_query_options() {
+        let doc = document();
+        let text_input = &doc["components"]["schemas"]["TextInput"];
+        assert_eq!(text_input["required"], json!(["text"]));
+        assert_eq!(text_input["properties"]["k"]["default"], 1);
+        assert_eq!(text_input["properties"]["split"]["$ref"], "#/components/schemas/SplitMode");
+        assert_eq!(text_input["properties"]["extra_refs"]["maxItems"], 32);
+
+        let post = &doc["paths"]["/color"]["post"];
+        let body = &post["requestBody"]["content"]["application/json"]["schema"];
+        assert_eq!(body["$ref"], "#/components/schemas/TextInput");
+        let expected = [
+            "format",
+            "space",
+            "quantize",
+            "warmth",
+            "cvd",
+            "explain",
+            "distribution",
+            "temp",
+            "blend_space",
+            "rank",
+            "name",
+            "Idempotency-Key",
+        ];
+        assert_eq!(parameter_names(post), expected);
+
+        // GET carries the body's options in the query string, ahead of the same color options
+        let get = &doc["paths"]["/color"]["get"];
+        let names = parameter_names(get);
+        assert_eq!(names[..8], ["text", "k", "blend", "min_similarity", "jitter", "seed", "split", "model"]);
+        assert_eq!(names[8..], expected);
+        assert_eq!(get["parameters"][0]["required"], true);
+        let cvd = json!(["none", "protanopia", "deuteranopia", "tritanopia"]);
+        assert_eq!(get["parameters"][12]["schema"]["enum"], cvd);
+    }
+}
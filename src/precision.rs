@@ -0,0 +1,46 @@
+//! Storage precision of reference embeddings, written by `generate_ref_embeddings` and detected
+//! by the server when loading.
+
+use crate::env::env_parse;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt, str::FromStr};
+
+/// How reference embedding values are stored (`COLORIZER_REF_PRECISION`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Precision {
+    /// 32-bit floats; the reference file is a plain JSON array
+    #[default]
+    F32,
+    /// 16-bit floats, halving reference memory. The file is a JSON object tagged
+    /// `"precision": "f16"` with each value stored as its 16-bit pattern.
+    F16,
+}
+
+impl Precision {
+    /// Read `COLORIZER_REF_PRECISION`, defaulting to f32
+    pub fn from_env() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        env_parse("COLORIZER_REF_PRECISION", Precision::default())
+    }
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "f32" => Ok(Precision::F32),
+            "f16" => Ok(Precision::F16),
+            other => Err(format!("unknown precision {:?} (expected f32 or f16)", other)),
+        }
+    }
+}
+
+impl fmt::Display for Precision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Precision::F32 => "f32",
+            Precision::F16 => "f16",
+        })
+    }
+}
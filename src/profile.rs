@@ -0,0 +1,103 @@
+//! Named presets (`COLORIZER_PROFILE`) matching the embedding recipe of common sentence-transformer
+//! models.
+//!
+//! A preset fills in the pooling, normalization and metric settings in one go. Each setting it
+//! covers can still be overridden by its own variable, in the environment or the config file.
+
+use std::{fmt, str::FromStr};
+
+/// A preset of embedding settings
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Profile {
+    /// `sentence-transformers/all-MiniLM-L6-v2`
+    MiniLm,
+    /// `sentence-transformers/all-mpnet-base-v2`
+    Mpnet,
+    /// No preset; every setting comes from its own variable or its default
+    #[default]
+    Custom,
+}
+
+impl Profile {
+    /// Values the preset supplies, keyed by the variable they stand in for
+    pub fn settings(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            // Mean pooling over every attended token, [CLS] and [SEP] included, then L2
+            // normalization, so embeddings are compared by cosine. Trained on 256-token inputs.
+            Profile::MiniLm => &[
+                ("COLORIZER_POOLING", "mean"),
+                ("COLORIZER_EXCLUDE_SPECIAL", "0"),
+                ("COLORIZER_PRE_POOL_NORM", "0"),
+                ("COLORIZER_METRIC", "cosine"),
+                ("COLORIZER_MAX_TOKENS", "256"),
+            ],
+            // The same recipe as MiniLM, with the longer 384-token inputs mpnet was trained on
+            Profile::Mpnet => &[
+                ("COLORIZER_POOLING", "mean"),
+                ("COLORIZER_EXCLUDE_SPECIAL", "0"),
+                ("COLORIZER_PRE_POOL_NORM", "0"),
+                ("COLORIZER_METRIC", "cosine"),
+                ("COLORIZER_MAX_TOKENS", "384"),
+            ],
+            Profile::Custom => &[],
+        }
+    }
+
+    /// The preset's value for `key`, if it sets one
+    pub fn value(self, key: &str) -> Option<&'static str> {
+        self.settings().iter().find(|(name, _)| *name == key).map(|(_, value)| *value)
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "minilm" => Ok(Profile::MiniLm),
+            "mpnet" => Ok(Profile::Mpnet),
+            "custom" => Ok(Profile::Custom),
+            other => Err(format!("unknown profile {:?} (expected minilm, mpnet or custom)", other)),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Profile::MiniLm => "minilm",
+            Profile::Mpnet => "mpnet",
+            Profile::Custom => "custom",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{embedding::PoolingStrategy, metric::SimilarityMetric};
+
+    /// The settings a preset applies, parsed as the binaries parse them
+    fn applied(profile: Profile) -> (PoolingStrategy, bool, bool, SimilarityMetric, usize) {
+        let value = |key: &str| profile.value(key).unwrap();
+        (
+            value("COLORIZER_POOLING").parse().unwrap(),
+            value("COLORIZER_EXCLUDE_SPECIAL") == "1",
+            value("COLORIZER_PRE_POOL_NORM") == "1",
+            value("COLORIZER_METRIC").parse().unwrap(),
+            value("COLORIZER_MAX_TOKENS").parse().unwrap(),
+        )
+    }
+
+    #[test]
+    fn each_preset_applies_its_models_recipe() {
+        assert_eq!("MiniLM".parse::<Profile>(), Ok(Profile::MiniLm));
+        let minilm = applied(Profile::MiniLm);
+        assert_eq!(minilm, (PoolingStrategy::Mean, false, false, SimilarityMetric::Cosine, 256));
+        let mpnet = applied("mpnet".parse().unwrap());
+        assert_eq!(mpnet, (PoolingStrategy::Mean, false, false, SimilarityMetric::Cosine, 384));
+
+        assert!(Profile::Custom.settings().is_empty());
+        assert!("bert".parse::<Profile>().unwrap_err().contains("unknown profile \"bert\""));
+    }
+}
@@ -0,0 +1,609 @@
+use crate::{
+    ann::IvfIndex,
+    embedding::l2_normalize,
+    metric::{l2_norm, SimilarityMetric},
+    precision::Precision,
+};
+use half::f16;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Bumped whenever the cached layout changes, so old caches are ignored
+const CACHE_VERSION: u32 = 4;
+
+/// Embedding values at the precision the reference file was written in
+#[derive(Deserialize, Serialize)]
+pub enum Vector {
+    F32(Vec<f32>),
+    F16(Vec<f16>),
+}
+
+impl Vector {
+    pub fn len(&self) -> usize {
+        match self {
+            Vector::F32(v) => v.len(),
+            Vector::F16(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn norm(&self) -> f32 {
+        match self {
+            Vector::F32(v) => l2_norm(v),
+            Vector::F16(v) => l2_norm(v),
+        }
+    }
+}
+
+/// Reference embedding with an associated RGB color.
+#[derive(Deserialize, Serialize)]
+pub struct RefEmbedding {
+    pub word: String,
+    pub embedding: Vector,
+    pub color: (u8, u8, u8),
+    /// L2 norm of `embedding`, filled in by [`RefEmbedding::cache_norm`] after loading rather
+    /// than stored in the cache
+    #[serde(skip)]
+    pub norm: f32,
+}
+
+impl RefEmbedding {
+    /// Compute and keep the embedding's norm, so cosine scoring doesn't redo it per request
+    pub fn cache_norm(&mut self) {
+        self.norm = self.embedding.norm();
+    }
+}
+
+/// One entry of the reference JSON, with values of type `T`
+#[derive(Deserialize)]
+struct SourceEmbedding<T> {
+    /// Source word; empty for files generated before words were recorded
+    #[serde(default)]
+    word: String,
+    embedding: Vec<T>,
+    color: (u8, u8, u8),
+}
+
+/// Reference JSON written with `COLORIZER_REF_PRECISION=f16`
+#[derive(Deserialize)]
+struct TaggedSource {
+    precision: Precision,
+    references: Vec<SourceEmbedding<f16>>,
+}
+
+/// A parsed reference file: its precision and the values widened to f32
+type Source = (Precision, Vec<SourceEmbedding<f32>>);
+
+/// Which entry wins when several reference files define the same word (`COLORIZER_REF_CONFLICT`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the entries from the first file listing the word
+    #[default]
+    First,
+    /// Let later files override earlier ones
+    Last,
+    /// Refuse to load
+    Error,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "first" => Ok(ConflictPolicy::First),
+            "last" => Ok(ConflictPolicy::Last),
+            "error" => Ok(ConflictPolicy::Error),
+            other => Err(format!("unknown conflict policy {:?} (expected first, last or error)", other)),
+        }
+    }
+}
+
+impl fmt::Display for ConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConflictPolicy::First => "first",
+            ConflictPolicy::Last => "last",
+            ConflictPolicy::Error => "error",
+        })
+    }
+}
+
+/// Expand a reference path setting into the files it names: a comma-separated list, a directory
+/// (every `.json` file in it, by name), or a single file
+pub fn resolve_paths(spec: &Path) -> Result<Vec<PathBuf>, Box<dyn Error + Send + Sync>> {
+    let spec_str = spec.to_string_lossy();
+    if spec_str.contains(',') {
+        return Ok(spec_str
+            .split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+            .collect());
+    }
+    if !spec.is_dir() {
+        return Ok(vec![spec.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(spec)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "json") {
+            files.push(path);
+        }
+    }
+    if files.is_empty() {
+        return Err(format!("no .json reference files in {}", spec.display()).into());
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Where the binary cache for `spec` lives: next to a single file, or inside a directory
+fn cache_path(spec: &Path, files: &[PathBuf]) -> PathBuf {
+    match files {
+        [file] => file.with_extension("bin"),
+        _ if spec.is_dir() => spec.join("merged.bin"),
+        _ => files[0].with_extension("merged.bin"),
+    }
+}
+
+/// Hash of every file's contents plus the conflict policy, which together determine the merge
+fn sources_hash(sources: &[(PathBuf, Vec<u8>)], policy: ConflictPolicy) -> u64 {
+    match sources {
+        [(_, json)] => content_hash(json),
+        _ => {
+            let mut bytes: Vec<u8> = sources.iter().flat_map(|(_, json)| content_hash(json).to_le_bytes()).collect();
+            bytes.extend_from_slice(policy.to_string().as_bytes());
+            content_hash(&bytes)
+        }
+    }
+}
+
+/// Parse every file and concatenate their entries, resolving words defined in more than one file
+/// by `policy`.
+///
+/// Duplicates within one file, and entries without a word, are kept as they are. Files must agree
+/// on precision and embedding dimension.
+fn merge_sources(
+    sources: &[(PathBuf, Vec<u8>)],
+    policy: ConflictPolicy,
+) -> Result<(Source, usize), Box<dyn Error + Send + Sync>> {
+    let mut precision = None;
+    let mut dimension: Option<(usize, &Path)> = None;
+    // Index of the file whose entries win for each word
+    let mut owners: HashMap<String, usize> = HashMap::new();
+    let mut entries = Vec::new();
+
+    for (i, (path, json)) in sources.iter().enumerate() {
+        let (file_precision, file_entries) =
+            parse_source(json).map_err(|e| format!("invalid reference file {}: {}", path.display(), e))?;
+        match precision {
+            None => precision = Some(file_precision),
+            Some(p) if p != file_precision => {
+                return Err(format!("{} is {}, but earlier reference files are {}", path.display(), file_precision, p).into());
+            }
+            Some(_) => {}
+        }
+        if let Some(first) = file_entries.first() {
+            let dim = first.embedding.len();
+            match dimension {
+                None => dimension = Some((dim, path)),
+                Some((expected, first_path)) if expected != dim => {
+                    return Err(format!(
+                        "{} has dimension {}, but {} has {}",
+                        path.display(),
+                        dim,
+                        first_path.display(),
+                        expected
+                    )
+                    .into());
+                }
+                Some(_) => {}
+            }
+        }
+
+        for entry in &file_entries {
+            if entry.word.is_empty() {
+                continue;
+            }
+            match owners.entry(entry.word.clone()) {
+                Entry::Vacant(vacant) => {
+                    vacant.insert(i);
+                }
+                Entry::Occupied(mut owner) if *owner.get() != i => match policy {
+                    ConflictPolicy::First => {}
+                    ConflictPolicy::Last => *owner.get_mut() = i,
+                    ConflictPolicy::Error => {
+                        return Err(format!(
+                            "word {:?} is defined in both {} and {}",
+                            entry.word,
+                            sources[*owner.get()].0.display(),
+                            path.display()
+                        )
+                        .into());
+                    }
+                },
+                Entry::Occupied(_) => {}
+            }
+        }
+        entries.extend(file_entries.into_iter().map(|entry| (i, entry)));
+    }
+
+    let total = entries.len();
+    let merged: Vec<_> = entries
+        .into_iter()
+        .filter(|(i, entry)| entry.word.is_empty() || owners[&entry.word] == *i)
+        .map(|(_, entry)| entry)
+        .collect();
+    let dropped = total - merged.len();
+    Ok(((precision.unwrap_or_default(), merged), dropped))
+}
+
+/// Reference embeddings prepared for scoring: normalized, optionally indexed
+#[derive(Deserialize, Serialize)]
+pub struct ReferenceSet {
+    /// FNV-1a hash of the source JSON, used to detect a stale cache
+    pub content_hash: u64,
+    /// In file order, which `generate_ref_embeddings` sorts by word; never reordered on load
+    pub embeddings: Vec<RefEmbedding>,
+    /// Precision of the source file, which the embeddings are kept in
+    pub precision: Precision,
+    /// Metric the embeddings were prepared for; only cosine normalizes them
+    pub metric: SimilarityMetric,
+    /// Whether every embedding was L2-normalized
+    pub normalized: bool,
+    pub ann_index: Option<IvfIndex>,
+}
+
+/// On-disk binary cache of a [`ReferenceSet`]
+#[derive(Deserialize, Serialize)]
+struct CacheFile {
+    version: u32,
+    /// [`ReferenceSet::load`]'s `model_fingerprint` when the cache was written
+    model_fingerprint: u64,
+    set: ReferenceSet,
+}
+
+/// 64-bit FNV-1a hash; stable across runs and Rust versions, unlike `DefaultHasher`
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Parse either reference JSON layout, widening f16 values to f32.
+///
+/// A plain array is the f32 layout; an object is the tagged layout written for other precisions.
+fn parse_source(json: &[u8]) -> Result<Source, Box<dyn Error + Send + Sync>> {
+    if json.iter().find(|b| !b.is_ascii_whitespace()) != Some(&b'{') {
+        return Ok((Precision::F32, serde_json::from_slice(json)?));
+    }
+
+    let tagged: TaggedSource = serde_json::from_slice(json)?;
+    if tagged.precision != Precision::F16 {
+        return Err(format!("tagged reference files must be f16, got {}", tagged.precision).into());
+    }
+    let embeddings = tagged
+        .references
+        .into_iter()
+        .map(|r| SourceEmbedding {
+            word: r.word,
+            embedding: r.embedding.into_iter().map(f16::to_f32).collect(),
+            color: r.color,
+        })
+        .collect();
+    Ok((Precision::F16, embeddings))
+}
+
+impl ReferenceSet {
+    /// Prepare parsed reference embeddings: normalize them for cosine, and build the ANN index if
+    /// requested
+    fn from_source(
+        (precision, mut embeddings): Source,
+        hash: u64,
+        metric: SimilarityMetric,
+        ann: bool,
+        ann_probes: usize,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        // Every reference must share one dimension so the startup check against the model holds
+        if let Some(first) = embeddings.first() {
+            let dim = first.embedding.len();
+            if let Some(i) = embeddings.iter().position(|r| r.embedding.len() != dim) {
+                return Err(format!(
+                    "reference {} has dimension {}, expected {}",
+                    i,
+                    embeddings[i].embedding.len(),
+                    dim
+                )
+                .into());
+            }
+        }
+
+        // Normalize references once so cosine requests can score with a plain dot product. Other
+        // metrics depend on the raw magnitudes, so they are left as they are.
+        let mut normalized = metric.normalizes();
+        if normalized {
+            for ref_emb in &mut embeddings {
+                normalized &= l2_normalize(&mut ref_emb.embedding);
+            }
+        }
+
+        // The ANN index relies on dot products, so it needs every reference normalized
+        let ann_index = if ann && normalized && !embeddings.is_empty() {
+            let vectors: Vec<&[f32]> = embeddings.iter().map(|r| r.embedding.as_slice()).collect();
+            Some(IvfIndex::build(&vectors, ann_probes))
+        } else {
+            if ann && !metric.normalizes() {
+                eprintln!("⚠️  ANN index disabled: it requires COLORIZER_METRIC=cosine, not {}", metric);
+            } else if ann && !normalized {
+                eprintln!("⚠️  ANN index disabled: reference embeddings could not all be normalized");
+            }
+            None
+        };
+
+        // Normalization and indexing happen in f32; only then are f16 references narrowed again
+        let embeddings = embeddings
+            .into_iter()
+            .map(|r| RefEmbedding {
+                embedding: match precision {
+                    Precision::F32 => Vector::F32(r.embedding),
+                    Precision::F16 => Vector::F16(r.embedding.into_iter().map(f16::from_f32).collect()),
+                },
+                word: r.word,
+                color: r.color,
+                norm: 0.0,
+            })
+            .collect();
+
+        Ok(ReferenceSet {
+            content_hash: hash,
+            embeddings,
+            precision,
+            metric,
+            normalized,
+            ann_index,
+        })
+    }
+
+    /// Load reference embeddings from `spec` (see [`resolve_paths`]), reusing the binary cache
+    /// when it is fresh.
+    ///
+    /// The cache (`<path>.bin` for a single file) is keyed by a hash of the JSON contents and by
+    /// `model_fingerprint`, which identifies the model the references are served with, so editing
+    /// any file or swapping the model invalidates it. A missing, stale or unreadable cache falls
+    /// back to parsing the JSON and rewriting the cache.
+    pub fn load(
+        spec: &Path,
+        policy: ConflictPolicy,
+        metric: SimilarityMetric,
+        ann: bool,
+        ann_probes: usize,
+        model_fingerprint: u64,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let files = resolve_paths(spec)?;
+        let sources = files
+            .iter()
+            .map(|path| Ok((path.clone(), fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        let hash = sources_hash(&sources, policy);
+        let cache_path = cache_path(spec, &files);
+
+        // A cache prepared for another metric has the wrong (normalized or raw) embeddings
+        if let Some(mut set) = read_cache(&cache_path, hash, model_fingerprint).filter(|set| set.metric == metric) {
+            // The index is only reused if ANN is still wanted; it is rebuilt if newly enabled
+            if ann == set.ann_index.is_some() || !set.normalized {
+                if let Some(ivf) = &mut set.ann_index {
+                    ivf.set_probes(ann_probes);
+                }
+                println!("  • ref cache:      {} (fresh)", cache_path.display());
+                return Ok(set);
+            }
+        }
+
+        let source = match sources.as_slice() {
+            [(_, json)] => parse_source(json)?,
+            _ => {
+                let (source, dropped) = merge_sources(&sources, policy)?;
+                println!(
+                    "  • ref files:      {} merged, {} duplicate entries dropped ({})",
+                    sources.len(),
+                    dropped,
+                    policy
+                );
+                source
+            }
+        };
+        let set = Self::from_source(source, hash, metric, ann, ann_probes)?;
+        match write_cache(&cache_path, &set, model_fingerprint) {
+            Ok(()) => println!("  • ref cache:      {} (written)", cache_path.display()),
+            Err(e) => eprintln!("⚠️  Could not write reference cache {}: {}", cache_path.display(), e),
+        }
+        Ok(set)
+    }
+
+    /// Prepare in-memory `(word, embedding, color)` entries, such as a synthetic palette, the way
+    /// [`ReferenceSet::load`] prepares a file's. The content hash covers the words only.
+    pub fn from_entries(
+        entries: impl IntoIterator<Item = (String, Vec<f32>, (u8, u8, u8))>,
+        metric: SimilarityMetric,
+        ann: bool,
+        ann_probes: usize,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let source: Vec<SourceEmbedding<f32>> = entries
+            .into_iter()
+            .map(|(word, embedding, color)| SourceEmbedding { word, embedding, color })
+            .collect();
+        let words: Vec<&str> = source.iter().map(|entry| entry.word.as_str()).collect();
+        let hash = content_hash(words.join("\n").as_bytes());
+        Self::from_source((Precision::F32, source), hash, metric, ann, ann_probes)
+    }
+}
+
+/// Read the cache if it exists, has the current layout, and matches `hash` and `model_fingerprint`
+fn read_cache(cache_path: &Path, hash: u64, model_fingerprint: u64) -> Option<ReferenceSet> {
+    let bytes = fs::read(cache_path).ok()?;
+    let cache: CacheFile = bincode::deserialize(&bytes).ok()?;
+    let fresh = cache.version == CACHE_VERSION
+        && cache.model_fingerprint == model_fingerprint
+        && cache.set.content_hash == hash;
+    fresh.then_some(cache.set)
+}
+
+fn write_cache(cache_path: &Path, set: &ReferenceSet, model_fingerprint: u64) -> Result<(), Box<dyn Error + Send + Sync>> {
+    #[derive(Serialize)]
+    struct CacheFileRef<'a> {
+        version: u32,
+        model_fingerprint: u64,
+        set: &'a ReferenceSet,
+    }
+
+    let bytes = bincode::serialize(&CacheFileRef {
+        version: CACHE_VERSION,
+        model_fingerprint,
+        set,
+    })?;
+    fs::write(cache_path, bytes)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_palette(path: &Path, color: (u8, u8, u8)) {
+        let json = serde_json::json!([{ "word": "sun", "embedding": [0.6, 0.8], "color": color }]);
+        fs::write(path, json.to_string()).unwrap();
+    }
+
+    /// A reference JSON entry
+    fn entry(word: &str, embedding: &[f32], color: (u8, u8, u8)) -> serde_json::Value {
+        serde_json::json!({ "word": word, "embedding": embedding, "color": color })
+    }
+
+    fn load_merged(spec: &Path, policy: ConflictPolicy) -> Result<ReferenceSet, Box<dyn Error + Send + Sync>> {
+        ReferenceSet::load(spec, policy, SimilarityMetric::Cosine, false, 1, 0)
+    }
+
+    fn load(path: &Path, model_fingerprint: u64) -> ReferenceSet {
+        ReferenceSet::load(path, ConflictPolicy::First, SimilarityMetric::Cosine, false, 1, model_fingerprint).unwrap()
+    }
+
+    #[test]
+    fn cache_is_reused_until_the_json_or_model_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("refs.json");
+        let cache = path.with_extension("bin");
+        write_palette(&path, (255, 200, 0));
+
+        let set = load(&path, 1);
+        assert!(read_cache(&cache, set.content_hash, 1).is_some());
+        assert!(read_cache(&cache, set.content_hash, 2).is_none(), "another model must not reuse the cache");
+
+        write_palette(&path, (250, 250, 250));
+        let edited = load(&path, 1);
+        assert_ne!(edited.content_hash, set.content_hash);
+        assert_eq!(edited.embeddings[0].color, (250, 250, 250));
+        assert!(read_cache(&cache, set.content_hash, 1).is_none(), "the old JSON's cache was replaced");
+        assert!(read_cache(&cache, edited.content_hash, 1).is_some());
+    }
+
+    #[test]
+    fn cache_for_another_model_is_rebuilt() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("refs.json");
+        write_palette(&path, (255, 200, 0));
+        load(&path, 1);
+
+        // Tamper with the cache so it shows whether it was served
+        let cache = path.with_extension("bin");
+        let mut stale: CacheFile = bincode::deserialize(&fs::read(&cache).unwrap()).unwrap();
+        stale.set.embeddings[0].color = (0, 0, 0);
+        fs::write(&cache, bincode::serialize(&stale).unwrap()).unwrap();
+        assert_eq!(load(&path, 1).embeddings[0].color, (0, 0, 0), "a fresh cache is reused as is");
+        assert_eq!(load(&path, 2).embeddings[0].color, (255, 200, 0));
+    }
+
+    #[test]
+    fn f16_keeps_recall_at_1_on_the_built_in_palette() {
+        use crate::matching::References;
+        use rand_pcg::{
+            rand_core::{RngCore, SeedableRng},
+            Pcg32,
+        };
+
+        let json = fs::read("custom/ref_embeddings.json").unwrap();
+        let prepare = |precision: Precision| {
+            let (_, mut source) = parse_source(&json).unwrap();
+            if precision == Precision::F16 {
+                // As written by `generate_ref_embeddings` with `COLORIZER_REF_PRECISION=f16`
+                for r in &mut source {
+                    r.embedding.iter_mut().for_each(|x| *x = f16::from_f32(*x).to_f32());
+                }
+            }
+            let set = ReferenceSet::from_source((precision, source), 0, SimilarityMetric::Cosine, false, 1).unwrap();
+            References::new(set)
+        };
+        let (full, half) = (prepare(Precision::F32), prepare(Precision::F16));
+        assert!(matches!(half.embeddings[0].embedding, Vector::F16(_)));
+
+        // A query near every fourth reference (the scan is slow in debug builds), slightly
+        // perturbed so it isn't an exact copy
+        let mut rng = Pcg32::seed_from_u64(3);
+        let recall = |refs: &References, queries: &[(usize, Vec<f32>)]| {
+            let hits = queries.iter().filter(|(i, q)| refs.top_matches(q.clone(), 1)[0].index == *i).count();
+            hits as f32 / queries.len() as f32
+        };
+        let queries: Vec<(usize, Vec<f32>)> = full
+            .embeddings
+            .iter()
+            .enumerate()
+            .step_by(4)
+            .map(|(i, r)| {
+                let Vector::F32(v) = &r.embedding else { unreachable!() };
+                (i, v.iter().map(|x| x + (rng.next_u32() as f32 / u32::MAX as f32 - 0.5) * 0.01).collect())
+            })
+            .collect();
+        assert!(recall(&full, &queries) > 0.9);
+        assert_eq!(recall(&half, &queries), recall(&full, &queries));
+    }
+
+    #[test]
+    fn two_files_merge_by_conflict_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let (emotions, nature) = (dir.path().join("emotions.json"), dir.path().join("nature.json"));
+        let joy = entry("joy", &[1.0, 0.0], (255, 220, 0));
+        let calm = entry("calm", &[0.0, 1.0], (120, 180, 255));
+        let moss = entry("moss", &[0.8, 0.6], (80, 120, 40));
+        fs::write(&emotions, serde_json::json!([joy, calm]).to_string()).unwrap();
+        let teal_calm = entry("calm", &[0.6, 0.8], (0, 128, 128));
+        fs::write(&nature, serde_json::json!([teal_calm, moss]).to_string()).unwrap();
+        let color_of = |set: &ReferenceSet, word: &str| set.embeddings.iter().find(|r| r.word == word).map(|r| r.color);
+
+        // A directory is read in file-name order, like the equivalent comma-separated list
+        let list = PathBuf::from(format!("{},{}", emotions.display(), nature.display()));
+        for spec in [dir.path().to_path_buf(), list.clone()] {
+            let first = load_merged(&spec, ConflictPolicy::First).unwrap();
+            let words: Vec<&str> = first.embeddings.iter().map(|r| r.word.as_str()).collect();
+            assert_eq!(words, ["joy", "calm", "moss"]);
+            assert_eq!(color_of(&first, "calm"), Some((120, 180, 255)));
+        }
+        let last = load_merged(&list, ConflictPolicy::Last).unwrap();
+        assert_eq!(last.embeddings.len(), 3);
+        assert_eq!(color_of(&last, "calm"), Some((0, 128, 128)));
+        let error = load_merged(&list, ConflictPolicy::Error).err().unwrap().to_string();
+        assert!(error.contains("\"calm\" is defined in both"), "{error}");
+
+        let moss_3d = entry("moss", &[0.8, 0.6, 0.0], (80, 120, 40));
+        fs::write(&nature, serde_json::json!([moss_3d]).to_string()).unwrap();
+        let error = load_merged(&list, ConflictPolicy::First).err().unwrap().to_string();
+        assert!(error.contains("has dimension 3"), "{error}");
+    }
+}
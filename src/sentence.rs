@@ -0,0 +1,45 @@
+//! Punctuation-based sentence splitting for `"split": "sentence"`.
+
+/// Whether `c` ends a sentence
+fn is_terminator(c: char) -> bool {
+    matches!(c, '.' | '!' | '?')
+}
+
+/// Split text after `.`, `!` or `?` when followed by whitespace or the end of the text.
+///
+/// Runs of terminators such as `?!` or `...` stay with their sentence, and a terminator followed
+/// by anything else (`3.14`, `e.g.,`) doesn't split. Sentences are trimmed, and pieces without any
+/// letter or digit are dropped.
+pub fn split_sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if !is_terminator(c) {
+            continue;
+        }
+        let mut end = i + c.len_utf8();
+        while let Some(&(j, next)) = chars.peek().filter(|&&(_, next)| is_terminator(next)) {
+            end = j + next.len_utf8();
+            chars.next();
+        }
+        if let Some(&(_, next)) = chars.peek() {
+            if !next.is_whitespace() {
+                continue;
+            }
+        }
+
+        push_sentence(&mut sentences, &text[start..end]);
+        start = end;
+    }
+    push_sentence(&mut sentences, &text[start..]);
+    sentences
+}
+
+fn push_sentence<'a>(sentences: &mut Vec<&'a str>, piece: &'a str) {
+    let sentence = piece.trim();
+    if sentence.chars().any(char::is_alphanumeric) {
+        sentences.push(sentence);
+    }
+}
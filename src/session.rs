@@ -0,0 +1,147 @@
+//! ONNX session construction, shared so both binaries pick the same execution provider.
+
+use crate::env::env_parse;
+use ort::{
+    environment::Environment,
+    execution_providers::{ExecutionProvider, CUDAExecutionProviderOptions, CoreMLExecutionProviderOptions},
+    session::{Session, SessionBuilder},
+};
+use std::{
+    error::Error,
+    fmt,
+    num::NonZeroUsize,
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// Hardware backend used to run the model (`COLORIZER_EP`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Provider {
+    /// ONNX Runtime's default CPU provider
+    #[default]
+    Cpu,
+    /// NVIDIA GPUs; needs an ONNX Runtime build with CUDA support
+    Cuda,
+    /// Apple's Core ML, on macOS
+    CoreMl,
+}
+
+impl Provider {
+    /// Read `COLORIZER_EP`, defaulting to the CPU provider
+    pub fn from_env() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        env_parse("COLORIZER_EP", Provider::default())
+    }
+
+    fn execution_provider(self) -> Option<ExecutionProvider> {
+        match self {
+            Provider::Cpu => None,
+            Provider::Cuda => Some(ExecutionProvider::CUDA(CUDAExecutionProviderOptions::default())),
+            Provider::CoreMl => Some(ExecutionProvider::CoreML(CoreMLExecutionProviderOptions::default())),
+        }
+    }
+}
+
+impl FromStr for Provider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cpu" => Ok(Provider::Cpu),
+            "cuda" => Ok(Provider::Cuda),
+            "coreml" => Ok(Provider::CoreMl),
+            other => Err(format!("unknown execution provider {:?} (expected cpu, cuda or coreml)", other)),
+        }
+    }
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Provider::Cpu => "cpu",
+            Provider::Cuda => "cuda",
+            Provider::CoreMl => "coreml",
+        })
+    }
+}
+
+/// Load a model on the requested provider, falling back to CPU with a warning if it isn't available
+pub fn build_session(
+    environment: &Arc<Environment>,
+    model_path: &Path,
+    provider: Provider,
+) -> Result<Session, Box<dyn Error + Send + Sync>> {
+    load_session(environment, model_path, available_provider(provider))
+}
+
+/// The provider's execution provider if this build supports it; warns and returns `None` (CPU) otherwise
+fn available_provider(provider: Provider) -> Option<ExecutionProvider> {
+    let ep = provider.execution_provider()?;
+    if ep.is_available() {
+        Some(ep)
+    } else {
+        eprintln!("⚠️  Execution provider {} is not available in this build, falling back to cpu", provider);
+        None
+    }
+}
+
+fn load_session(
+    environment: &Arc<Environment>,
+    model_path: &Path,
+    ep: Option<ExecutionProvider>,
+) -> Result<Session, Box<dyn Error + Send + Sync>> {
+    let mut builder = SessionBuilder::new(environment)?;
+    if let Some(ep) = ep {
+        builder = builder.with_execution_providers([ep])?;
+    }
+    Ok(builder.with_model_from_file(model_path)?)
+}
+
+/// Sessions for one model, handed out round-robin (`COLORIZER_SESSION_POOL`).
+///
+/// ONNX Runtime allows concurrent `Run` calls on one session and `ort` marks `Session` as
+/// `Send + Sync`, so a pool of one is already safe to share across workers and needs no lock.
+/// More sessions let concurrent inferences work in separate memory arenas instead of contending
+/// on one, at the cost of holding the model weights once per session.
+pub struct SessionPool {
+    sessions: Vec<Session>,
+    next: AtomicUsize,
+}
+
+impl SessionPool {
+    /// Load `size` sessions of the same model, warning at most once about a provider fallback
+    pub fn build(
+        environment: &Arc<Environment>,
+        model_path: &Path,
+        provider: Provider,
+        size: NonZeroUsize,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let ep = available_provider(provider);
+        let sessions = (0..size.get())
+            .map(|_| load_session(environment, model_path, ep.clone()))
+            .collect::<Result<_, _>>()?;
+        Ok(SessionPool {
+            sessions,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// The next session in turn
+    pub fn get(&self) -> &Session {
+        let i = self.next.fetch_add(1, Ordering::Relaxed) % self.sessions.len();
+        &self.sessions[i]
+    }
+
+    /// Every session, e.g. to warm each one up
+    pub fn all(&self) -> &[Session] {
+        &self.sessions
+    }
+
+    /// Number of sessions in the pool
+    pub fn size(&self) -> usize {
+        self.sessions.len()
+    }
+}
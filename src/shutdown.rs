@@ -0,0 +1,80 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServerHandle, ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web, Error,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::signal;
+
+/// Number of requests currently being handled
+#[derive(Default)]
+pub struct InFlight(AtomicUsize);
+
+impl InFlight {
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn enter(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn exit(&self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Decrements the in-flight counter when the request finishes, even on error
+struct InFlightGuard(web::Data<InFlight>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.exit();
+    }
+}
+
+/// Middleware counting in-flight requests so shutdown can report how many it is draining
+pub async fn track_in_flight(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let _guard = req.app_data::<web::Data<InFlight>>().cloned().map(|in_flight| {
+        in_flight.enter();
+        InFlightGuard(in_flight)
+    });
+
+    next.call(req).await
+}
+
+/// Wait for SIGINT or SIGTERM, then stop accepting connections and drain in-flight requests.
+///
+/// Requests still running after the server's shutdown timeout are dropped.
+pub async fn shutdown_on_signal(handle: ServerHandle, in_flight: web::Data<InFlight>) {
+    wait_for_signal().await;
+    log::info!("shutting down, draining {} requests", in_flight.count());
+    handle.stop(true).await;
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut sigterm) => {
+            tokio::select! {
+                _ = signal::ctrl_c() => {}
+                _ = sigterm.recv() => {}
+            }
+        }
+        Err(e) => {
+            log::warn!("could not listen for SIGTERM, only SIGINT will trigger shutdown: {}", e);
+            let _ = signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = signal::ctrl_c().await;
+}
@@ -0,0 +1,164 @@
+//! PNG rendering of the palette for `/palette.png` and single-color swatches for `/swatch`.
+
+use image::{ImageFormat, Rgb, RgbImage, Rgba, RgbaImage};
+use std::io::Cursor;
+
+use crate::color_space::contrast_ratio;
+
+/// Side length of one swatch in pixels
+pub const CELL_SIZE: u32 = 32;
+
+/// Transparent gap between neighboring swatches
+const GAP: u32 = 2;
+
+/// Width and height of one glyph of the label font, in pixels
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+
+/// Pixels between a label and the edges of its swatch
+const LABEL_MARGIN: u32 = 2;
+
+/// Render `colors` as a grid of square swatches, `cols` per row, left to right and top to bottom.
+///
+/// With `labels`, each swatch also shows its label along the bottom edge, in black or white,
+/// whichever contrasts more. Cells past the last color in the final row stay transparent.
+pub fn render_png(colors: &[(u8, u8, u8)], labels: Option<&[&str]>, cols: u32) -> Result<Vec<u8>, image::ImageError> {
+    let rows = (colors.len() as u32).div_ceil(cols).max(1);
+    let pitch = CELL_SIZE + GAP;
+    let mut image = RgbaImage::from_fn(cols * pitch - GAP, rows * pitch - GAP, |x, y| {
+        let (col, row) = (x / pitch, y / pitch);
+        let in_gap = x % pitch >= CELL_SIZE || y % pitch >= CELL_SIZE;
+        match colors.get((row * cols + col) as usize) {
+            Some(&(r, g, b)) if !in_gap => Rgba([r, g, b, 255]),
+            _ => Rgba([0, 0, 0, 0]),
+        }
+    });
+    for (i, (&rgb, label)) in colors.iter().zip(labels.unwrap_or_default()).enumerate() {
+        let (col, row) = (i as u32 % cols, i as u32 / cols);
+        draw_label(&mut image, label, rgb, col * pitch, row * pitch);
+    }
+
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+/// Render a `size` × `size` square filled with `rgb`
+pub fn render_solid_png(rgb: (u8, u8, u8), size: u32) -> Result<Vec<u8>, image::ImageError> {
+    let image = RgbImage::from_pixel(size, size, Rgb([rgb.0, rgb.1, rgb.2]));
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+/// Draw `label` centered along the bottom of the swatch at (`left`, `top`), uppercased and cut
+/// to the characters that fit
+fn draw_label(image: &mut RgbaImage, label: &str, background: (u8, u8, u8), left: u32, top: u32) {
+    let (black, white) = ((0, 0, 0), (255, 255, 255));
+    let (r, g, b) = if contrast_ratio(background, black) >= contrast_ratio(background, white) { black } else { white };
+    let advance = GLYPH_WIDTH + 1;
+    let max_chars = ((CELL_SIZE - 2 * LABEL_MARGIN + 1) / advance) as usize;
+    let chars: Vec<char> = label.chars().take(max_chars).collect();
+    let width = (chars.len() as u32 * advance).saturating_sub(1);
+    let x0 = left + (CELL_SIZE - width) / 2;
+    let y0 = top + CELL_SIZE - LABEL_MARGIN - GLYPH_HEIGHT;
+    for (i, c) in chars.into_iter().enumerate() {
+        for (dy, bits) in glyph(c).into_iter().enumerate() {
+            for dx in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> dx) != 0 {
+                    image.put_pixel(x0 + i as u32 * advance + dx, y0 + dy as u32, Rgba([r, g, b, 255]));
+                }
+            }
+        }
+    }
+}
+
+/// Rows of the 3×5 bitmap for `c`, top to bottom, leftmost pixel in the highest bit. Letters are
+/// drawn uppercase; characters without a glyph are drawn as `?`.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        ' ' => [0; 5],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        _ => [0b110, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(png: &[u8]) -> RgbaImage {
+        image::load_from_memory(png).unwrap().to_rgba8()
+    }
+
+    fn pixels_of(image: &RgbaImage, rgba: [u8; 4]) -> usize {
+        image.pixels().filter(|p| p.0 == rgba).count()
+    }
+
+    #[test]
+    fn labels_are_drawn_in_the_more_contrasting_ink() {
+        let colors = [(250, 240, 200), (20, 20, 60)];
+        let plain = decode(&render_png(&colors, None, 2).unwrap());
+        let labeled = decode(&render_png(&colors, Some(&["sun", "night"]), 2).unwrap());
+        assert_eq!(labeled.dimensions(), plain.dimensions());
+        assert_eq!(pixels_of(&plain, [0, 0, 0, 255]) + pixels_of(&plain, [255, 255, 255, 255]), 0);
+
+        // Black on the light swatch, white on the dark one, both inside their own cell
+        let left = decode(&render_png(&colors[..1], Some(&["sun"]), 1).unwrap());
+        let right = decode(&render_png(&colors[1..], Some(&["night"]), 1).unwrap());
+        assert!(pixels_of(&left, [0, 0, 0, 255]) > 0);
+        assert_eq!(pixels_of(&left, [255, 255, 255, 255]), 0);
+        assert!(pixels_of(&right, [255, 255, 255, 255]) > 0);
+        assert_eq!(pixels_of(&right, [0, 0, 0, 255]), 0);
+        assert_eq!(
+            pixels_of(&labeled, [0, 0, 0, 255]) + pixels_of(&labeled, [255, 255, 255, 255]),
+            pixels_of(&left, [0, 0, 0, 255]) + pixels_of(&right, [255, 255, 255, 255]),
+        );
+    }
+
+    #[test]
+    fn long_labels_are_cut_to_fit_the_swatch() {
+        let long = decode(&render_png(&[(0, 0, 0)], Some(&["turquoise-blue"]), 1).unwrap());
+        let cut = decode(&render_png(&[(0, 0, 0)], Some(&["turquoi"]), 1).unwrap());
+        assert_eq!(long, cut);
+        assert_eq!(glyph('é'), glyph('?'));
+    }
+}
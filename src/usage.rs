@@ -0,0 +1,178 @@
+//! Per-client request counts for `GET /usage`, and the optional daily quota (`COLORIZER_DAILY_QUOTA`).
+//!
+//! Counts are kept per peer IP for the current UTC day and cleared at midnight. At most
+//! [`MAX_CLIENTS`] IPs are tracked; past that the least recently seen one is dropped.
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::{header, StatusCode},
+    middleware::Next,
+    web, Error,
+};
+use lru::LruCache;
+use std::{
+    net::IpAddr,
+    num::NonZeroUsize,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use crate::{audit::now_secs, error_response};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Client IPs counted per day, so a flood of spoofed or rotating addresses can't grow the map
+/// without bound
+pub const MAX_CLIENTS: usize = 10_000;
+
+/// Paths neither counted nor limited, so health probes and checking usage keep working over quota
+const UNCOUNTED_PATHS: &[&str] = &["/healthz", "/usage"];
+
+/// Requests per client IP since the last UTC midnight
+pub struct Usage {
+    /// Requests a client may make per day; `None` only counts
+    quota: Option<u64>,
+    counts: Mutex<DayCounts>,
+}
+
+struct DayCounts {
+    /// Days since the Unix epoch that `by_ip` belongs to
+    day: u64,
+    by_ip: LruCache<IpAddr, u64>,
+}
+
+impl Usage {
+    pub fn new(quota: Option<u64>) -> Self {
+        Self::with_capacity(quota, NonZeroUsize::new(MAX_CLIENTS).unwrap())
+    }
+
+    fn with_capacity(quota: Option<u64>, capacity: NonZeroUsize) -> Self {
+        Usage {
+            quota,
+            counts: Mutex::new(DayCounts {
+                day: 0,
+                by_ip: LruCache::new(capacity),
+            }),
+        }
+    }
+
+    pub fn quota(&self) -> Option<u64> {
+        self.quota
+    }
+
+    /// Count one request from `ip` and return its count for today
+    fn record(&self, ip: IpAddr) -> u64 {
+        let mut counts = self.today();
+        let count = counts.by_ip.get_or_insert_mut(ip, || 0);
+        *count += 1;
+        *count
+    }
+
+    /// Today's counts, busiest client first
+    pub fn snapshot(&self) -> Vec<(IpAddr, u64)> {
+        let mut clients: Vec<(IpAddr, u64)> = self.today().by_ip.iter().map(|(&ip, &n)| (ip, n)).collect();
+        clients.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        clients
+    }
+
+    /// Lock the counts, clearing them first if a UTC midnight has passed since the last request
+    fn today(&self) -> MutexGuard<'_, DayCounts> {
+        let today = now_secs() / SECS_PER_DAY;
+        let mut counts = self.counts.lock().unwrap();
+        if counts.day != today {
+            counts.day = today;
+            counts.by_ip.clear();
+        }
+        counts
+    }
+}
+
+/// Time until counts are next cleared
+pub fn until_reset() -> Duration {
+    Duration::from_secs(SECS_PER_DAY - now_secs() % SECS_PER_DAY)
+}
+
+/// Middleware counting requests per peer IP and rejecting them with 429 once the client is over
+/// the daily quota, with `Retry-After` set to the next UTC midnight
+pub async fn track_usage(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let usage = req.app_data::<web::Data<Usage>>().cloned();
+    let ip = req
+        .peer_addr()
+        .map(|addr| addr.ip())
+        .filter(|_| !UNCOUNTED_PATHS.contains(&req.path()));
+    if let (Some(usage), Some(ip)) = (usage, ip) {
+        let count = usage.record(ip);
+        if let Some(quota) = usage.quota.filter(|&quota| count > quota) {
+            let mut res = error_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                format!("daily quota of {} requests exceeded; it resets at midnight UTC", quota),
+            );
+            res.headers_mut()
+                .insert(header::RETRY_AFTER, header::HeaderValue::from(until_reset().as_secs()));
+            return Ok(req.into_response(res).map_into_right_body());
+        }
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{
+        middleware::from_fn,
+        test::{call_service, init_service, TestRequest},
+        App, HttpResponse,
+    };
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    #[test]
+    fn counts_are_per_client_and_the_least_recent_is_dropped_at_capacity() {
+        let usage = Usage::with_capacity(None, NonZeroUsize::new(2).unwrap());
+        assert_eq!(usage.record(ip(1)), 1);
+        assert_eq!(usage.record(ip(2)), 1);
+        assert_eq!(usage.record(ip(1)), 2);
+        assert_eq!(usage.snapshot(), [(ip(1), 2), (ip(2), 1)]);
+
+        // 2 was seen longest ago, so the third client replaces it
+        usage.record(ip(3));
+        assert_eq!(usage.snapshot(), [(ip(1), 2), (ip(3), 1)]);
+    }
+
+    #[actix_web::test]
+    async fn requests_over_the_quota_get_429_until_midnight() {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Usage::new(Some(2))))
+                .wrap(from_fn(track_usage))
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+        let from = |last: u8, path: &str| {
+            TestRequest::get()
+                .uri(path)
+                .peer_addr(SocketAddr::new(ip(last), 4000))
+                .to_request()
+        };
+
+        for _ in 0..2 {
+            assert_eq!(call_service(&app, from(1, "/color")).await.status(), StatusCode::OK);
+        }
+        let res = call_service(&app, from(1, "/color")).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = res.headers().get(header::RETRY_AFTER).unwrap().to_str().unwrap().parse().unwrap();
+        assert!(retry_after > 0 && retry_after <= SECS_PER_DAY, "{retry_after}");
+
+        // Other clients and uncounted paths are unaffected
+        assert_eq!(call_service(&app, from(2, "/color")).await.status(), StatusCode::OK);
+        assert_eq!(call_service(&app, from(1, "/healthz")).await.status(), StatusCode::OK);
+    }
+}
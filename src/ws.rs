@@ -0,0 +1,127 @@
+use actix_web::{get, rt, web, HttpRequest, HttpResponse};
+use actix_ws::{AggregatedMessage, Session};
+use colorizer::error::ColorizerError;
+use std::time::{Duration, Instant};
+
+use crate::{config::RateLimit, logging::RequestId, AppState, ColorOutput, ErrorOutput};
+
+/// Largest text frame accepted from a client
+const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// Token bucket limiting how often one connection may request a color
+struct RateLimiter {
+    interval: Duration,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    fn new(limit: RateLimit) -> Self {
+        RateLimiter {
+            interval: Duration::from_millis(limit.milliseconds_per_request),
+            burst: limit.burst as f64,
+            tokens: limit.burst as f64,
+            last: Instant::now(),
+        }
+    }
+
+    /// Take a token if one is available, refilling one per `interval` since the last call
+    fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last).as_secs_f64() / self.interval.as_secs_f64();
+        self.tokens = (self.tokens + refill).min(self.burst);
+        self.last = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// GET /ws → upgrades to a WebSocket that replies to each text frame with `{ "r", "g", "b" }`
+#[get("/ws")]
+pub async fn ws(req: HttpRequest, body: web::Payload, data: web::Data<AppState>) -> actix_web::Result<HttpResponse> {
+    let (response, session, stream) = actix_ws::handle(&req, body)?;
+    let stream = stream.max_frame_size(MAX_FRAME_SIZE).aggregate_continuations();
+
+    let id = RequestId::of(&req);
+    rt::spawn(async move {
+        if let Err(e) = serve(data, session, stream).await {
+            log::debug!("websocket id={} closed: {}", id, e);
+        }
+    });
+
+    Ok(response)
+}
+
+/// Handle frames until the client disconnects or closes the socket
+async fn serve(
+    data: web::Data<AppState>,
+    mut session: Session,
+    mut stream: actix_ws::AggregatedMessageStream,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut limiter = data.rate_limit.map(RateLimiter::new);
+    // Last input and its reply, so repeated keystrokes that don't change the text skip inference
+    let mut last: Option<(String, String)> = None;
+
+    while let Some(msg) = stream.recv().await {
+        match msg? {
+            AggregatedMessage::Text(text) => {
+                let text = text.trim();
+                if let Some((_, reply)) = last.as_ref().filter(|(prev, _)| prev == text) {
+                    session.text(reply.clone()).await?;
+                    continue;
+                }
+                if limiter.as_mut().is_some_and(|limiter| !limiter.try_acquire()) {
+                    session.text(error_frame("rate limit exceeded")).await?;
+                    continue;
+                }
+
+                let reply = colorize(&data, text).await;
+                session.text(reply.clone()).await?;
+                last = Some((text.to_string(), reply));
+            }
+            AggregatedMessage::Ping(bytes) => session.pong(&bytes).await?,
+            AggregatedMessage::Close(reason) => {
+                session.close(reason).await?;
+                return Ok(());
+            }
+            AggregatedMessage::Binary(_) => session.text(error_frame("only text frames are supported")).await?,
+            AggregatedMessage::Pong(_) => {}
+        }
+    }
+
+    // The client went away without a close frame
+    Ok(())
+}
+
+/// Look up the closest color for a frame and render the reply as JSON
+async fn colorize(data: &AppState, text: &str) -> String {
+    if text.is_empty() {
+        return error_frame(&ColorizerError::EmptyInput.to_string());
+    }
+    if let Err(e) = data.check_language(text) {
+        return error_frame(&e);
+    }
+
+    let model = data.default_model();
+    let refs = model.references();
+    match model.embed_blocking(text).await {
+        Ok(emb) => {
+            let rgb = refs
+                .top_matches(emb, 1)
+                .first()
+                .map_or(data.fallback_color, |m| refs.embeddings[m.index].color);
+            serde_json::to_string(&ColorOutput::new(rgb)).unwrap_or_default()
+        }
+        Err(e) => error_frame(&e.to_string()),
+    }
+}
+
+fn error_frame(message: &str) -> String {
+    serde_json::to_string(&ErrorOutput { error: message.to_string() }).unwrap_or_default()
+}